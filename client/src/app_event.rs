@@ -14,14 +14,21 @@ pub enum AppEvent {
         y: i32,
         button: MouseButton,
     },
-    WebsocketConnected,
-    WebsocketDisconnected,
-    WebsocketMessage {
+    MouseWheel {
+        delta_y: f64,
+    },
+    JoinSubmitted {
+        name: String,
+        appearance: u32,
+    },
+    ServerConnected,
+    ServerDisconnected,
+    ServerMessage {
         message: PlayerEventEnvelope<PlayerEvent>,
         received_at: f32,
     },
     AssetsLoaded {
-        assets: Assets,
+        assets: Box<Assets>,
     },
 }
 
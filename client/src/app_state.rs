@@ -7,6 +7,8 @@ use web_sys::{WebGl2RenderingContext, WebGlProgram, WebGlUniformLocation};
 use crate::app_event::AppEvent;
 use crate::assets::Assets;
 use crate::game_state::{GameState, PartialGameState};
+use crate::join_form::JoinForm;
+use crate::lighting::LightMap;
 use crate::metrics::Metrics;
 use crate::vertex_buffer_renderer::VertexBufferRenderer;
 
@@ -15,12 +17,20 @@ pub struct AppState {
     pub gl: WebGl2RenderingContext,
     pub program: WebGlProgram,
     pub text_program: WebGlProgram,
+    pub light_program: WebGlProgram,
     pub uniform_locations: UniformLocations,
     pub assets: Option<Assets>,
     pub vertex_buffer_renderer: VertexBufferRenderer,
+    /// Background tiles of the current room
+    pub static_tiles_renderer: VertexBufferRenderer,
+    /// Recreated when the viewport changes
+    pub light_map: Option<LightMap>,
+    /// Without it lights are skipped and only the day/night tint is applied
+    pub light_map_supported: bool,
     pub metrics: Rc<RefCell<Metrics>>,
     pub viewport: Vector2<u32>,
     pub events: Rc<RefCell<Vec<AppEvent>>>,
+    pub join_form: JoinForm,
     pub game_state: Result<GameState, PartialGameState>,
 }
 
@@ -30,4 +40,5 @@ pub struct UniformLocations {
     pub text_view_projection: WebGlUniformLocation,
     pub text_sampler: WebGlUniformLocation,
     pub text_distance_range: WebGlUniformLocation,
+    pub light_view_projection: WebGlUniformLocation,
 }
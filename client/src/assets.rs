@@ -9,7 +9,9 @@ use wasm_bindgen::JsValue;
 use web_sys::WebGl2RenderingContext as GL;
 
 pub struct Assets {
-    pub tileset: Texture,
+    pub asset_paths: AssetPaths,
+    /// Indexed by `TilesetId`
+    pub tilesets: Vec<Texture>,
     pub charset: Texture,
     pub font: Texture,
     pub white: Texture,
@@ -20,7 +22,10 @@ pub async fn load(gl: &GL, asset_paths: &AssetPaths) -> Result<Assets, JsValue>
     let window = web_sys::window().ok_or("No window")?;
     let white = texture::create_white_texture(gl)?;
 
-    let tileset = texture::load_texture(gl, &asset_paths.tileset, GL::NEAREST).await?;
+    let mut tilesets = vec![];
+    for tileset in &asset_paths.tilesets {
+        tilesets.push(texture::load_texture(gl, tileset, GL::NEAREST).await?);
+    }
     let charset = texture::load_texture(gl, &asset_paths.charset, GL::NEAREST).await?;
     let font = texture::load_texture(gl, &asset_paths.font, GL::LINEAR).await?;
 
@@ -28,7 +33,8 @@ pub async fn load(gl: &GL, asset_paths: &AssetPaths) -> Result<Assets, JsValue>
     let font_atlas = FontAtlas::from_meta(font_meta);
 
     Ok(Assets {
-        tileset,
+        asset_paths: asset_paths.clone(),
+        tilesets,
         charset,
         font,
         white,
@@ -1,10 +1,12 @@
+use mmo_common::room::PIXELS_PER_TILE;
 use nalgebra::{Matrix3, Point2, Scale2, Translation2, Vector2};
 
-pub static PIXELS_PER_TILE: u32 = 16;
-
 static MAX_X_TILES_ON_SCREEN: f32 = 30.0;
 static MAX_Y_TILES_ON_SCREEN: f32 = 16.875;
 
+pub static MIN_ZOOM: i32 = -2;
+pub static MAX_ZOOM: i32 = 3;
+
 #[derive(Debug, Clone)]
 pub struct Camera {
     pub world_to_ndc: Matrix3<f32>,
@@ -16,7 +18,14 @@ pub struct Camera {
 }
 
 impl Camera {
-    pub fn new(focus: Vector2<f32>, map_size: Vector2<u32>, screen_size: Vector2<u32>) -> Self {
+    pub fn new(
+        focus: Vector2<f32>,
+        bounds_min: Vector2<f32>,
+        bounds_max: Vector2<f32>,
+        screen_size: Vector2<u32>,
+        zoom: i32,
+        shake: Vector2<f32>,
+    ) -> Self {
         // FIXME: some very wide aspect ratios have a striping artifact
 
         let pixels_per_tile = PIXELS_PER_TILE as f32;
@@ -29,17 +38,21 @@ impl Camera {
             } else {
                 screen_size[1] / MAX_Y_TILES_ON_SCREEN
             };
-            (bounded / pixels_per_tile).ceil() * pixels_per_tile
+            // Zoom stays in whole steps so that pixel art is never scaled unevenly
+            let scale = (bounded / pixels_per_tile).ceil() + zoom as f32;
+            scale.max(1.0) * pixels_per_tile
         };
         let pixels_per_logical_pixel = pixels_per_world_unit / pixels_per_tile;
 
         let world_to_camera = {
             let focus = focus.map(|a| (a * pixels_per_world_unit).round() / pixels_per_world_unit);
-            let map_size = map_size.cast();
             let world_viewport = screen_size / pixels_per_world_unit;
+            let shake = shake.map(|a| (a * pixels_per_world_unit).round() / pixels_per_world_unit);
             Translation2::new(
-                camera_translation(focus[0], world_viewport[0], map_size[0]),
-                camera_translation(focus[1], world_viewport[1], map_size[1]),
+                camera_translation(focus[0], world_viewport[0], bounds_min[0], bounds_max[0])
+                    + shake[0],
+                camera_translation(focus[1], world_viewport[1], bounds_min[1], bounds_max[1])
+                    + shake[1],
             )
         };
         let camera_to_logical_screen = Scale2::new(pixels_per_tile, pixels_per_tile);
@@ -84,12 +97,13 @@ impl Camera {
     }
 }
 
-fn camera_translation(focus: f32, world_viewport: f32, map_size: f32) -> f32 {
-    if map_size <= world_viewport {
-        (world_viewport - map_size) / 2.0
+fn camera_translation(focus: f32, world_viewport: f32, min: f32, max: f32) -> f32 {
+    if max - min <= world_viewport {
+        (world_viewport - min - max) / 2.0
     } else {
-        let max_translate = map_size - world_viewport;
-        (world_viewport / 2.0 - focus).min(0.0).max(-max_translate)
+        (world_viewport / 2.0 - focus)
+            .min(-min)
+            .max(world_viewport - max)
     }
 }
 
@@ -0,0 +1,73 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use mmo_common::player_command::{PlayerCommand, PlayerCommandEnvelope};
+use mmo_common::player_event::{PlayerEvent, PlayerEventEnvelope};
+use mmo_common::room::RoomId;
+use wasm_bindgen::prelude::*;
+use web_sys::WebSocket;
+
+use crate::app_event::AppEvent;
+use crate::console_warn;
+use crate::metrics::Metrics;
+use crate::ws_connection;
+use crate::wt_connection::{self, WtConnection};
+
+pub enum Connection {
+    WebSocket(WebSocket),
+    WebTransport(WtConnection),
+}
+
+/// Prefers WebTransport and falls back to a websocket.
+pub async fn connect(
+    events: Rc<RefCell<Vec<AppEvent>>>,
+    metrics: Rc<RefCell<Metrics>>,
+) -> Result<Connection, JsValue> {
+    match wt_connection::connect(events.clone(), metrics.clone()).await {
+        Ok(Some(connection)) => return Ok(Connection::WebTransport(connection)),
+        Ok(None) => {}
+        Err(err) => console_warn!("WebTransport failed, falling back to websocket: {err:?}"),
+    }
+    ws_connection::connect(events, metrics).map(Connection::WebSocket)
+}
+
+pub fn send(
+    connection: &Connection,
+    room_id: RoomId,
+    commands: Vec<PlayerCommand>,
+    metrics: &mut Metrics,
+) -> Result<(), JsValue> {
+    let command_count = commands.len();
+    let envelope = PlayerCommandEnvelope { room_id, commands };
+    let bytes = postcard::to_stdvec(&envelope).map_err(|e| e.to_string())?;
+    match connection {
+        Connection::WebSocket(ws) => ws_connection::send(ws, &bytes)?,
+        Connection::WebTransport(wt) => wt_connection::send(wt, &bytes)?,
+    }
+    metrics.record_net_command(bytes.len() as u32, command_count as u32);
+    Ok(())
+}
+
+pub fn push_message(events: &RefCell<Vec<AppEvent>>, metrics: &RefCell<Metrics>, bytes: &[u8]) {
+    let performance = web_sys::window()
+        .expect("No window")
+        .performance()
+        .expect("No performance");
+    let received_at = (performance.now() * 1e-3) as f32;
+    let message: PlayerEventEnvelope<PlayerEvent> = match postcard::from_bytes(bytes) {
+        Ok(message) => message,
+        Err(err) => {
+            console_warn!("Dropping malformed message: {err}");
+            return;
+        }
+    };
+    let event_count = message.events.len();
+    let app_event = AppEvent::ServerMessage {
+        message,
+        received_at,
+    };
+    events.borrow_mut().push(app_event);
+    metrics
+        .borrow_mut()
+        .record_net_event(bytes.len() as u32, event_count as u32);
+}
@@ -1,13 +1,17 @@
+use std::collections::VecDeque;
+
 use mmo_common::{
     client_config::ClientConfig,
+    combat::DamageType,
+    equipment::PlayerStats,
     object::{Direction4, Direction8, ObjectId, ObjectType},
     player_command::PlayerCommand,
-    player_event::{PlayerEvent, PlayerEventEnvelope},
-    room::{ForegroundTile, RoomId, TileIndex},
+    player_event::{self, BossHealthBar, PlayerEvent, PlayerEventEnvelope},
+    room::{self, ForegroundTile, Light, RoomId, Terrain, TileIndex},
 };
 use nalgebra::Vector2;
 
-use crate::camera::Camera;
+use crate::{camera::Camera, minimap::Minimap, room_tiles::RoomTiles};
 
 pub struct GameState {
     pub time: Timestamps,
@@ -15,14 +19,36 @@ pub struct GameState {
     pub directions_pressed: [bool; 4],
     pub last_ping: Option<LastPing>,
     pub ping_rtt: f32,
+    pub ping_jitter: f32,
+    pub server_clock: ServerClock,
+    pub interpolation_delay: f32,
+    pub movement_prediction: MovementPrediction,
     pub self_id: ObjectId,
     pub client_config: ClientConfig,
     pub room: Room,
+    pub room_tiles: RoomTiles,
+    pub minimap: Minimap,
     pub objects: Vec<Object>,
     pub camera: Camera,
+    pub camera_focus: Option<Vector2<f32>>,
+    pub camera_zoom: i32,
+    pub camera_shake: Option<CameraShake>,
     pub health_change_labels: Vec<HealthChangeLabel>,
     pub attack_markers: Vec<AttackMarker>,
+    pub death: Option<Death>,
+    /// Local time when the server disconnects everyone
+    pub shutdown_at: Option<f32>,
+    pub notice: Option<Notice>,
     pub show_debug: bool,
+    pub show_world_map: bool,
+    pub lighting_enabled: bool,
+    pub world_clock: Option<WorldClock>,
+    pub show_equipment: bool,
+    /// Item ids of the self player
+    pub equipped: Vec<String>,
+    /// Of the self player, until the server sends them the velocity comes from the client config
+    pub stats: Option<PlayerStats>,
+    pub boss_health_bar: Option<BossHealthBar>,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -31,6 +57,20 @@ pub struct Timestamps {
     pub frame_delta: f32,
 }
 
+#[derive(Debug, Clone)]
+pub struct MovementPrediction {
+    pub next_sequence_number: u32,
+    pub pending_moves: Vec<PendingMove>,
+    pub error: Vector2<f32>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PendingMove {
+    pub sequence_number: u32,
+    pub direction: Option<Direction8>,
+    pub sent_at: f32,
+}
+
 #[derive(Debug, Clone)]
 pub struct Room {
     pub room_id: RoomId,
@@ -39,36 +79,80 @@ pub struct Room {
     pub bg_sparse_layer: Vec<(Vector2<u32>, TileIndex)>,
     pub fg_sparse_layer: Vec<ForegroundTile>,
     pub collisions: Vec<bool>,
+    pub terrain: Vec<Terrain>,
+    pub neighbours: Vec<Neighbour>,
+    pub portals: Vec<Vector2<u32>>,
+    pub lights: Vec<Light>,
+}
+
+impl Room {
+    /// Positions past the edge are free where a neighbouring level continues
+    pub fn collision_at(&self, position: Vector2<f32>) -> bool {
+        if room::in_bounds(self.size, position) {
+            return room::collision_at(self.size, &self.collisions, position);
+        }
+        self.neighbours.iter().all(|neighbour| {
+            let position = position - neighbour.offset.cast();
+            room::collision_at(neighbour.room.size, &neighbour.room.collisions, position)
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Neighbour {
+    pub offset: Vector2<i32>,
+    pub room: Room,
 }
 
 #[derive(Debug, Clone)]
 pub struct Object {
     pub id: ObjectId,
     pub typ: ObjectType,
+    pub name: String,
     pub remote_position: Vector2<f32>,
     pub remote_position_received_at: f32,
     pub local_position: Vector2<f32>,
+    pub movement_snapshots: VecDeque<MovementSnapshot>,
+    pub movement_tick: u32,
     pub direction: Option<Direction8>,
     pub look_direction: Direction4,
     pub animation_id: usize,
+    pub overlay_animation_ids: Vec<usize>,
     pub animation: Option<ObjectAnimation>,
     pub velocity: f32,
     pub health: i32,
     pub max_health: i32,
 }
 
+#[derive(Debug, Clone, Copy)]
+pub struct MovementSnapshot {
+    pub position: Vector2<f32>,
+    pub velocity: f32,
+    pub direction: Option<Direction8>,
+    pub look_direction: Direction4,
+    pub timestamp: f32,
+}
+
 #[derive(Debug, Clone)]
 pub struct ObjectAnimation {
     pub animation_index: u8,
     pub started_at: f32,
 }
 
+#[derive(Debug, Clone, Copy)]
+pub struct CameraShake {
+    pub started_at: f32,
+}
+
 #[derive(Debug, Clone)]
 pub struct HealthChangeLabel {
     pub health_change: i32,
     pub position: Vector2<f32>,
     pub received_at: f32,
     pub object_type: ObjectType,
+    /// None when healing
+    pub damage_type: Option<DamageType>,
+    pub critical: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -77,9 +161,76 @@ pub struct AttackMarker {
     pub position: Vector2<f32>,
     pub radius: f32,
     pub length: f32,
+    pub started_at: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Death {
+    pub respawn_at: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct WorldClock {
+    pub time_of_day: f32,
+    pub day_length: f32,
     pub received_at: f32,
 }
 
+#[derive(Debug, Clone)]
+pub struct Notice {
+    pub text: String,
+    pub received_at: f32,
+}
+
+static SERVER_CLOCK_SAMPLE_COUNT: usize = 8;
+
+#[derive(Debug, Clone)]
+pub struct ServerClock {
+    pub tick_interval: f64,
+    pub offset: Option<f64>,
+    samples: VecDeque<ServerClockSample>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ServerClockSample {
+    rtt: f32,
+    offset: f64,
+}
+
+impl ServerClock {
+    pub fn new(tick_interval: f32) -> Self {
+        Self {
+            tick_interval: tick_interval as f64,
+            offset: None,
+            samples: VecDeque::new(),
+        }
+    }
+
+    pub fn add_sample(&mut self, sent_at: f32, received_at: f32, tick: u32, since_tick: f32) {
+        let server_time = tick as f64 * self.tick_interval + since_tick as f64;
+        let midpoint = (sent_at as f64 + received_at as f64) / 2.0;
+        self.samples.push_back(ServerClockSample {
+            rtt: received_at - sent_at,
+            offset: server_time - midpoint,
+        });
+        if self.samples.len() > SERVER_CLOCK_SAMPLE_COUNT {
+            self.samples.pop_front();
+        }
+
+        // As in NTP, the sample with the lowest round trip is the least distorted by queuing
+        self.offset = self
+            .samples
+            .iter()
+            .min_by(|a, b| a.rtt.total_cmp(&b.rtt))
+            .map(|sample| sample.offset);
+    }
+
+    pub fn tick_to_local_time(&self, tick: u32) -> Option<f32> {
+        self.offset
+            .map(|offset| (tick as f64 * self.tick_interval - offset) as f32)
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct LastPing {
     pub sequence_number: u32,
@@ -88,6 +239,7 @@ pub struct LastPing {
 
 pub struct PartialGameState {
     pub time: Timestamps,
+    pub ws_commands: Vec<PlayerCommand>,
     pub self_id: Option<ObjectId>,
     pub client_config: Option<ClientConfig>,
     pub room: Option<Room>,
@@ -101,6 +253,7 @@ impl PartialGameState {
                 now: 0.0,
                 frame_delta: 0.0,
             },
+            ws_commands: vec![],
             self_id: None,
             client_config: None,
             room: None,
@@ -112,21 +265,53 @@ impl PartialGameState {
         let self_id = self.self_id?;
         let client_config = self.client_config.clone()?;
         let room = self.room.clone()?;
-        let camera = Camera::new(Default::default(), Default::default(), Default::default());
+        let room_tiles = RoomTiles::new(&room, &client_config);
+        let minimap = Minimap::new(&room);
+        let camera = Camera::new(
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            0,
+            Vector2::zeros(),
+        );
         Some(GameState {
             time: self.time,
             ws_commands: Vec::new(),
             directions_pressed: [false; 4],
             last_ping: None,
             ping_rtt: 0.0,
+            ping_jitter: 0.0,
+            server_clock: ServerClock::new(client_config.tick_interval),
+            interpolation_delay: player_event::INTERPOLATION_MIN_DELAY,
+            movement_prediction: MovementPrediction {
+                next_sequence_number: 1,
+                pending_moves: vec![],
+                error: Vector2::new(0.0, 0.0),
+            },
             self_id,
             client_config,
             room,
+            room_tiles,
+            minimap,
             objects: vec![],
             camera,
+            camera_focus: None,
+            camera_zoom: 0,
+            camera_shake: None,
             health_change_labels: vec![],
             attack_markers: vec![],
+            death: None,
+            shutdown_at: None,
+            notice: None,
             show_debug: false,
+            show_world_map: false,
+            lighting_enabled: true,
+            world_clock: None,
+            show_equipment: false,
+            equipped: vec![],
+            stats: None,
+            boss_health_bar: None,
         })
     }
 }
@@ -0,0 +1,68 @@
+use std::{cell::RefCell, rc::Rc};
+
+use wasm_bindgen::{prelude::*, JsCast, JsValue};
+use web_sys::{
+    Document, Event, HtmlElement, HtmlFormElement, HtmlInputElement, HtmlOptionElement,
+    HtmlSelectElement,
+};
+
+use crate::app_event::AppEvent;
+
+/// The name and appearance form in the page, shown until the server accepts the player
+pub struct JoinForm {
+    form: HtmlFormElement,
+    name: HtmlInputElement,
+    appearance: HtmlSelectElement,
+    error: HtmlElement,
+}
+
+impl JoinForm {
+    pub fn new(document: &Document, events: Rc<RefCell<Vec<AppEvent>>>) -> Result<Self, JsValue> {
+        let element = |id: &str| document.get_element_by_id(id).ok_or("No join form element");
+        let join_form = Self {
+            form: element("join-form")?.dyn_into()?,
+            name: element("join-name")?.dyn_into()?,
+            appearance: element("join-appearance")?.dyn_into()?,
+            error: element("join-error")?.dyn_into()?,
+        };
+
+        let submit_listener = {
+            let name = join_form.name.clone();
+            let appearance = join_form.appearance.clone();
+            Closure::<dyn FnMut(_)>::new(move |event: Event| {
+                event.prevent_default();
+                let app_event = AppEvent::JoinSubmitted {
+                    name: name.value().trim().to_string(),
+                    appearance: appearance.selected_index().max(0) as u32,
+                };
+                (*events).borrow_mut().push(app_event);
+            })
+            .into_js_value()
+        };
+        join_form
+            .form
+            .add_event_listener_with_callback("submit", submit_listener.unchecked_ref())?;
+
+        Ok(join_form)
+    }
+
+    pub fn show(&self, appearances: &[String]) -> Result<(), JsValue> {
+        self.appearance.set_length(0);
+        for appearance in appearances {
+            let option = HtmlOptionElement::new_with_text(appearance)?;
+            self.appearance.add_with_html_option_element(&option)?;
+        }
+        self.error.set_text_content(None);
+        self.form.set_hidden(false);
+        self.name.focus()
+    }
+
+    pub fn show_error(&self, reason: &str) {
+        self.error.set_text_content(Some(reason));
+    }
+
+    pub fn hide(&self) -> Result<(), JsValue> {
+        self.form.set_hidden(true);
+        self.name.blur()
+    }
+}
@@ -2,6 +2,7 @@ use std::cell::RefCell;
 use std::rc::Rc;
 
 use game_state::PartialGameState;
+use mmo_common::room::RoomId;
 use nalgebra::Vector2;
 use vertex_buffer_renderer::VertexBufferRenderer;
 use wasm_bindgen::prelude::*;
@@ -9,17 +10,23 @@ use wasm_bindgen::JsCast;
 use web_sys::WebGl2RenderingContext as GL;
 
 use crate::app_state::{AppState, UniformLocations};
+use crate::join_form::JoinForm;
 use crate::metrics::Metrics;
 
 mod app_event;
 mod app_state;
 mod assets;
 mod camera;
+mod connection;
 mod fetch;
 mod font_atlas;
 mod game_state;
+mod join_form;
+mod lighting;
 mod metrics;
+mod minimap;
 mod render;
+mod room_tiles;
 mod shader;
 mod texture;
 mod update;
@@ -28,10 +35,12 @@ mod util;
 mod vertex_buffer;
 mod vertex_buffer_renderer;
 mod ws_connection;
+mod wt_connection;
 
 static VERTEX_SHADER: &str = include_str!("shader-vert.glsl");
 static FRAGMENT_SHADER: &str = include_str!("shader-frag.glsl");
 static TEXT_FRAGMENT_SHADER: &str = include_str!("text-frag.glsl");
+static LIGHT_FRAGMENT_SHADER: &str = include_str!("light-frag.glsl");
 
 #[wasm_bindgen(start)]
 pub async fn start() -> Result<(), JsValue> {
@@ -59,43 +68,21 @@ pub async fn start() -> Result<(), JsValue> {
         let frag_shader = shader::compile(&gl, GL::FRAGMENT_SHADER, FRAGMENT_SHADER)?;
         shader::link(&gl, &vert_shader, &frag_shader)?
     };
-    gl.bind_attrib_location(
-        &program,
-        vertex_buffer_renderer::ATTRIB_LOC_POSITION,
-        "position",
-    );
-    gl.bind_attrib_location(
-        &program,
-        vertex_buffer_renderer::ATTRIB_LOC_TEXTURE_POSITION,
-        "texturePosition",
-    );
-    gl.bind_attrib_location(
-        &program,
-        vertex_buffer_renderer::ATTRIB_LOC_TEXTURE_INDEX,
-        "textureIndex",
-    );
+    bind_attrib_locations(&gl, &program);
 
     let text_program = {
         let vert_shader = shader::compile(&gl, GL::VERTEX_SHADER, VERTEX_SHADER)?;
         let frag_shader = shader::compile(&gl, GL::FRAGMENT_SHADER, TEXT_FRAGMENT_SHADER)?;
         shader::link(&gl, &vert_shader, &frag_shader)?
     };
-    // FIXME: duplication
-    gl.bind_attrib_location(
-        &text_program,
-        vertex_buffer_renderer::ATTRIB_LOC_POSITION,
-        "position",
-    );
-    gl.bind_attrib_location(
-        &text_program,
-        vertex_buffer_renderer::ATTRIB_LOC_TEXTURE_POSITION,
-        "texturePosition",
-    );
-    gl.bind_attrib_location(
-        &text_program,
-        vertex_buffer_renderer::ATTRIB_LOC_TEXTURE_INDEX,
-        "textureIndex",
-    );
+    bind_attrib_locations(&gl, &text_program);
+
+    let light_program = {
+        let vert_shader = shader::compile(&gl, GL::VERTEX_SHADER, VERTEX_SHADER)?;
+        let frag_shader = shader::compile(&gl, GL::FRAGMENT_SHADER, LIGHT_FRAGMENT_SHADER)?;
+        shader::link(&gl, &vert_shader, &frag_shader)?
+    };
+    bind_attrib_locations(&gl, &light_program);
 
     let uniform_locations = UniformLocations {
         view_projection: gl
@@ -113,27 +100,38 @@ pub async fn start() -> Result<(), JsValue> {
         text_distance_range: gl
             .get_uniform_location(&text_program, "distanceRange")
             .ok_or("No uniform location")?,
+        light_view_projection: gl
+            .get_uniform_location(&light_program, "viewProjection")
+            .ok_or("No uniform location")?,
     };
 
     let vertex_buffer_renderer = VertexBufferRenderer::new(&gl)?;
+    let static_tiles_renderer = VertexBufferRenderer::new(&gl)?;
 
     let metrics = Rc::new(RefCell::new(Metrics::new(&window)));
+    let events = Rc::new(RefCell::new(vec![]));
+    let join_form = JoinForm::new(&document, events.clone())?;
 
     let mut app_state = AppState {
         client_git_sha,
         gl,
         program,
         text_program,
+        light_program,
         uniform_locations,
         assets: None,
         vertex_buffer_renderer,
+        static_tiles_renderer,
+        light_map: None,
+        light_map_supported: true,
         metrics: metrics.clone(),
         viewport: Vector2::new(canvas.client_width() as u32, canvas.client_height() as u32),
-        events: Rc::new(RefCell::new(vec![])),
+        events,
+        join_form,
         game_state: Err(PartialGameState::new()),
     };
 
-    let ws = ws_connection::connect(app_state.events.clone(), metrics)?;
+    let connection = connection::connect(app_state.events.clone(), metrics).await?;
 
     user_input::setup_handlers(&document, app_state.events.clone())?;
 
@@ -150,18 +148,22 @@ pub async fn start() -> Result<(), JsValue> {
             let events = (*app_state.events).take();
             update::update(&mut app_state, events);
 
-            if let Ok(ref mut game_state) = &mut app_state.game_state {
-                let room_id = game_state.room.room_id;
-                let ws_commands = std::mem::take(&mut game_state.ws_commands);
-                if !ws_commands.is_empty() {
-                    ws_connection::send(
-                        &ws,
-                        room_id,
-                        ws_commands,
-                        &mut app_state.metrics.borrow_mut(),
-                    )
-                    .unwrap();
-                }
+            let (room_id, ws_commands) = match &mut app_state.game_state {
+                Ok(game_state) => (
+                    game_state.room.room_id,
+                    std::mem::take(&mut game_state.ws_commands),
+                ),
+                // Only global commands are sent before joining, the room is ignored for those
+                Err(partial) => (RoomId(0), std::mem::take(&mut partial.ws_commands)),
+            };
+            if !ws_commands.is_empty() {
+                connection::send(
+                    &connection,
+                    room_id,
+                    ws_commands,
+                    &mut app_state.metrics.borrow_mut(),
+                )
+                .unwrap();
             }
 
             render::render(&mut app_state);
@@ -172,6 +174,24 @@ pub async fn start() -> Result<(), JsValue> {
     Ok(())
 }
 
+fn bind_attrib_locations(gl: &GL, program: &web_sys::WebGlProgram) {
+    gl.bind_attrib_location(
+        program,
+        vertex_buffer_renderer::ATTRIB_LOC_POSITION,
+        "position",
+    );
+    gl.bind_attrib_location(
+        program,
+        vertex_buffer_renderer::ATTRIB_LOC_TEXTURE_POSITION,
+        "texturePosition",
+    );
+    gl.bind_attrib_location(
+        program,
+        vertex_buffer_renderer::ATTRIB_LOC_TEXTURE_INDEX,
+        "textureIndex",
+    );
+}
+
 fn start_self_referential_closure(
     mut consume: impl FnMut(&js_sys::Function) + 'static + Clone,
     mut f: impl FnMut() + 'static,
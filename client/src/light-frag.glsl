@@ -0,0 +1,14 @@
+#version 300 es
+precision mediump float;
+
+in vec2 vertTexturePosition;
+in vec4 vertColor;
+flat in float vertTextureIndex;
+
+out vec4 fragColor;
+
+// Light quads have texture positions from -1.0 to 1.0, so the light fades out at the radius
+void main() {
+    float falloff = clamp(1.0 - length(vertTexturePosition), 0.0, 1.0);
+    fragColor = vec4(vertColor.rgb * vertColor.a * falloff * falloff, 1.0);
+}
@@ -0,0 +1,104 @@
+use std::f32::consts::TAU;
+
+use nalgebra::{Vector2, Vector3};
+use wasm_bindgen::JsValue;
+use web_sys::{WebGl2RenderingContext as GL, WebGlFramebuffer};
+
+use crate::{game_state::GameState, texture::Texture};
+
+static DAY_COLOR: Vector3<f32> = Vector3::new(1.0, 1.0, 1.0);
+static NIGHT_COLOR: Vector3<f32> = Vector3::new(0.18, 0.22, 0.42);
+static DUSK_TINT: Vector3<f32> = Vector3::new(1.0, 0.7, 0.55);
+
+/// Lights are accumulated into a texture the size of the screen, which then multiplies the scene
+pub struct LightMap {
+    pub framebuffer: WebGlFramebuffer,
+    pub texture: Texture,
+}
+
+impl LightMap {
+    pub fn new(gl: &GL, size: Vector2<u32>) -> Result<Self, JsValue> {
+        let texture = gl.create_texture().ok_or("Could not create texture")?;
+        gl.bind_texture(GL::TEXTURE_2D, Some(&texture));
+        gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+            GL::TEXTURE_2D,
+            0,
+            GL::RGBA as i32,
+            size.x as i32,
+            size.y as i32,
+            0,
+            GL::RGBA,
+            GL::UNSIGNED_BYTE,
+            None,
+        )?;
+        gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MIN_FILTER, GL::LINEAR as i32);
+        gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MAG_FILTER, GL::LINEAR as i32);
+        gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_WRAP_S, GL::CLAMP_TO_EDGE as i32);
+        gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_WRAP_T, GL::CLAMP_TO_EDGE as i32);
+
+        let framebuffer = gl
+            .create_framebuffer()
+            .ok_or("Could not create framebuffer")?;
+        gl.bind_framebuffer(GL::FRAMEBUFFER, Some(&framebuffer));
+        gl.framebuffer_texture_2d(
+            GL::FRAMEBUFFER,
+            GL::COLOR_ATTACHMENT0,
+            GL::TEXTURE_2D,
+            Some(&texture),
+            0,
+        );
+        let status = gl.check_framebuffer_status(GL::FRAMEBUFFER);
+        gl.bind_framebuffer(GL::FRAMEBUFFER, None);
+
+        let light_map = Self {
+            framebuffer,
+            texture: Texture {
+                texture,
+                width: size.x,
+                height: size.y,
+            },
+        };
+        if status != GL::FRAMEBUFFER_COMPLETE {
+            light_map.delete(gl);
+            return Err(format!("Light map framebuffer is incomplete: {status}").into());
+        }
+        Ok(light_map)
+    }
+
+    pub fn size(&self) -> Vector2<u32> {
+        Vector2::new(self.texture.width, self.texture.height)
+    }
+
+    pub fn delete(self, gl: &GL) {
+        gl.delete_framebuffer(Some(&self.framebuffer));
+        gl.delete_texture(Some(&self.texture.texture));
+    }
+}
+
+/// From 0.0 to 1.0 starting at midnight, or noon until the server sends the clock
+pub fn time_of_day(game_state: &GameState) -> f32 {
+    match &game_state.world_clock {
+        Some(clock) => {
+            let elapsed = game_state.time.now - clock.received_at;
+            (clock.time_of_day + elapsed / clock.day_length).rem_euclid(1.0)
+        }
+        None => 0.5,
+    }
+}
+
+/// The color unlit areas are multiplied with
+pub fn ambient_color(time_of_day: f32) -> Vector3<f32> {
+    // 0.0 at midnight, 1.0 at noon
+    let daylight = 0.5 - 0.5 * (time_of_day * TAU).cos();
+    let t = smoothstep(0.3, 0.7, daylight);
+    let color = NIGHT_COLOR.lerp(&DAY_COLOR, t);
+
+    // Warmer around sunrise and sunset
+    let dusk = 1.0 - ((daylight - 0.5).abs() / 0.2).min(1.0);
+    color.lerp(&color.component_mul(&DUSK_TINT), dusk)
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
@@ -0,0 +1,48 @@
+use nalgebra::{Vector2, Vector4};
+
+use crate::game_state::Room;
+
+static WALL_COLOR: Vector4<u8> = Vector4::new(0x30, 0x30, 0x38, 0xdf);
+static FLOOR_COLOR: Vector4<u8> = Vector4::new(0x80, 0x80, 0x70, 0xdf);
+static WATER_COLOR: Vector4<u8> = Vector4::new(0x30, 0x60, 0xc0, 0xdf);
+
+/// A colour per tile of the room, with empty tiles left transparent
+#[derive(Debug, Clone)]
+pub struct Minimap {
+    pub size: Vector2<u32>,
+    pub colors: Vec<Vector4<u8>>,
+}
+
+impl Minimap {
+    pub fn new(room: &Room) -> Self {
+        let tile_count = (room.size.x * room.size.y) as usize;
+        let mut painted = vec![false; tile_count];
+        for layer in &room.bg_dense_layers {
+            for (i, tile_index) in layer.iter().enumerate() {
+                painted[i] |= tile_index.index.is_some();
+            }
+        }
+        for (position, tile_index) in &room.bg_sparse_layer {
+            let i = (position.y * room.size.x + position.x) as usize;
+            painted[i] |= tile_index.index.is_some();
+        }
+
+        let colors = (0..tile_count)
+            .map(|i| {
+                if room.collisions[i] {
+                    WALL_COLOR
+                } else if room.terrain[i].water {
+                    WATER_COLOR
+                } else if painted[i] {
+                    FLOOR_COLOR
+                } else {
+                    Vector4::zeros()
+                }
+            })
+            .collect();
+        Self {
+            size: room.size,
+            colors,
+        }
+    }
+}
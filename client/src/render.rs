@@ -1,31 +1,60 @@
 use mmo_common::{
+    combat::{DamageType, Resistances},
+    equipment::StatModifiers,
     object::ObjectType,
-    room::{ForegroundTile, TileIndex},
+    room::{
+        self, ForegroundTile, TileIndex, TilesetId, WorldMapRoom, MAX_TILESETS, PIXELS_PER_TILE,
+    },
 };
-use nalgebra::{Vector2, Vector4};
+use nalgebra::{Point2, Vector2, Vector4};
 use web_sys::WebGl2RenderingContext as GL;
 
 use crate::{
     app_state::AppState,
     assets::Assets,
-    camera::{self},
+    console_warn,
     font_atlas::Align,
     game_state::GameState,
+    lighting::{self, LightMap},
     metrics::Metrics,
+    texture::Texture,
     vertex_buffer::{LineVertexBuffer, TileVertexBuffer, VertexBuffer},
 };
 
+static CHARSET_TEXTURE_INDEX: u32 = 0;
+
+static MINIMAP_MAX_SIZE: f32 = 64.0;
+static MINIMAP_MAX_SCALE: f32 = 2.0;
+static WORLD_MAP_MARGIN: f32 = 16.0;
+
+// Below the minimap
+static EQUIPMENT_PANEL_ORIGIN: Vector2<f32> = Vector2::new(4.0, 72.0);
+static EQUIPMENT_PANEL_WIDTH: f32 = 176.0;
+static EQUIPMENT_PANEL_LINE_HEIGHT: f32 = 9.0;
+
+// Below notices
+static BOSS_HEALTH_BAR_TOP: f32 = 32.0;
+static BOSS_HEALTH_BAR_SIZE: Vector2<f32> = Vector2::new(160.0, 6.0);
+
+fn tileset_texture_index(tileset: TilesetId) -> u32 {
+    1 + tileset.0 as u32
+}
+
 pub fn init(state: &mut AppState) {
     let gl = &state.gl;
 
     gl.use_program(Some(&state.program));
-    gl.uniform1iv_with_i32_array(Some(&state.uniform_locations.sampler), &[0, 1]);
+    let texture_units: Vec<i32> = (0..=MAX_TILESETS as i32).collect();
+    gl.uniform1iv_with_i32_array(Some(&state.uniform_locations.sampler), &texture_units);
 
     gl.use_program(Some(&state.text_program));
     gl.uniform1iv_with_i32_array(Some(&state.uniform_locations.text_sampler), &[0, 1]);
 }
 
 pub fn render(state: &mut AppState) {
+    upload_static_tiles(state);
+    update_light_map(state);
+
     let gl = &state.gl;
     gl.clear_color(0.0, 0.0, 0.0, 1.0);
     gl.clear(GL::COLOR_BUFFER_BIT);
@@ -43,17 +72,9 @@ pub fn render(state: &mut AppState) {
     gl.use_program(Some(&state.program));
 
     {
-        let mut vertex_buffer = TileVertexBuffer::new(Vector2::new(
-            assets.tileset.width / camera::PIXELS_PER_TILE,
-            assets.tileset.height / camera::PIXELS_PER_TILE,
-        ));
-
-        for layer in &game_state.room.bg_dense_layers {
-            render_dense_tile_layer(layer, game_state.room.size, &mut vertex_buffer);
-        }
-        render_sparse_tile_layer(&game_state.room.bg_sparse_layer, &mut vertex_buffer);
+        let mut vertex_buffer = tile_vertex_buffer(assets);
+        render_background(game_state, &mut vertex_buffer);
         render_foreground(game_state, &mut vertex_buffer);
-
         let vertex_buffer = vertex_buffer.vertex_buffer;
 
         gl.uniform_matrix3fv_with_f32_array(
@@ -62,19 +83,83 @@ pub fn render(state: &mut AppState) {
             game_state.camera.world_to_ndc.as_slice(),
         );
 
+        for (i, texture) in tile_textures(assets).enumerate() {
+            gl.active_texture(GL::TEXTURE0 + i as u32);
+            gl.bind_texture(GL::TEXTURE_2D, Some(&texture.texture));
+        }
         gl.active_texture(GL::TEXTURE0);
-        gl.bind_texture(GL::TEXTURE_2D, Some(&assets.tileset.texture));
-        gl.active_texture(GL::TEXTURE1);
-        gl.bind_texture(GL::TEXTURE_2D, Some(&assets.charset.texture));
 
+        state.static_tiles_renderer.render_uploaded_triangles(gl);
+        state
+            .vertex_buffer_renderer
+            .render_triangles(&vertex_buffer, gl);
+    }
+    {
+        let ambient = lighting::ambient_color(lighting::time_of_day(game_state));
+        let light_map = state
+            .light_map
+            .as_ref()
+            .filter(|_| game_state.lighting_enabled);
+
+        let screen_size = game_state.camera.logical_screen_size;
+        let zero = Vector2::new(0.0, 0.0);
+        let mut vertex_buffer = VertexBuffer::new();
+        if let Some(light_map) = light_map {
+            let mut light_vertices = VertexBuffer::new();
+            render_lights(game_state, &mut light_vertices);
+
+            gl.bind_framebuffer(GL::FRAMEBUFFER, Some(&light_map.framebuffer));
+            gl.clear_color(ambient.x, ambient.y, ambient.z, 1.0);
+            gl.clear(GL::COLOR_BUFFER_BIT);
+            gl.use_program(Some(&state.light_program));
+            gl.uniform_matrix3fv_with_f32_array(
+                Some(&state.uniform_locations.light_view_projection),
+                false,
+                game_state.camera.world_to_ndc.as_slice(),
+            );
+            gl.blend_func(GL::ONE, GL::ONE);
+            state
+                .vertex_buffer_renderer
+                .render_triangles(&light_vertices, gl);
+            gl.bind_framebuffer(GL::FRAMEBUFFER, None);
+
+            // The framebuffer texture starts at the bottom
+            let white = Vector4::new(0xff, 0xff, 0xff, 0xff);
+            let texture_top_left = Vector2::new(0.0, 1.0);
+            let texture_extent = Vector2::new(1.0, -1.0);
+            vertex_buffer.push_quad(
+                zero,
+                screen_size,
+                texture_top_left,
+                texture_extent,
+                white,
+                0,
+            );
+        } else {
+            let color = (ambient * 255.0).map(|a| a as u8).push(0xff);
+            vertex_buffer.push_quad(zero, screen_size, zero, zero, color, 0);
+        }
+
+        gl.use_program(Some(&state.program));
+        gl.uniform_matrix3fv_with_f32_array(
+            Some(&state.uniform_locations.view_projection),
+            false,
+            game_state.camera.logical_screen_to_ndc.as_slice(),
+        );
+        gl.active_texture(GL::TEXTURE0);
+        let texture = light_map.map_or(&assets.white, |light_map| &light_map.texture);
+        gl.bind_texture(GL::TEXTURE_2D, Some(&texture.texture));
+        gl.blend_func(GL::DST_COLOR, GL::ZERO);
         state
             .vertex_buffer_renderer
             .render_triangles(&vertex_buffer, gl);
+        gl.blend_func(GL::SRC_ALPHA, GL::ONE_MINUS_SRC_ALPHA);
     }
     {
         let mut vertex_buffer = VertexBuffer::new();
         render_health_bars(game_state, &mut vertex_buffer);
         render_attack_markers(game_state, &mut vertex_buffer);
+        render_death_overlay(game_state, &mut vertex_buffer);
 
         gl.uniform_matrix3fv_with_f32_array(
             Some(&state.uniform_locations.view_projection),
@@ -99,6 +184,33 @@ pub fn render(state: &mut AppState) {
             .vertex_buffer_renderer
             .render_lines(&line_vertices, gl);
     }
+    {
+        let mut vertex_buffer = VertexBuffer::new();
+        let mut line_vertices = LineVertexBuffer::new();
+        if game_state.show_world_map {
+            render_world_map(game_state, &mut vertex_buffer, &mut line_vertices);
+        } else {
+            render_minimap(game_state, &mut vertex_buffer);
+            render_equipment_panel(game_state, &mut vertex_buffer);
+        }
+        render_boss_health_bar(game_state, &mut vertex_buffer);
+        let line_vertices = line_vertices.vertex_buffer;
+
+        gl.uniform_matrix3fv_with_f32_array(
+            Some(&state.uniform_locations.view_projection),
+            false,
+            game_state.camera.logical_screen_to_ndc.as_slice(),
+        );
+        gl.active_texture(GL::TEXTURE0);
+        gl.bind_texture(GL::TEXTURE_2D, Some(&assets.white.texture));
+
+        state
+            .vertex_buffer_renderer
+            .render_triangles(&vertex_buffer, gl);
+        state
+            .vertex_buffer_renderer
+            .render_lines(&line_vertices, gl);
+    }
 
     gl.use_program(Some(&state.text_program));
 
@@ -124,6 +236,7 @@ pub fn render(state: &mut AppState) {
     }
     {
         let mut text_vertices = VertexBuffer::new();
+        render_screen_text(game_state, assets, &mut text_vertices);
         render_debug_ui(
             state,
             game_state,
@@ -137,32 +250,69 @@ pub fn render(state: &mut AppState) {
     }
 }
 
-fn render_dense_tile_layer(
-    layer: &[TileIndex],
-    room_size: Vector2<u32>,
-    tileset_vertices: &mut TileVertexBuffer,
-) {
-    for (i, tile_index) in layer.iter().copied().enumerate() {
-        if let Some(tile_index) = tile_index.0 {
-            let i = i as u32;
-            let x = i % room_size.x;
-            let y = i / room_size.x;
-            let xy = Vector2::new(x as f32, y as f32);
-            tileset_vertices.push_tile(xy, tile_index.get() as u32, 0);
-        }
+/// Indexed by texture index
+fn tile_textures(assets: &Assets) -> impl Iterator<Item = &Texture> {
+    std::iter::once(&assets.charset).chain(&assets.tilesets)
+}
+
+fn tile_vertex_buffer(assets: &Assets) -> TileVertexBuffer {
+    TileVertexBuffer::new(
+        tile_textures(assets)
+            .map(|texture| {
+                Vector2::new(
+                    texture.width / PIXELS_PER_TILE,
+                    texture.height / PIXELS_PER_TILE,
+                )
+            })
+            .collect(),
+    )
+}
+
+/// The static tiles only change with the room, the config or the textures
+fn upload_static_tiles(state: &mut AppState) {
+    let (Some(assets), Ok(game_state)) = (&state.assets, &mut state.game_state) else {
+        return;
+    };
+    let room_tiles = &mut game_state.room_tiles;
+    if !room_tiles.needs_upload {
+        return;
     }
+    let mut vertex_buffer = tile_vertex_buffer(assets);
+    for (xy, tile_index) in &room_tiles.static_tiles {
+        push_map_tile(*xy, *tile_index, &mut vertex_buffer);
+    }
+    state
+        .static_tiles_renderer
+        .upload(&vertex_buffer.vertex_buffer, &state.gl);
+    room_tiles.needs_upload = false;
 }
 
-fn render_sparse_tile_layer(
-    layer: &[(Vector2<u32>, TileIndex)],
-    tileset_vertices: &mut TileVertexBuffer,
-) {
-    for (position, tile_index) in layer {
-        if let Some(tile_index) = tile_index.0 {
-            let xy = position.map(|x| x as f32);
-            tileset_vertices.push_tile(xy, tile_index.get() as u32, 0);
+/// Everything but the static tiles, which are drawn from the uploaded buffer
+fn render_background(game_state: &GameState, tile_vertices: &mut TileVertexBuffer) {
+    let room_tiles = &game_state.room_tiles;
+    for (xy, tile_index) in &room_tiles.animated_tiles {
+        if let Some(tile_index) = room_tiles.frame_at(*tile_index, game_state.time.now) {
+            push_map_tile(*xy, tile_index, tile_vertices);
         }
     }
+
+    // Objects are only synced for the current room, so the neighbours' foreground goes below them
+    for neighbour in &game_state.room.neighbours {
+        render_foreground_tile_layer(
+            game_state,
+            &neighbour.room.fg_sparse_layer,
+            (f32::NEG_INFINITY, f32::INFINITY),
+            neighbour.offset.cast(),
+            tile_vertices,
+        );
+    }
+}
+
+fn push_map_tile(xy: Vector2<f32>, tile_index: TileIndex, tile_vertices: &mut TileVertexBuffer) {
+    if let Some(index) = tile_index.index {
+        let texture_index = tileset_texture_index(tile_index.tileset);
+        tile_vertices.push_tile(xy, index.get() as u32, texture_index);
+    }
 }
 
 fn render_foreground(game_state: &GameState, tile_vertices: &mut TileVertexBuffer) {
@@ -171,58 +321,158 @@ fn render_foreground(game_state: &GameState, tile_vertices: &mut TileVertexBuffe
     // TODO: traversing the foreground layer multiple times could be optimized
     for obj in game_state.objects.iter() {
         render_foreground_tile_layer(
+            game_state,
             &game_state.room.fg_sparse_layer,
             (fg_y_lower_bound, obj.local_position.y),
+            Vector2::zeros(),
             tile_vertices,
         );
         fg_y_lower_bound = obj.local_position.y;
 
-        if let Some(animation) = &game_state.client_config.animations.get(obj.animation_id) {
-            let sprite_size = animation.sprite_size;
-            let position = obj.local_position - (sprite_size.cast() - animation.anchor);
-
-            let (animation, started_at) = match &obj.animation {
-                Some(obj_animation) => {
-                    let animation = &animation.custom[obj_animation.animation_index as usize];
-                    (animation, obj_animation.started_at)
-                }
-                None if obj.direction.is_some() => {
-                    (&animation.walk, obj.remote_position_received_at)
-                }
-                None => (&animation.idle, obj.remote_position_received_at),
+        let (custom_index, started_at) = match &obj.animation {
+            Some(obj_animation) if obj_animation.started_at <= game_state.time.now => (
+                Some(obj_animation.animation_index as usize),
+                obj_animation.started_at,
+            ),
+            _ => (None, obj.remote_position_received_at),
+        };
+        let animation_time = game_state.time.now - started_at;
+
+        // Equipment overlays play the same animation on top of the object
+        let animation_ids =
+            std::iter::once(obj.animation_id).chain(obj.overlay_animation_ids.iter().copied());
+        for animation_id in animation_ids {
+            let Some(animation_set) = game_state.client_config.animations.get(animation_id) else {
+                continue;
+            };
+            let sprite_size = animation_set.sprite_size;
+            let position = obj.local_position - (sprite_size.cast() - animation_set.anchor);
+
+            let animation = match custom_index {
+                Some(i) => match animation_set.custom.get(i) {
+                    Some(animation) => animation,
+                    None => continue,
+                },
+                None if obj.direction.is_some() => &animation_set.walk,
+                None => &animation_set.idle,
             };
-            let direction = obj.look_direction;
-            let animation_time = game_state.time.now - started_at;
-            if let Some(sprite_index) = animation.get(direction, animation_time) {
-                tile_vertices.push_tile_multi(position, sprite_size, sprite_index.0 as _, 1);
+            if let Some(sprite_index) = animation.get(obj.look_direction, animation_time) {
+                tile_vertices.push_tile_multi(
+                    position,
+                    sprite_size,
+                    sprite_index.0 as _,
+                    CHARSET_TEXTURE_INDEX,
+                );
             }
         }
     }
 
     render_foreground_tile_layer(
+        game_state,
         &game_state.room.fg_sparse_layer,
         (fg_y_lower_bound, f32::INFINITY),
+        Vector2::zeros(),
         tile_vertices,
     );
 }
 
 fn render_foreground_tile_layer(
+    game_state: &GameState,
     layer: &[ForegroundTile],
     y_bounds: (f32, f32),
+    offset: Vector2<f32>,
     tile_vertices: &mut TileVertexBuffer,
 ) {
     for fg_tile in layer.iter() {
         let fg_y = fg_tile.position.y as f32;
         let fg_dy = fg_tile.height as f32 + 1.0; // adding 1 so the bottom of the tile is the reference point
         if fg_y + fg_dy >= y_bounds.0 && fg_y + fg_dy < y_bounds.1 {
-            if let TileIndex(Some(fg_tile_index)) = fg_tile.tile_index {
-                let xy = fg_tile.position.cast();
-                tile_vertices.push_tile(xy, fg_tile_index.get() as _, 0);
+            let tile_index = game_state
+                .room_tiles
+                .frame_at(fg_tile.tile_index, game_state.time.now);
+            if let Some(tile_index) = tile_index {
+                push_map_tile(offset + fg_tile.position.cast(), tile_index, tile_vertices);
             }
         }
     }
 }
 
+/// Keeps the light map the size of the screen, or gives up on it if the context can't render to
+/// textures
+fn update_light_map(state: &mut AppState) {
+    if !state.light_map_supported {
+        return;
+    }
+    if let Some(light_map) = &state.light_map {
+        if light_map.size() == state.viewport {
+            return;
+        }
+    }
+    if let Some(light_map) = state.light_map.take() {
+        light_map.delete(&state.gl);
+    }
+    if state.viewport.x == 0 || state.viewport.y == 0 {
+        return;
+    }
+    match LightMap::new(&state.gl, state.viewport) {
+        Ok(light_map) => state.light_map = Some(light_map),
+        Err(err) => {
+            console_warn!("Lighting disabled: {err:?}");
+            state.light_map_supported = false;
+        }
+    }
+}
+
+/// Placed lights of the room and its neighbours, and the area attacks
+fn render_lights(game_state: &GameState, vertex_buffer: &mut VertexBuffer) {
+    let room = &game_state.room;
+    let placed_lights = room
+        .lights
+        .iter()
+        .map(|light| (Vector2::zeros(), light))
+        .chain(room.neighbours.iter().flat_map(|neighbour| {
+            let offset: Vector2<f32> = neighbour.offset.cast();
+            neighbour
+                .room
+                .lights
+                .iter()
+                .map(move |light| (offset, light))
+        }));
+    for (offset, light) in placed_lights {
+        push_light(
+            offset + light.position,
+            light.radius,
+            light.color.push(0xff),
+            vertex_buffer,
+        );
+    }
+
+    for marker in &game_state.attack_markers {
+        if marker.started_at > game_state.time.now {
+            continue;
+        }
+        let color = Vector4::new(0xff, 0x40, 0x20, 0xbf);
+        push_light(marker.position, marker.radius + 1.0, color, vertex_buffer);
+    }
+}
+
+fn push_light(
+    position: Vector2<f32>,
+    radius: f32,
+    color: Vector4<u8>,
+    vertex_buffer: &mut VertexBuffer,
+) {
+    let radius = Vector2::new(radius, radius);
+    vertex_buffer.push_quad(
+        position - radius,
+        radius * 2.0,
+        Vector2::new(-1.0, -1.0),
+        Vector2::new(2.0, 2.0),
+        color,
+        0,
+    );
+}
+
 fn render_health_bars(game_state: &GameState, vertex_buffer: &mut VertexBuffer) {
     for obj in game_state.objects.iter() {
         if let Some(animation) = game_state.client_config.animations.get(obj.animation_id) {
@@ -256,6 +506,10 @@ fn render_debug_lines(game_state: &GameState, vertex_buffer: &mut LineVertexBuff
 
 fn render_attack_markers(game_state: &GameState, vertex_buffer: &mut VertexBuffer) {
     for marker in &game_state.attack_markers {
+        if marker.started_at > game_state.time.now {
+            continue;
+        }
+
         let wh = Vector2::new(2.0, 2.0) * marker.radius;
         let xy = marker.position - wh / 2.0;
         let color = Vector4::new(0xff, 0, 0, 0x1f);
@@ -268,7 +522,7 @@ fn render_attack_markers(game_state: &GameState, vertex_buffer: &mut VertexBuffe
             0,
         );
 
-        let t = (game_state.time.now - marker.received_at) / marker.length;
+        let t = (game_state.time.now - marker.started_at) / marker.length;
         let wh = wh * t;
         let xy = marker.position - wh / 2.0;
         vertex_buffer.push_quad(
@@ -282,6 +536,274 @@ fn render_attack_markers(game_state: &GameState, vertex_buffer: &mut VertexBuffe
     }
 }
 
+fn render_death_overlay(game_state: &GameState, vertex_buffer: &mut VertexBuffer) {
+    if game_state.death.is_none() {
+        return;
+    }
+    if let Some(logical_screen_to_world) = game_state.camera.world_to_logical_screen.try_inverse() {
+        let xy = logical_screen_to_world
+            .transform_point(&Point2::origin())
+            .coords;
+        let wh = logical_screen_to_world.transform_vector(&game_state.camera.logical_screen_size);
+        let zero = Vector2::new(0.0, 0.0);
+        let color = Vector4::new(0, 0, 0, 0x9f);
+        vertex_buffer.push_quad(xy, wh, zero, zero, color, 0);
+    }
+}
+
+/// Drawn in logical screen pixels at the top left corner
+fn render_minimap(game_state: &GameState, vertex_buffer: &mut VertexBuffer) {
+    let minimap = &game_state.minimap;
+    let Some(&longest_side) = minimap.size.iter().max() else {
+        return;
+    };
+    if longest_side == 0 {
+        return;
+    }
+    let scale = (MINIMAP_MAX_SIZE / longest_side as f32).min(MINIMAP_MAX_SCALE);
+    let origin = Vector2::new(4.0, 4.0);
+    let zero = Vector2::new(0.0, 0.0);
+
+    let wh = minimap.size.cast() * scale;
+    let color = Vector4::new(0, 0, 0, 0x7f);
+    vertex_buffer.push_quad(origin, wh, zero, zero, color, 0);
+
+    let tile_wh = Vector2::new(scale, scale);
+    for (i, color) in minimap.colors.iter().enumerate() {
+        if color.w == 0 {
+            continue;
+        }
+        let i = i as u32;
+        let position = Vector2::new(i % minimap.size.x, i / minimap.size.x);
+        let xy = origin + position.cast() * scale;
+        vertex_buffer.push_quad(xy, tile_wh, zero, zero, *color, 0);
+    }
+
+    let marker_wh = Vector2::new(2.0, 2.0);
+    for portal in &game_state.room.portals {
+        let xy = origin + portal.cast().add_scalar(0.5) * scale - marker_wh / 2.0;
+        let color = Vector4::new(0xc0, 0x40, 0xff, 0xff);
+        vertex_buffer.push_quad(xy, marker_wh, zero, zero, color, 0);
+    }
+    for obj in &game_state.objects {
+        if !room::in_bounds(minimap.size, obj.local_position) {
+            continue;
+        }
+        let color = if obj.id == game_state.self_id {
+            Vector4::new(0xff, 0xff, 0, 0xff)
+        } else if obj.typ == ObjectType::Player {
+            Vector4::new(0xff, 0xff, 0xff, 0xff)
+        } else {
+            Vector4::new(0xff, 0, 0, 0xff)
+        };
+        let xy = origin + obj.local_position * scale - marker_wh / 2.0;
+        vertex_buffer.push_quad(xy, marker_wh, zero, zero, color, 0);
+    }
+}
+
+fn render_boss_health_bar(game_state: &GameState, vertex_buffer: &mut VertexBuffer) {
+    let Some(boss) = &game_state.boss_health_bar else {
+        return;
+    };
+    let x = (game_state.camera.logical_screen_size.x - BOSS_HEALTH_BAR_SIZE.x) / 2.0;
+    let xy = Vector2::new(x, BOSS_HEALTH_BAR_TOP);
+    let zero = Vector2::new(0.0, 0.0);
+    let border = Vector2::new(1.0, 1.0);
+    let background = Vector4::new(0, 0, 0, 0xbf);
+    vertex_buffer.push_quad(
+        xy - border,
+        BOSS_HEALTH_BAR_SIZE + 2.0 * border,
+        zero,
+        zero,
+        background,
+        0,
+    );
+
+    let fraction = (boss.health as f32 / boss.max_health.max(1) as f32).clamp(0.0, 1.0);
+    let wh = Vector2::new(BOSS_HEALTH_BAR_SIZE.x * fraction, BOSS_HEALTH_BAR_SIZE.y);
+    let color = if boss.enraged {
+        Vector4::new(0xff, 0x60, 0, 0xff)
+    } else {
+        Vector4::new(0xc0, 0, 0, 0xff)
+    };
+    vertex_buffer.push_quad(xy, wh, zero, zero, color, 0);
+}
+
+fn render_equipment_panel(game_state: &GameState, vertex_buffer: &mut VertexBuffer) {
+    if !game_state.show_equipment {
+        return;
+    }
+    let line_count = equipment_panel_lines(game_state).len() as f32;
+    let wh = Vector2::new(
+        EQUIPMENT_PANEL_WIDTH,
+        line_count * EQUIPMENT_PANEL_LINE_HEIGHT + 4.0,
+    );
+    let zero = Vector2::new(0.0, 0.0);
+    let color = Vector4::new(0, 0, 0, 0x9f);
+    vertex_buffer.push_quad(EQUIPMENT_PANEL_ORIGIN, wh, zero, zero, color, 0);
+}
+
+/// Equipped items are highlighted
+fn equipment_panel_lines(game_state: &GameState) -> Vec<(String, bool)> {
+    let mut lines = vec![("Equipment (1-9)".to_string(), false)];
+    for (i, item) in game_state
+        .client_config
+        .equipment
+        .iter()
+        .take(9)
+        .enumerate()
+    {
+        let modifiers = describe_modifiers(&item.modifiers);
+        let equipped = game_state.equipped.contains(&item.id);
+        lines.push((format!("{} {} {modifiers}", i + 1, item.name), equipped));
+    }
+    if let Some(stats) = &game_state.stats {
+        lines.push((
+            format!(
+                "HP {} Dmg {} Def {}",
+                stats.max_health, stats.damage, stats.defence
+            ),
+            false,
+        ));
+        lines.push((
+            format!(
+                "Range {:.1} Speed {:.1} Crit {:.0}%",
+                stats.attack_range,
+                stats.velocity,
+                stats.critical_chance * 100.0
+            ),
+            false,
+        ));
+        let resistances = resistance_list(&stats.resistances)
+            .iter()
+            .filter(|(_, resistance)| *resistance != 0.0)
+            .map(|(damage_type, resistance)| format!("{damage_type:?} {:.0}%", resistance * 100.0))
+            .collect::<Vec<_>>();
+        lines.push((
+            format!("{:?} damage {}", stats.damage_type, resistances.join(" ")),
+            false,
+        ));
+    }
+    lines
+}
+
+fn describe_modifiers(modifiers: &StatModifiers) -> String {
+    let mut parts = vec![];
+    if modifiers.max_health != 0 {
+        parts.push(format!("{:+} hp", modifiers.max_health));
+    }
+    if modifiers.damage != 0 {
+        parts.push(format!("{:+} dmg", modifiers.damage));
+    }
+    if modifiers.defence != 0 {
+        parts.push(format!("{:+} def", modifiers.defence));
+    }
+    if modifiers.attack_range != 0.0 {
+        parts.push(format!("{:+} range", modifiers.attack_range));
+    }
+    if modifiers.velocity != 0.0 {
+        parts.push(format!("{:+} speed", modifiers.velocity));
+    }
+    if modifiers.critical_chance != 0.0 {
+        parts.push(format!("{:+}% crit", modifiers.critical_chance * 100.0));
+    }
+    for (damage_type, resistance) in resistance_list(&modifiers.resistances) {
+        if resistance != 0.0 {
+            parts.push(format!("{:+}% {damage_type:?} res", resistance * 100.0));
+        }
+    }
+    if let Some(damage_type) = modifiers.damage_type {
+        parts.push(format!("{damage_type:?}"));
+    }
+    parts.join(" ")
+}
+
+fn resistance_list(resistances: &Resistances) -> [(DamageType, f32); 3] {
+    [DamageType::Physical, DamageType::Fire, DamageType::Poison]
+        .map(|damage_type| (damage_type, resistances.get(damage_type)))
+}
+
+/// Scale and offset that fit the whole world on the screen, from world tiles to logical pixels
+fn world_map_transform(game_state: &GameState) -> Option<(f32, Vector2<f32>)> {
+    let rooms = &game_state.client_config.world_map;
+    let min = rooms
+        .iter()
+        .map(|room| room.position.cast::<f32>())
+        .reduce(|a, b| a.inf(&b))?;
+    let max = rooms
+        .iter()
+        .map(|room| room.position.cast::<f32>() + room.size.cast())
+        .reduce(|a, b| a.sup(&b))?;
+    let extent = max - min;
+    let available = game_state
+        .camera
+        .logical_screen_size
+        .add_scalar(-2.0 * WORLD_MAP_MARGIN);
+    let scale = (available.x / extent.x).min(available.y / extent.y);
+    if !scale.is_finite() || scale <= 0.0 {
+        return None;
+    }
+    let offset = (game_state.camera.logical_screen_size - extent * scale) / 2.0 - min * scale;
+    Some((scale, offset))
+}
+
+fn render_world_map(
+    game_state: &GameState,
+    vertex_buffer: &mut VertexBuffer,
+    line_vertices: &mut LineVertexBuffer,
+) {
+    let zero = Vector2::new(0.0, 0.0);
+    let color = Vector4::new(0, 0, 0, 0xbf);
+    let screen_size = game_state.camera.logical_screen_size;
+    vertex_buffer.push_quad(zero, screen_size, zero, zero, color, 0);
+
+    let Some((scale, offset)) = world_map_transform(game_state) else {
+        return;
+    };
+    let rooms = &game_state.client_config.world_map;
+    let center = |room: &WorldMapRoom| {
+        offset + (room.position.cast::<f32>() + room.size.cast() / 2.0) * scale
+    };
+
+    for room in rooms {
+        let xy = offset + room.position.cast() * scale;
+        let wh = room.size.cast() * scale;
+        let color = if room.room_id == game_state.room.room_id {
+            Vector4::new(0xc0, 0xa0, 0x40, 0xff)
+        } else {
+            Vector4::new(0x60, 0x60, 0x60, 0xff)
+        };
+        vertex_buffer.push_quad(xy, wh, zero, zero, color, 0);
+        line_vertices.push_rect(xy, wh, Vector4::new(0xa0, 0xa0, 0xa0, 0xff));
+    }
+
+    for room in rooms {
+        for target_id in &room.connections {
+            if target_id.0 < room.room_id.0 {
+                continue;
+            }
+            if let Some(target) = rooms.iter().find(|r| r.room_id == *target_id) {
+                let color = Vector4::new(0xc0, 0x40, 0xff, 0xff);
+                line_vertices.push_line(center(room), center(target), color);
+            }
+        }
+    }
+
+    let current_room = rooms
+        .iter()
+        .find(|room| room.room_id == game_state.room.room_id);
+    let self_object = game_state
+        .objects
+        .iter()
+        .find(|obj| obj.id == game_state.self_id);
+    if let (Some(room), Some(obj)) = (current_room, self_object) {
+        let marker_wh = Vector2::new(3.0, 3.0);
+        let xy = offset + (room.position.cast() + obj.local_position) * scale - marker_wh / 2.0;
+        let color = Vector4::new(0xff, 0xff, 0, 0xff);
+        vertex_buffer.push_quad(xy, marker_wh, zero, zero, color, 0);
+    }
+}
+
 fn render_world_text(game_state: &GameState, assets: &Assets, vertex_buffer: &mut VertexBuffer) {
     let black = Vector4::new(0, 0, 0, 0xff);
     let eps = Vector2::new(0.4, 0.4);
@@ -293,13 +815,17 @@ fn render_world_text(game_state: &GameState, assets: &Assets, vertex_buffer: &mu
             } else {
                 Vector4::new(0xff, 0xff, 0xff, 0xff)
             };
-            let str = obj.id.0.to_string();
+            assets.font_atlas.push_text(
+                &obj.name,
+                xy + eps,
+                6.0,
+                black,
+                Align::Center,
+                vertex_buffer,
+            );
             assets
                 .font_atlas
-                .push_text(&str, xy + eps, 6.0, black, Align::Center, vertex_buffer);
-            assets
-                .font_atlas
-                .push_text(&str, xy, 6.0, color, Align::Center, vertex_buffer);
+                .push_text(&obj.name, xy, 6.0, color, Align::Center, vertex_buffer);
         }
     }
 
@@ -307,20 +833,102 @@ fn render_world_text(game_state: &GameState, assets: &Assets, vertex_buffer: &mu
         let dt = game_state.time.now - label.received_at;
         let dy = 5.0 + 10.0 * dt * dt;
         let xy = game_state.camera.world_point_to_screen(label.position) - Vector2::new(0.0, dy);
-        let color = if label.health_change > 0 {
-            Vector4::new(0, 0xff, 0xff, 0xff)
-        } else if label.object_type == ObjectType::Mob {
-            Vector4::new(0xff, 0xff, 0xff, 0xff)
+        let color = match label.damage_type {
+            None => Vector4::new(0, 0xff, 0xff, 0xff),
+            Some(DamageType::Fire) => Vector4::new(0xff, 0x90, 0x20, 0xff),
+            Some(DamageType::Poison) => Vector4::new(0x60, 0xe0, 0x40, 0xff),
+            Some(DamageType::Physical) if label.object_type == ObjectType::Mob => {
+                Vector4::new(0xff, 0xff, 0xff, 0xff)
+            }
+            Some(DamageType::Physical) => Vector4::new(0xff, 0, 0, 0xff),
+        };
+        let (str, size) = if label.critical {
+            (format!("{}!", label.health_change.abs()), 12.0)
         } else {
-            Vector4::new(0xff, 0, 0, 0xff)
+            (label.health_change.abs().to_string(), 8.0)
         };
-        let str = label.health_change.abs().to_string();
         assets
             .font_atlas
-            .push_text(&str, xy + eps, 8.0, black, Align::Center, vertex_buffer);
+            .push_text(&str, xy + eps, size, black, Align::Center, vertex_buffer);
         assets
             .font_atlas
-            .push_text(&str, xy, 8.0, color, Align::Center, vertex_buffer);
+            .push_text(&str, xy, size, color, Align::Center, vertex_buffer);
+    }
+}
+
+fn render_screen_text(game_state: &GameState, assets: &Assets, buf: &mut VertexBuffer) {
+    let white = Vector4::new(0xff, 0xff, 0xff, 0xff);
+    let center = game_state.camera.logical_screen_size / 2.0;
+    let fa = &assets.font_atlas;
+
+    if let Some(death) = &game_state.death {
+        let xy = center - Vector2::new(0.0, 16.0);
+        fa.push_text("You died", xy, 16.0, white, Align::Center, buf);
+        let respawn_in = (death.respawn_at - game_state.time.now).ceil();
+        if respawn_in > 0.0 {
+            let str = format!("Respawning in {respawn_in}");
+            fa.push_text(
+                &str,
+                center + Vector2::new(0.0, 4.0),
+                8.0,
+                white,
+                Align::Center,
+                buf,
+            );
+        }
+    }
+
+    if game_state.show_world_map {
+        if let Some((scale, offset)) = world_map_transform(game_state) {
+            for room in &game_state.client_config.world_map {
+                let xy = offset + room.position.cast() * scale + Vector2::new(2.0, 2.0);
+                fa.push_text(&room.name, xy, 6.0, white, Align::Left, buf);
+            }
+        }
+    }
+
+    if game_state.show_equipment && !game_state.show_world_map {
+        let highlight = Vector4::new(0xff, 0xd0, 0x40, 0xff);
+        for (i, (line, equipped)) in equipment_panel_lines(game_state).iter().enumerate() {
+            let xy = EQUIPMENT_PANEL_ORIGIN
+                + Vector2::new(2.0, 2.0 + i as f32 * EQUIPMENT_PANEL_LINE_HEIGHT);
+            let color = if *equipped { highlight } else { white };
+            fa.push_text(line, xy, 7.0, color, Align::Left, buf);
+        }
+    }
+
+    if let Some(boss) = &game_state.boss_health_bar {
+        let xy = Vector2::new(center.x, BOSS_HEALTH_BAR_TOP - 10.0);
+        let name = if boss.enraged {
+            format!("{} (enraged)", boss.name)
+        } else {
+            boss.name.clone()
+        };
+        fa.push_text(&name, xy, 8.0, white, Align::Center, buf);
+    }
+
+    if let Some(notice) = &game_state.notice {
+        let xy = Vector2::new(center.x, 8.0);
+        fa.push_text(&notice.text, xy, 8.0, white, Align::Center, buf);
+    }
+
+    if let Some(shutdown_at) = game_state.shutdown_at {
+        let shutdown_in = (shutdown_at - game_state.time.now).ceil().max(0.0);
+        let str = format!("Server shutting down in {shutdown_in}");
+        let xy = center - Vector2::new(0.0, 40.0);
+        fa.push_text(&str, xy, 8.0, white, Align::Center, buf);
+    }
+
+    let self_object = game_state
+        .objects
+        .iter()
+        .find(|obj| obj.id == game_state.self_id);
+    if let Some(obj) = self_object {
+        let room = &game_state.room;
+        if room::terrain_at(room.size, &room.terrain, obj.local_position).no_pvp {
+            let xy = Vector2::new(4.0, game_state.camera.logical_screen_size.y - 12.0);
+            fa.push_text("Safe zone", xy, 8.0, white, Align::Left, buf);
+        }
     }
 }
 
@@ -337,6 +945,14 @@ fn render_debug_ui(
         ("p50:", &format!("{:.1}ms", metrics.fps_stats.median_ms)),
         ("p100:", &format!("{:.1} ms", metrics.fps_stats.max_ms)),
         ("ping:", &format!("{:.1} ms", game_state.ping_rtt * 1000.0)),
+        (
+            "jitter:",
+            &format!("{:.1} ms", game_state.ping_jitter * 1000.0),
+        ),
+        (
+            "delay:",
+            &format!("{:.1} ms", game_state.interpolation_delay * 1000.0),
+        ),
         ("in:", &format!("{} B/s", metrics.net_stats.in_bytes)),
         ("", &format!("{} evt/s", metrics.net_stats.in_events)),
         ("", &format!("{} frame/s", metrics.net_stats.in_frames)),
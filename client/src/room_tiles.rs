@@ -0,0 +1,73 @@
+use std::collections::{HashMap, HashSet};
+
+use mmo_common::{animation::TileAnimation, client_config::ClientConfig, room::TileIndex};
+use nalgebra::Vector2;
+
+use crate::game_state::Room;
+
+/// Background tiles of the room and its neighbours, split so that only the animated ones
+/// change from frame to frame
+#[derive(Debug, Clone)]
+pub struct RoomTiles {
+    /// Keyed by the first frame, which is the tile painted in the map
+    pub animations: HashMap<TileIndex, TileAnimation>,
+    pub static_tiles: Vec<(Vector2<f32>, TileIndex)>,
+    /// Animated tiles and everything stacked on top of them, in drawing order
+    pub animated_tiles: Vec<(Vector2<f32>, TileIndex)>,
+    /// Static tiles are kept on the GPU until they or the textures change
+    pub needs_upload: bool,
+}
+
+impl RoomTiles {
+    pub fn new(room: &Room, client_config: &ClientConfig) -> Self {
+        let animations = client_config
+            .tile_animations
+            .iter()
+            .filter_map(|animation| Some((*animation.frames.first()?, animation.clone())))
+            .collect();
+        let mut room_tiles = RoomTiles {
+            animations,
+            static_tiles: vec![],
+            animated_tiles: vec![],
+            needs_upload: true,
+        };
+        for neighbour in &room.neighbours {
+            room_tiles.add_room(&neighbour.room, neighbour.offset.cast());
+        }
+        room_tiles.add_room(room, Vector2::zeros());
+        room_tiles
+    }
+
+    fn add_room(&mut self, room: &Room, offset: Vector2<f32>) {
+        let dense_tiles = room.bg_dense_layers.iter().flat_map(|layer| {
+            layer.iter().enumerate().map(|(i, tile_index)| {
+                let i = i as u32;
+                (Vector2::new(i % room.size.x, i / room.size.x), *tile_index)
+            })
+        });
+        let sparse_tiles = room.bg_sparse_layer.iter().copied();
+
+        let mut animated_positions = HashSet::new();
+        for (position, tile_index) in dense_tiles.chain(sparse_tiles) {
+            if tile_index.index.is_none() {
+                continue;
+            }
+            if self.animations.contains_key(&tile_index) {
+                animated_positions.insert(position);
+            }
+            let xy = offset + position.cast();
+            if animated_positions.contains(&position) {
+                self.animated_tiles.push((xy, tile_index));
+            } else {
+                self.static_tiles.push((xy, tile_index));
+            }
+        }
+    }
+
+    pub fn frame_at(&self, tile_index: TileIndex, time: f32) -> Option<TileIndex> {
+        match self.animations.get(&tile_index) {
+            Some(animation) => animation.get(time),
+            None => Some(tile_index),
+        }
+    }
+}
@@ -7,13 +7,32 @@ flat in float vertTextureIndex;
 
 out vec4 fragColor;
 
-uniform sampler2D sampler[2];
+// The charset and up to 7 tilesets
+uniform sampler2D sampler[8];
 
 void main() {
     switch (int(vertTextureIndex)) {
         case 1:
             fragColor = texture(sampler[1], vertTexturePosition);
             break;
+        case 2:
+            fragColor = texture(sampler[2], vertTexturePosition);
+            break;
+        case 3:
+            fragColor = texture(sampler[3], vertTexturePosition);
+            break;
+        case 4:
+            fragColor = texture(sampler[4], vertTexturePosition);
+            break;
+        case 5:
+            fragColor = texture(sampler[5], vertTexturePosition);
+            break;
+        case 6:
+            fragColor = texture(sampler[6], vertTexturePosition);
+            break;
+        case 7:
+            fragColor = texture(sampler[7], vertTexturePosition);
+            break;
         default:
             fragColor = texture(sampler[0], vertTexturePosition);
             break;
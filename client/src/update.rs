@@ -1,20 +1,42 @@
+use std::collections::VecDeque;
+
 use mmo_common::client_config::ClientConfig;
 use mmo_common::object::{Direction4, Direction8};
-use mmo_common::player_command::{GlobalCommand, RoomCommand};
-use mmo_common::player_event::{PlayerEvent, PlayerEventEnvelope};
+use mmo_common::player_command::{self, GlobalCommand, PlayerCommand, RoomCommand};
+use mmo_common::player_event::{self, PlayerEvent, PlayerEventEnvelope};
 use mmo_common::room::RoomSync;
 use mmo_common::{rle, room};
 use nalgebra::Vector2;
 
 use crate::app_event::{AppEvent, MouseButton};
 use crate::app_state::AppState;
-use crate::camera::Camera;
+use crate::camera::{self, Camera};
 use crate::game_state::{
-    AttackMarker, GameState, HealthChangeLabel, LastPing, Object, ObjectAnimation,
-    PartialGameState, Room,
+    AttackMarker, CameraShake, Death, GameState, HealthChangeLabel, LastPing, MovementPrediction,
+    MovementSnapshot, Neighbour, Notice, Object, ObjectAnimation, PartialGameState, PendingMove,
+    Room, WorldClock,
 };
+use crate::minimap::Minimap;
+use crate::room_tiles::RoomTiles;
 use crate::{assets, console_error, console_warn};
 
+static NOTICE_DURATION: f32 = 3.0;
+
+static NEIGHBOUR_VIEW_DISTANCE: f32 = 4.0;
+
+static CAMERA_DEAD_ZONE: Vector2<f32> = Vector2::new(1.5, 1.0);
+static CAMERA_SMOOTHING_TIME: f32 = 0.15;
+static CAMERA_SNAP_DISTANCE: f32 = 8.0;
+
+static SCREEN_SHAKE_DURATION: f32 = 0.3;
+static SCREEN_SHAKE_AMPLITUDE: f32 = 0.2;
+static SCREEN_SHAKE_FREQUENCY: f32 = 40.0;
+
+static PREDICTION_SMOOTHING_TIME: f32 = 0.1;
+static PREDICTION_MAX_SMOOTHED_ERROR: f32 = 2.0;
+
+static INTERPOLATION_DELAY_ADJUST_RATE: f32 = 0.5;
+
 pub fn update(state: &mut AppState, events: Vec<AppEvent>) {
     update_camera(state);
 
@@ -29,6 +51,18 @@ pub fn update(state: &mut AppState, events: Vec<AppEvent>) {
                         "KeyD" => direction_pressed(game_state, Direction4::Right, true),
                         "Space" => start_attack(game_state),
                         "KeyP" => game_state.show_debug = !game_state.show_debug,
+                        "KeyM" => game_state.show_world_map = !game_state.show_world_map,
+                        "KeyL" => game_state.lighting_enabled = !game_state.lighting_enabled,
+                        "KeyI" => game_state.show_equipment = !game_state.show_equipment,
+                        "Equal" | "NumpadAdd" => change_zoom(game_state, 1),
+                        "Minus" | "NumpadSubtract" => change_zoom(game_state, -1),
+                        code if game_state.show_equipment => {
+                            if let Some(digit) = code.strip_prefix("Digit") {
+                                if let Ok(number @ 1..=9) = digit.parse::<usize>() {
+                                    toggle_equipment(game_state, number - 1);
+                                }
+                            }
+                        }
                         _ => (),
                     }
                 }
@@ -51,13 +85,33 @@ pub fn update(state: &mut AppState, events: Vec<AppEvent>) {
                     }
                 }
             }
-            AppEvent::WebsocketConnected => {}
-            AppEvent::WebsocketDisconnected => state.game_state = Err(PartialGameState::new()),
-            AppEvent::WebsocketMessage {
+            AppEvent::MouseWheel { delta_y } => {
+                if let Ok(game_state) = &mut state.game_state {
+                    if delta_y < 0.0 {
+                        change_zoom(game_state, 1);
+                    } else if delta_y > 0.0 {
+                        change_zoom(game_state, -1);
+                    }
+                }
+            }
+            AppEvent::JoinSubmitted { name, appearance } => {
+                if let Err(partial) = &mut state.game_state {
+                    match player_command::check_player_name(&name) {
+                        Ok(()) => partial
+                            .ws_commands
+                            .push(GlobalCommand::Join { name, appearance }.into()),
+                        Err(reason) => state.join_form.show_error(&reason),
+                    }
+                }
+            }
+            AppEvent::ServerConnected => {}
+            AppEvent::ServerDisconnected => state.game_state = Err(PartialGameState::new()),
+            AppEvent::ServerMessage {
                 message,
                 received_at,
             } => {
                 update_async(state, &message);
+                update_join_form(state, &message);
 
                 match &mut state.game_state {
                     Ok(game_state) => {
@@ -75,7 +129,10 @@ pub fn update(state: &mut AppState, events: Vec<AppEvent>) {
                 }
             }
             AppEvent::AssetsLoaded { assets } => {
-                state.assets = Some(assets);
+                state.assets = Some(*assets);
+                if let Ok(game_state) = &mut state.game_state {
+                    game_state.room_tiles.needs_upload = true;
+                }
             }
         }
     }
@@ -96,22 +153,57 @@ pub fn update(state: &mut AppState, events: Vec<AppEvent>) {
             .retain(|label| game_state.time.now - label.received_at < 1.0);
         game_state
             .attack_markers
-            .retain(|marker| game_state.time.now - marker.received_at < marker.length);
+            .retain(|marker| game_state.time.now - marker.started_at < marker.length);
+        if game_state
+            .notice
+            .as_ref()
+            .is_some_and(|notice| game_state.time.now - notice.received_at >= NOTICE_DURATION)
+        {
+            game_state.notice = None;
+        }
     }
 }
 
 fn update_async(state: &mut AppState, message: &PlayerEventEnvelope<PlayerEvent>) {
     for event in message.events.iter() {
-        if let PlayerEvent::Initial { client_config, .. } = event {
-            let gl = state.gl.clone();
-            let events = state.events.clone();
-            let asset_paths = client_config.asset_paths.clone();
-            wasm_bindgen_futures::spawn_local(async move {
-                let assets = assets::load(&gl, &asset_paths).await.unwrap();
-                (*events)
-                    .borrow_mut()
-                    .push(AppEvent::AssetsLoaded { assets });
-            });
+        let client_config = match event {
+            PlayerEvent::Initial { client_config, .. } => client_config,
+            // The map may have switched tilesets
+            PlayerEvent::ClientConfigChanged { client_config }
+                if state
+                    .assets
+                    .as_ref()
+                    .is_some_and(|assets| assets.asset_paths != client_config.asset_paths) =>
+            {
+                client_config
+            }
+            _ => continue,
+        };
+        let gl = state.gl.clone();
+        let events = state.events.clone();
+        let asset_paths = client_config.asset_paths.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            let assets = Box::new(assets::load(&gl, &asset_paths).await.unwrap());
+            (*events)
+                .borrow_mut()
+                .push(AppEvent::AssetsLoaded { assets });
+        });
+    }
+}
+
+fn update_join_form(state: &AppState, message: &PlayerEventEnvelope<PlayerEvent>) {
+    for event in message.events.iter() {
+        let result = match event {
+            PlayerEvent::JoinOptions { appearances } => state.join_form.show(appearances),
+            PlayerEvent::JoinRejected { reason } => {
+                state.join_form.show_error(reason);
+                Ok(())
+            }
+            PlayerEvent::Initial { .. } => state.join_form.hide(),
+            _ => Ok(()),
+        };
+        if let Err(err) = result {
+            console_error!("Failed to update the join form: {err:?}");
         }
     }
 }
@@ -129,16 +221,29 @@ fn update_partial(partial: &mut PartialGameState, events: PlayerEventEnvelope<Pl
                 partial.self_id = Some(self_id);
                 partial.client_config = Some(*client_config);
             }
+            PlayerEvent::ClientConfigChanged { client_config } => {
+                partial.client_config = Some(*client_config);
+            }
             PlayerEvent::RoomEntered { room } => {
                 partial.room = Some(load_room_map(*room));
             }
+            PlayerEvent::JoinOptions { .. } | PlayerEvent::JoinRejected { .. } => {}
             PlayerEvent::Pong { .. }
             | PlayerEvent::ObjectAppeared { .. }
             | PlayerEvent::ObjectMovementChanged { .. }
+            | PlayerEvent::MovementAcknowledged { .. }
             | PlayerEvent::ObjectAnimationAction { .. }
             | PlayerEvent::ObjectHealthChanged { .. }
+            | PlayerEvent::ObjectEquipmentChanged { .. }
+            | PlayerEvent::StatsChanged { .. }
+            | PlayerEvent::BossHealthBar { .. }
             | PlayerEvent::AttackTargeted { .. }
-            | PlayerEvent::ObjectDisappeared { .. } => {
+            | PlayerEvent::ObjectDisappeared { .. }
+            | PlayerEvent::CheckpointActivated { .. }
+            | PlayerEvent::Died { .. }
+            | PlayerEvent::WorldClock { .. }
+            | PlayerEvent::ServerShutdown { .. }
+            | PlayerEvent::Notice { .. } => {
                 remaining.events.push(event);
             }
         }
@@ -161,38 +266,78 @@ fn handle_server_events(
 
 fn handle_server_event(game_state: &mut GameState, received_at: f32, event: PlayerEvent) {
     match event {
-        PlayerEvent::Pong { sequence_number } => {
+        PlayerEvent::Pong {
+            sequence_number,
+            tick,
+            since_tick,
+        } => {
             if let Some(last_ping) = &mut game_state.last_ping {
                 if sequence_number == last_ping.sequence_number {
-                    game_state.ping_rtt = received_at - last_ping.sent_at;
+                    game_state.server_clock.add_sample(
+                        last_ping.sent_at,
+                        received_at,
+                        tick,
+                        since_tick,
+                    );
+
+                    let ping_rtt = received_at - last_ping.sent_at;
+                    if game_state.ping_rtt > 0.0 {
+                        // Interarrival jitter estimate as in RFC 3550
+                        let deviation = (ping_rtt - game_state.ping_rtt).abs();
+                        game_state.ping_jitter += (deviation - game_state.ping_jitter) / 16.0;
+                    }
+                    game_state.ping_rtt = ping_rtt;
+                    game_state
+                        .ws_commands
+                        .push(GlobalCommand::PongReceived { sequence_number }.into());
                 } else {
                     console_warn!("Unexpected pong sequence number, received: {sequence_number}, expected: {}", last_ping.sequence_number);
                 }
             }
         }
-        PlayerEvent::Initial { .. } => {}
+        PlayerEvent::JoinOptions { .. }
+        | PlayerEvent::JoinRejected { .. }
+        | PlayerEvent::Initial { .. } => {}
+        PlayerEvent::ClientConfigChanged { client_config } => {
+            game_state.client_config = *client_config;
+            game_state.room_tiles = RoomTiles::new(&game_state.room, &game_state.client_config);
+        }
         PlayerEvent::RoomEntered { room } => {
             game_state.room = load_room_map(*room);
+            game_state.room_tiles = RoomTiles::new(&game_state.room, &game_state.client_config);
+            game_state.minimap = Minimap::new(&game_state.room);
+            game_state.camera_focus = None;
             game_state.objects.clear();
             game_state.health_change_labels.clear();
             game_state.attack_markers.clear();
+            game_state.boss_health_bar = None;
+            game_state.death = None;
         }
         PlayerEvent::ObjectAppeared {
             object_id,
             animation_id,
             object_type,
+            name,
+            overlay_animation_ids,
             health,
             max_health,
         } => {
             let object = Object {
                 id: object_id,
                 typ: object_type,
+                name,
                 remote_position: Vector2::new(0.0, 0.0),
                 remote_position_received_at: f32::NEG_INFINITY,
                 local_position: Vector2::new(0.0, 0.0),
+                movement_snapshots: VecDeque::new(),
+                movement_tick: 0,
                 direction: None,
                 look_direction: Direction4::Down,
                 animation_id: animation_id as usize,
+                overlay_animation_ids: overlay_animation_ids
+                    .into_iter()
+                    .map(|id| id as usize)
+                    .collect(),
                 animation: None,
                 velocity: 0.0,
                 health,
@@ -212,8 +357,24 @@ fn handle_server_event(game_state: &mut GameState, received_at: f32, event: Play
             velocity,
             direction,
             look_direction,
+            tick,
         } => {
+            let timestamp = game_state
+                .server_clock
+                .tick_to_local_time(tick)
+                .unwrap_or(received_at);
             if let Some(obj) = game_state.objects.iter_mut().find(|o| o.id == object_id) {
+                // Datagrams can arrive out of order or be repeated by the server
+                let is_stale = tick < obj.movement_tick
+                    || (tick == obj.movement_tick
+                        && obj.id != game_state.self_id
+                        && obj.remote_position == position
+                        && obj.direction == direction);
+                if is_stale {
+                    return;
+                }
+                obj.movement_tick = tick;
+
                 let old_direction = obj.direction;
 
                 obj.remote_position = position;
@@ -221,8 +382,18 @@ fn handle_server_event(game_state: &mut GameState, received_at: f32, event: Play
                 obj.velocity = velocity;
                 obj.direction = direction;
                 obj.look_direction = look_direction;
-                if obj.id == game_state.self_id {
+                if obj.id != game_state.self_id {
+                    obj.movement_snapshots.push_back(MovementSnapshot {
+                        position,
+                        velocity,
+                        direction,
+                        look_direction,
+                        timestamp,
+                    });
+                } else {
                     obj.local_position = position;
+                    game_state.movement_prediction.pending_moves.clear();
+                    game_state.movement_prediction.error = Vector2::new(0.0, 0.0);
 
                     // Continue moving if moved to another room but keys are already pressed
                     // TODO: maybe this should be facilitated by the server?
@@ -234,14 +405,23 @@ fn handle_server_event(game_state: &mut GameState, received_at: f32, event: Play
                 console_warn!("Got ObjectMovementChanged for {object_id:?} but no object");
             }
         }
+        PlayerEvent::MovementAcknowledged {
+            sequence_number,
+            position,
+            direction,
+        } => {
+            reconcile_self_movement(game_state, sequence_number, position, direction);
+        }
         PlayerEvent::ObjectAnimationAction {
             object_id,
             animation_index,
+            tick,
         } => {
+            let started_at = remote_event_time(game_state, tick);
             if let Some(obj) = game_state.objects.iter_mut().find(|o| o.id == object_id) {
                 obj.animation = Some(ObjectAnimation {
                     animation_index,
-                    started_at: game_state.time.now,
+                    started_at,
                 });
             } else {
                 console_warn!("Got ObjectAnimationAction for {object_id:?} but no object");
@@ -251,7 +431,14 @@ fn handle_server_event(game_state: &mut GameState, received_at: f32, event: Play
             object_id,
             change: damage,
             health,
+            damage_type,
+            critical,
         } => {
+            if let Some(boss) = &mut game_state.boss_health_bar {
+                if boss.object_id == object_id {
+                    boss.health = health;
+                }
+            }
             if let Some(obj) = game_state.objects.iter_mut().find(|o| o.id == object_id) {
                 obj.health = health;
 
@@ -266,23 +453,54 @@ fn handle_server_event(game_state: &mut GameState, received_at: f32, event: Play
                     position: obj.local_position - Vector2::new(0.0, obj_height),
                     received_at: game_state.time.now,
                     object_type: obj.typ,
+                    damage_type,
+                    critical,
                 });
+
+                if object_id == game_state.self_id && damage < 0 {
+                    game_state.camera_shake = Some(CameraShake {
+                        started_at: game_state.time.now,
+                    });
+                }
             } else {
                 console_warn!("Got ObjectDamaged for {object_id:?} but no object");
             }
         }
+        PlayerEvent::ObjectEquipmentChanged {
+            object_id,
+            overlay_animation_ids,
+            health,
+            max_health,
+        } => {
+            if let Some(obj) = game_state.objects.iter_mut().find(|o| o.id == object_id) {
+                obj.overlay_animation_ids = overlay_animation_ids
+                    .into_iter()
+                    .map(|id| id as usize)
+                    .collect();
+                obj.health = health;
+                obj.max_health = max_health;
+            }
+        }
+        PlayerEvent::StatsChanged { equipped, stats } => {
+            game_state.equipped = equipped;
+            game_state.stats = Some(stats);
+        }
+        PlayerEvent::BossHealthBar { boss } => {
+            game_state.boss_health_bar = boss;
+        }
         PlayerEvent::AttackTargeted {
             attacker_object_id,
             position,
             radius,
             length,
+            tick,
         } => {
             game_state.attack_markers.push(AttackMarker {
                 attacker_object_id,
                 position,
                 radius,
                 length,
-                received_at: game_state.time.now,
+                started_at: remote_event_time(game_state, tick),
             });
         }
         PlayerEvent::ObjectDisappeared { object_id } => {
@@ -291,21 +509,124 @@ fn handle_server_event(game_state: &mut GameState, received_at: f32, event: Play
                 .attack_markers
                 .retain(|m| m.attacker_object_id != object_id);
         }
+        PlayerEvent::CheckpointActivated { name } => {
+            game_state.notice = Some(Notice {
+                text: format!("Checkpoint: {name}"),
+                received_at: game_state.time.now,
+            });
+        }
+        PlayerEvent::Died { respawn_in } => {
+            game_state.death = Some(Death {
+                respawn_at: game_state.time.now + respawn_in,
+            });
+        }
+        PlayerEvent::ServerShutdown { seconds } => {
+            game_state.shutdown_at = Some(game_state.time.now + seconds);
+        }
+        PlayerEvent::Notice { text } => {
+            game_state.notice = Some(Notice {
+                text,
+                received_at: game_state.time.now,
+            });
+        }
+        PlayerEvent::WorldClock {
+            time_of_day,
+            day_length,
+        } => {
+            game_state.world_clock = Some(WorldClock {
+                time_of_day,
+                day_length,
+                received_at: game_state.time.now,
+            });
+        }
+    }
+}
+
+/// Remote objects are rendered with the interpolation delay, so events that start something on
+/// them are delayed by the same amount to line up with their movement
+fn remote_event_time(game_state: &GameState, tick: u32) -> f32 {
+    match game_state.server_clock.tick_to_local_time(tick) {
+        Some(time) => time + game_state.interpolation_delay,
+        None => game_state.time.now,
     }
 }
 
 fn update_camera(state: &mut AppState) {
     if let Ok(ref mut game_state) = &mut state.game_state {
-        let focus = game_state
+        let target = game_state
             .objects
             .iter()
             .find(|o| o.id == game_state.self_id)
             .map(|o| o.local_position)
             .unwrap_or_default();
-        let map_size = game_state.room.size;
+        let focus = follow_focus(game_state, target);
+        game_state.camera_focus = Some(focus);
+
+        let shake = camera_shake_offset(game_state);
+        let (bounds_min, bounds_max) = camera_bounds(&game_state.room);
         let viewport = state.viewport;
-        game_state.camera = Camera::new(focus, map_size, viewport);
+        game_state.camera = Camera::new(
+            focus,
+            bounds_min,
+            bounds_max,
+            viewport,
+            game_state.camera_zoom,
+            shake,
+        );
+    }
+}
+
+/// The camera only moves once the target leaves the dead zone around its focus, and then eases
+/// towards it instead of jumping
+fn follow_focus(game_state: &GameState, target: Vector2<f32>) -> Vector2<f32> {
+    let Some(focus) = game_state.camera_focus else {
+        return target;
+    };
+    if (target - focus).norm() > CAMERA_SNAP_DISTANCE {
+        return target;
+    }
+    let offset = (target - focus).zip_map(&CAMERA_DEAD_ZONE, |d, zone| d.clamp(-zone, zone));
+    let desired = target - offset;
+    let t = 1.0 - (-game_state.time.frame_delta / CAMERA_SMOOTHING_TIME).exp();
+    focus + (desired - focus) * t
+}
+
+fn camera_shake_offset(game_state: &mut GameState) -> Vector2<f32> {
+    let Some(shake) = game_state.camera_shake else {
+        return Vector2::zeros();
+    };
+    let elapsed = game_state.time.now - shake.started_at;
+    if elapsed >= SCREEN_SHAKE_DURATION {
+        game_state.camera_shake = None;
+        return Vector2::zeros();
     }
+    let amplitude = SCREEN_SHAKE_AMPLITUDE * (1.0 - elapsed / SCREEN_SHAKE_DURATION);
+    let phase = elapsed * SCREEN_SHAKE_FREQUENCY;
+    Vector2::new(phase.sin(), (phase * 1.3).cos()) * amplitude
+}
+
+fn change_zoom(game_state: &mut GameState, change: i32) {
+    game_state.camera_zoom =
+        (game_state.camera_zoom + change).clamp(camera::MIN_ZOOM, camera::MAX_ZOOM);
+}
+
+/// Extends the room past edges with a neighbouring level, so that its border is visible
+fn camera_bounds(room: &Room) -> (Vector2<f32>, Vector2<f32>) {
+    let size: Vector2<f32> = room.size.cast();
+    let mut min = Vector2::zeros();
+    let mut max = size;
+    for neighbour in &room.neighbours {
+        let start: Vector2<f32> = neighbour.offset.cast();
+        let end = start + neighbour.room.size.cast();
+        for axis in 0..2 {
+            if start[axis] >= size[axis] {
+                max[axis] = size[axis] + NEIGHBOUR_VIEW_DISTANCE;
+            } else if end[axis] <= 0.0 {
+                min[axis] = -NEIGHBOUR_VIEW_DISTANCE;
+            }
+        }
+    }
+    (min, max)
 }
 
 fn direction_pressed(game_state: &mut GameState, pressed_direction: Direction4, pressed: bool) {
@@ -314,6 +635,9 @@ fn direction_pressed(game_state: &mut GameState, pressed_direction: Direction4,
 }
 
 fn update_direction_if_needed(game_state: &mut GameState) {
+    if game_state.death.is_some() {
+        return;
+    }
     let new_direction = direction_from_pressed(&game_state.directions_pressed);
 
     if let Some(obj) = game_state
@@ -332,7 +656,7 @@ fn update_direction_if_needed(game_state: &mut GameState) {
                         + game_state.time.frame_delta
                             * obj.velocity
                             * new_direction.to_unit_vector();
-                    room::collision_at(game_state.room.size, &game_state.room.collisions, next_tile)
+                    game_state.room.collision_at(next_tile)
                 } else {
                     false
                 }
@@ -342,12 +666,14 @@ fn update_direction_if_needed(game_state: &mut GameState) {
                 obj.remote_position_received_at = game_state.time.now;
             }
 
-            let command = RoomCommand::Move {
-                position: obj.remote_position,
-                direction: obj.direction,
-                look_direction: obj.look_direction,
-            };
-            game_state.ws_commands.push(command.into());
+            let command = move_command(
+                &mut game_state.movement_prediction,
+                game_state.time.now,
+                obj.remote_position,
+                obj.direction,
+                obj.look_direction,
+            );
+            game_state.ws_commands.push(command);
         }
     } else {
         console_error!("No self object found");
@@ -373,6 +699,9 @@ fn direction_from_pressed(pressed: &[bool; 4]) -> Option<Direction8> {
 }
 
 fn mouse_left_pressed(game_state: &mut GameState, mouse: Vector2<f32>) {
+    if game_state.death.is_some() {
+        return;
+    }
     if let Some(player) = game_state
         .objects
         .iter_mut()
@@ -382,20 +711,23 @@ fn mouse_left_pressed(game_state: &mut GameState, mouse: Vector2<f32>) {
         let look_direction = Direction4::from_vector(to_click);
         if look_direction != player.look_direction {
             player.look_direction = look_direction;
-            game_state.ws_commands.push(
-                RoomCommand::Move {
-                    position: player.local_position,
-                    direction: player.direction,
-                    look_direction,
-                }
-                .into(),
+            let command = move_command(
+                &mut game_state.movement_prediction,
+                game_state.time.now,
+                player.remote_position,
+                player.direction,
+                look_direction,
             );
+            game_state.ws_commands.push(command);
         }
     }
     start_attack(game_state);
 }
 
 fn start_attack(game_state: &mut GameState) {
+    if game_state.death.is_some() {
+        return;
+    }
     if let Some(obj) = game_state
         .objects
         .iter_mut()
@@ -413,34 +745,66 @@ fn start_attack(game_state: &mut GameState) {
     }
 }
 
+/// Equips the item at the index of the carried equipment, or takes it off if already equipped
+fn toggle_equipment(game_state: &mut GameState, index: usize) {
+    if game_state.death.is_some() {
+        return;
+    }
+    let Some(item) = game_state.client_config.equipment.get(index) else {
+        return;
+    };
+    let command = if game_state.equipped.contains(&item.id) {
+        RoomCommand::Unequip { slot: item.slot }
+    } else {
+        RoomCommand::Equip {
+            item_id: item.id.clone(),
+        }
+    };
+    game_state.ws_commands.push(command.into());
+}
+
 fn update_self_movement(game_state: &mut GameState) {
     let room = &game_state.room;
+    let prediction = &mut game_state.movement_prediction;
 
-    // TODO: for self probably remote_position = local_position, make that more intentional
+    // For self, remote_position is the predicted position and local_position is where we render
+    // it, lagging behind while a reconciliation error is being smoothed out
     if let Some(obj) = game_state
         .objects
         .iter_mut()
         .find(|o| o.id == game_state.self_id)
     {
+        // Matches the velocity the server derives from the terrain
+        let config = &game_state.client_config;
+        let terrain = room::terrain_at(room.size, &room.terrain, obj.remote_position);
+        let velocity = game_state
+            .stats
+            .map_or(config.player_velocity, |stats| stats.velocity);
+        obj.velocity =
+            velocity * terrain.player_velocity_factor(config.slow_terrain_velocity_factor);
+
         if let Some(direction) = obj.direction {
             let delta = game_state.time.frame_delta * obj.velocity * direction.to_unit_vector();
             let target = obj.remote_position + delta;
 
-            if room::collision_at(room.size, &room.collisions, target) {
+            if room.collision_at(target) {
                 obj.direction = None;
-                game_state.ws_commands.push(
-                    RoomCommand::Move {
-                        position: obj.remote_position,
-                        direction: None,
-                        look_direction: obj.look_direction,
-                    }
-                    .into(),
+                let command = move_command(
+                    prediction,
+                    game_state.time.now,
+                    obj.remote_position,
+                    None,
+                    obj.look_direction,
                 );
+                game_state.ws_commands.push(command);
             } else {
                 obj.remote_position = target;
-                obj.local_position = target;
             }
         }
+
+        prediction.error *= (-game_state.time.frame_delta / PREDICTION_SMOOTHING_TIME).exp();
+        obj.local_position = obj.remote_position + prediction.error;
+
         if !is_animation_running(obj, &game_state.client_config, game_state.time.now) {
             obj.animation = None;
         }
@@ -449,17 +813,91 @@ fn update_self_movement(game_state: &mut GameState) {
     }
 }
 
+fn move_command(
+    prediction: &mut MovementPrediction,
+    now: f32,
+    position: Vector2<f32>,
+    direction: Option<Direction8>,
+    look_direction: Direction4,
+) -> PlayerCommand {
+    let sequence_number = prediction.next_sequence_number;
+    prediction.next_sequence_number = sequence_number.wrapping_add(1);
+    prediction.pending_moves.push(PendingMove {
+        sequence_number,
+        direction,
+        sent_at: now,
+    });
+    RoomCommand::Move {
+        sequence_number,
+        position,
+        direction,
+        look_direction,
+    }
+    .into()
+}
+
+fn reconcile_self_movement(
+    game_state: &mut GameState,
+    sequence_number: u32,
+    position: Vector2<f32>,
+    direction: Option<Direction8>,
+) {
+    let now = game_state.time.now;
+    let prediction = &mut game_state.movement_prediction;
+
+    let acked_at = prediction
+        .pending_moves
+        .iter()
+        .find(|pending| pending.sequence_number == sequence_number)
+        .map(|pending| pending.sent_at)
+        .unwrap_or(now);
+    prediction
+        .pending_moves
+        // The numbers wrap around, so later ones are those a positive distance ahead
+        .retain(|pending| pending.sequence_number.wrapping_sub(sequence_number) as i32 > 0);
+
+    if let Some(obj) = game_state
+        .objects
+        .iter_mut()
+        .find(|o| o.id == game_state.self_id)
+    {
+        // Replay the moves the server has not processed yet on top of the acknowledged state
+        let mut predicted_position = position;
+        let mut predicted_direction = direction;
+        let mut predicted_at = acked_at;
+        for pending in &prediction.pending_moves {
+            if let Some(dir) = predicted_direction {
+                predicted_position +=
+                    obj.velocity * (pending.sent_at - predicted_at) * dir.to_unit_vector();
+            }
+            predicted_direction = pending.direction;
+            predicted_at = pending.sent_at;
+        }
+        if let Some(dir) = predicted_direction {
+            predicted_position += obj.velocity * (now - predicted_at) * dir.to_unit_vector();
+        }
+
+        let error = obj.local_position - predicted_position;
+        prediction.error = if error.norm() < PREDICTION_MAX_SMOOTHED_ERROR {
+            error
+        } else {
+            Vector2::new(0.0, 0.0)
+        };
+        obj.remote_position = predicted_position;
+        obj.direction = predicted_direction;
+    } else {
+        console_error!("No self object found");
+    }
+}
+
 fn update_remote_movement(game_state: &mut GameState) {
+    update_interpolation_delay(game_state);
+
+    // Remote objects are rendered in the past so that there is usually a snapshot on both sides
+    let render_time = game_state.time.now - game_state.interpolation_delay;
     for obj in game_state.objects.iter_mut() {
         if obj.id != game_state.self_id {
-            obj.local_position = match obj.direction {
-                Some(dir) => {
-                    let mov_distance =
-                        obj.velocity * (game_state.time.now - obj.remote_position_received_at);
-                    obj.remote_position + mov_distance * dir.to_unit_vector()
-                }
-                None => obj.remote_position,
-            };
+            interpolate_remote_movement(obj, render_time);
         }
         if !is_animation_running(obj, &game_state.client_config, game_state.time.now) {
             obj.animation = None;
@@ -467,6 +905,40 @@ fn update_remote_movement(game_state: &mut GameState) {
     }
 }
 
+fn update_interpolation_delay(game_state: &mut GameState) {
+    let target_delay =
+        player_event::interpolation_delay(game_state.ping_rtt, game_state.ping_jitter);
+    let max_change = INTERPOLATION_DELAY_ADJUST_RATE * game_state.time.frame_delta;
+    game_state.interpolation_delay +=
+        (target_delay - game_state.interpolation_delay).clamp(-max_change, max_change);
+}
+
+fn interpolate_remote_movement(obj: &mut Object, render_time: f32) {
+    let snapshots = &mut obj.movement_snapshots;
+    while snapshots.len() >= 2 && snapshots[1].timestamp <= render_time {
+        snapshots.pop_front();
+    }
+
+    if let Some(from) = snapshots.front() {
+        obj.local_position = match snapshots.get(1) {
+            Some(to) if from.timestamp <= render_time => {
+                let t = (render_time - from.timestamp) / (to.timestamp - from.timestamp);
+                from.position.lerp(&to.position, t)
+            }
+            _ => match from.direction {
+                Some(dir) if from.timestamp <= render_time => {
+                    let mov_distance = from.velocity * (render_time - from.timestamp);
+                    from.position + mov_distance * dir.to_unit_vector()
+                }
+                _ => from.position,
+            },
+        };
+        obj.velocity = from.velocity;
+        obj.direction = from.direction;
+        obj.look_direction = from.look_direction;
+    }
+}
+
 fn is_animation_running(object: &Object, client_config: &ClientConfig, now: f32) -> bool {
     if let Some(animation) = &object.animation {
         let runtime = now - animation.started_at;
@@ -502,6 +974,15 @@ fn add_ping_if_needed(gs: &mut GameState) {
 fn load_room_map(room_sync: RoomSync) -> Room {
     let bg_dense_layers = room_sync.bg_dense_layers.iter().map(rle::decode).collect();
     let collisions = rle::decode(&room_sync.collisions);
+    let terrain = rle::decode(&room_sync.terrain);
+    let neighbours = room_sync
+        .neighbours
+        .into_iter()
+        .map(|neighbour| Neighbour {
+            offset: neighbour.offset,
+            room: load_room_map(neighbour.room),
+        })
+        .collect();
     Room {
         room_id: room_sync.room_id,
         size: room_sync.size,
@@ -509,5 +990,9 @@ fn load_room_map(room_sync: RoomSync) -> Room {
         bg_sparse_layer: room_sync.bg_sparse_layer,
         fg_sparse_layer: room_sync.fg_sparse_layer,
         collisions,
+        terrain,
+        neighbours,
+        portals: room_sync.portals,
+        lights: room_sync.lights,
     }
 }
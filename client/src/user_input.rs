@@ -1,7 +1,7 @@
 use std::{cell::RefCell, rc::Rc};
 
 use wasm_bindgen::{prelude::*, JsValue};
-use web_sys::{Document, KeyboardEvent, MouseEvent};
+use web_sys::{Document, HtmlCanvasElement, KeyboardEvent, MouseEvent, WheelEvent};
 
 use crate::app_event::AppEvent;
 
@@ -34,9 +34,16 @@ pub fn setup_handlers(
     let mousedown_listener = {
         let events = events.clone();
         Closure::<dyn FnMut(_)>::new(move |event: MouseEvent| {
+            // Leaves clicks on the join form alone
+            if !event
+                .target()
+                .is_some_and(|target| target.has_type::<HtmlCanvasElement>())
+            {
+                return;
+            }
             let app_event = AppEvent::MouseDown {
-                x: event.client_x(),
-                y: event.client_y(),
+                x: event.client_x() as i32,
+                y: event.client_y() as i32,
                 button: event.button().into(),
             };
             (*events).borrow_mut().push(app_event);
@@ -46,5 +53,17 @@ pub fn setup_handlers(
     };
     document.add_event_listener_with_callback("mousedown", mousedown_listener.unchecked_ref())?;
 
+    let wheel_listener = {
+        let events = events.clone();
+        Closure::<dyn FnMut(_)>::new(move |event: WheelEvent| {
+            let app_event = AppEvent::MouseWheel {
+                delta_y: event.delta_y(),
+            };
+            (*events).borrow_mut().push(app_event);
+        })
+        .into_js_value()
+    };
+    document.add_event_listener_with_callback("wheel", wheel_listener.unchecked_ref())?;
+
     Ok(())
 }
@@ -83,16 +83,15 @@ impl VertexBuffer {
 
 pub struct TileVertexBuffer {
     pub vertex_buffer: VertexBuffer,
-    pub tile_size_on_texture: Vector2<f32>,
-    pub texture_columns: u32,
+    /// Indexed by texture index
+    pub texture_tile_counts: Vec<Vector2<u32>>,
 }
 
 impl TileVertexBuffer {
-    pub fn new(tile_counts: Vector2<u32>) -> Self {
+    pub fn new(texture_tile_counts: Vec<Vector2<u32>>) -> Self {
         Self {
             vertex_buffer: VertexBuffer::new(),
-            tile_size_on_texture: Vector2::new(1.0, 1.0).component_div(&tile_counts.cast()),
-            texture_columns: tile_counts.x,
+            texture_tile_counts,
         }
     }
 
@@ -107,14 +106,16 @@ impl TileVertexBuffer {
         tile_index: u32,
         texture_index: u32,
     ) {
-        let u = (tile_index % self.texture_columns) as f32;
-        let v = (tile_index / self.texture_columns) as f32;
-        let texture_top_left = Vector2::new(
-            u * self.tile_size_on_texture.x,
-            v * self.tile_size_on_texture.y,
-        );
+        // Textures may still be loading after the room changed
+        let Some(tile_counts) = self.texture_tile_counts.get(texture_index as usize) else {
+            return;
+        };
+        let tile_size_on_texture = Vector2::new(1.0, 1.0).component_div(&tile_counts.cast());
+        let u = (tile_index % tile_counts.x) as f32;
+        let v = (tile_index / tile_counts.x) as f32;
+        let texture_top_left = Vector2::new(u * tile_size_on_texture.x, v * tile_size_on_texture.y);
         let tile_extent = tile_extent.cast();
-        let texture_extent = self.tile_size_on_texture.component_mul(&tile_extent);
+        let texture_extent = tile_size_on_texture.component_mul(&tile_extent);
         self.vertex_buffer.push_quad(
             top_left,
             tile_extent,
@@ -14,6 +14,7 @@ pub const ATTRIB_LOC_TEXTURE_INDEX: u32 = 3;
 pub struct VertexBufferRenderer {
     pub vao: WebGlVertexArrayObject,
     pub vbo: WebGlBuffer,
+    vertex_count: i32,
 }
 
 impl VertexBufferRenderer {
@@ -88,20 +89,34 @@ impl VertexBufferRenderer {
             gl.enable_vertex_attrib_array(ATTRIB_LOC_TEXTURE_INDEX);
         }
 
-        Ok(Self { vao, vbo })
+        Ok(Self {
+            vao,
+            vbo,
+            vertex_count: 0,
+        })
     }
 
     pub fn render_triangles(&mut self, vertex_buffer: &VertexBuffer, gl: &GL) {
-        self.prepare_buffer(vertex_buffer, gl);
-        gl.draw_arrays(GL::TRIANGLES, 0, vertex_buffer.vertices.len() as i32);
+        self.prepare_buffer(vertex_buffer, gl, GL::DYNAMIC_DRAW);
+        gl.draw_arrays(GL::TRIANGLES, 0, self.vertex_count);
     }
 
     pub fn render_lines(&mut self, vertex_buffer: &VertexBuffer, gl: &GL) {
-        self.prepare_buffer(vertex_buffer, gl);
-        gl.draw_arrays(GL::LINES, 0, vertex_buffer.vertices.len() as i32);
+        self.prepare_buffer(vertex_buffer, gl, GL::DYNAMIC_DRAW);
+        gl.draw_arrays(GL::LINES, 0, self.vertex_count);
     }
 
-    fn prepare_buffer(&self, vertex_buffer: &VertexBuffer, gl: &GL) {
+    /// Keeps the vertices on the GPU, to be drawn with `render_uploaded_triangles` over many frames
+    pub fn upload(&mut self, vertex_buffer: &VertexBuffer, gl: &GL) {
+        self.prepare_buffer(vertex_buffer, gl, GL::STATIC_DRAW);
+    }
+
+    pub fn render_uploaded_triangles(&self, gl: &GL) {
+        gl.bind_vertex_array(Some(&self.vao));
+        gl.draw_arrays(GL::TRIANGLES, 0, self.vertex_count);
+    }
+
+    fn prepare_buffer(&mut self, vertex_buffer: &VertexBuffer, gl: &GL, usage: u32) {
         gl.bind_vertex_array(Some(&self.vao));
         gl.bind_buffer(GL::ARRAY_BUFFER, Some(&self.vbo));
 
@@ -109,7 +124,8 @@ impl VertexBufferRenderer {
         unsafe {
             let byte_slice = vertex_buffer.byte_slice();
             let buffer_view = js_sys::Uint8Array::view(byte_slice);
-            gl.buffer_data_with_array_buffer_view(GL::ARRAY_BUFFER, &buffer_view, GL::DYNAMIC_DRAW);
+            gl.buffer_data_with_array_buffer_view(GL::ARRAY_BUFFER, &buffer_view, usage);
         }
+        self.vertex_count = vertex_buffer.vertices.len() as i32;
     }
 }
@@ -0,0 +1,153 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use js_sys::Uint8Array;
+use mmo_common::player_command::PlayerHandshake;
+use mmo_common::transport::{self, WebTransportInfo, WEBTRANSPORT_PATH};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{
+    ReadableStream, ReadableStreamDefaultReader, ReadableStreamReadResult, WebTransport,
+    WebTransportHash, WebTransportOptions, WritableStreamDefaultWriter,
+};
+
+use crate::app_event::AppEvent;
+use crate::connection;
+use crate::console_error;
+use crate::console_warn;
+use crate::fetch;
+use crate::metrics::Metrics;
+
+static CONNECT_TIMEOUT_MS: i32 = 3000;
+
+pub struct WtConnection {
+    writer: WritableStreamDefaultWriter,
+}
+
+/// Returns `None` if either the browser or the server does not support WebTransport.
+pub async fn connect(
+    events: Rc<RefCell<Vec<AppEvent>>>,
+    metrics: Rc<RefCell<Metrics>>,
+) -> Result<Option<WtConnection>, JsValue> {
+    let window = web_sys::window().expect("No window");
+    if !js_sys::Reflect::has(&window, &"WebTransport".into())? {
+        return Ok(None);
+    }
+    let info: Option<WebTransportInfo> = fetch::fetch_json(&window, "/api/webtransport").await?;
+    let Some(info) = info else {
+        return Ok(None);
+    };
+
+    let hostname = window.location().hostname()?;
+    let url = format!("https://{hostname}:{}{WEBTRANSPORT_PATH}", info.port);
+    let options = WebTransportOptions::new();
+    if let Some(certificate_hash) = &info.certificate_hash {
+        let hash = WebTransportHash::new();
+        hash.set_algorithm("sha-256");
+        hash.set_value_u8_array(&Uint8Array::from(certificate_hash.as_slice()));
+        options.set_server_certificate_hashes(&[hash]);
+    }
+    let transport = WebTransport::new_with_options(&url, &options)?;
+
+    // A blocked UDP port would otherwise leave the client waiting for a long time
+    let connected = Rc::new(Cell::new(false));
+    let on_timeout = {
+        let transport = transport.clone();
+        let connected = connected.clone();
+        Closure::once_into_js(move || {
+            if !connected.get() {
+                transport.close();
+            }
+        })
+    };
+    window.set_timeout_with_callback_and_timeout_and_arguments_0(
+        on_timeout.unchecked_ref(),
+        CONNECT_TIMEOUT_MS,
+    )?;
+    JsFuture::from(transport.ready()).await?;
+    connected.set(true);
+
+    let stream = JsFuture::from(transport.create_bidirectional_stream()).await?;
+    let writer = stream.writable().get_writer()?;
+    let connection = WtConnection { writer };
+
+    let handshake = postcard::to_stdvec(&PlayerHandshake::new()).map_err(|e| e.to_string())?;
+    send(&connection, &handshake)?;
+    (*events).borrow_mut().push(AppEvent::ServerConnected);
+
+    wasm_bindgen_futures::spawn_local(read_stream(
+        stream.readable().into(),
+        events.clone(),
+        metrics.clone(),
+    ));
+    wasm_bindgen_futures::spawn_local(read_datagrams(
+        transport.datagrams().readable(),
+        events,
+        metrics,
+    ));
+
+    Ok(Some(connection))
+}
+
+pub fn send(connection: &WtConnection, bytes: &[u8]) -> Result<(), JsValue> {
+    let frame = transport::encode_frame(bytes);
+    let write = connection
+        .writer
+        .write_with_chunk(&Uint8Array::from(frame.as_slice()));
+    // The disconnect itself is reported by the reading side closing
+    wasm_bindgen_futures::spawn_local(async move {
+        if let Err(err) = JsFuture::from(write).await {
+            console_warn!("WebTransport write failed: {err:?}");
+        }
+    });
+    Ok(())
+}
+
+async fn read_stream(
+    stream: ReadableStream,
+    events: Rc<RefCell<Vec<AppEvent>>>,
+    metrics: Rc<RefCell<Metrics>>,
+) {
+    let reader: ReadableStreamDefaultReader = stream.get_reader().unchecked_into();
+    let mut buffer = vec![];
+    loop {
+        match read_chunk(&reader).await {
+            Ok(Some(chunk)) => {
+                buffer.extend_from_slice(&chunk);
+                while let Some(frame) = transport::decode_frame(&mut buffer) {
+                    connection::push_message(&events, &metrics, &frame);
+                }
+            }
+            Ok(None) => {
+                console_error!("WebTransport disconnected");
+                break;
+            }
+            Err(err) => {
+                console_error!("WebTransport error: {err:?}");
+                break;
+            }
+        }
+    }
+    (*events).borrow_mut().push(AppEvent::ServerDisconnected);
+}
+
+async fn read_datagrams(
+    stream: ReadableStream,
+    events: Rc<RefCell<Vec<AppEvent>>>,
+    metrics: Rc<RefCell<Metrics>>,
+) {
+    let reader: ReadableStreamDefaultReader = stream.get_reader().unchecked_into();
+    // Disconnects are reported by the reliable stream
+    while let Ok(Some(datagram)) = read_chunk(&reader).await {
+        connection::push_message(&events, &metrics, &datagram);
+    }
+}
+
+async fn read_chunk(reader: &ReadableStreamDefaultReader) -> Result<Option<Vec<u8>>, JsValue> {
+    let result: ReadableStreamReadResult = JsFuture::from(reader.read()).await?.unchecked_into();
+    if result.get_done().unwrap_or(false) {
+        return Ok(None);
+    }
+    Ok(Some(Uint8Array::new(&result.get_value()).to_vec()))
+}
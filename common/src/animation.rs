@@ -2,6 +2,7 @@ use nalgebra::Vector2;
 use serde::{Deserialize, Serialize};
 
 use crate::object::Direction4;
+use crate::room::TileIndex;
 
 #[derive(Debug, Clone, Copy, Deserialize, Serialize)]
 pub struct SpriteIndex(pub u16);
@@ -33,20 +34,31 @@ impl DirectionalAnimation {
             Direction4::Left => &self.left,
             Direction4::Up => &self.up,
         };
-        if self.total_length == 0.0 {
-            frames.first().copied()
-        } else {
-            let rel_time = time % self.total_length;
-            let i = self
-                .start_times
-                .iter()
-                .take_while(|t| **t <= rel_time)
-                .count();
-            if i > 0 {
-                frames.get(i - 1).copied()
-            } else {
-                None
-            }
-        }
+        frame_index(self.total_length, &self.start_times, time).and_then(|i| frames.get(i).copied())
+    }
+}
+
+/// Map tiles showing the first frame cycle through all frames
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TileAnimation {
+    pub total_length: f32,
+    pub start_times: Vec<f32>,
+    pub frames: Vec<TileIndex>,
+}
+
+impl TileAnimation {
+    pub fn get(&self, time: f32) -> Option<TileIndex> {
+        frame_index(self.total_length, &self.start_times, time)
+            .and_then(|i| self.frames.get(i).copied())
+    }
+}
+
+fn frame_index(total_length: f32, start_times: &[f32], time: f32) -> Option<usize> {
+    if total_length == 0.0 {
+        Some(0)
+    } else {
+        let rel_time = time % total_length;
+        let i = start_times.iter().take_while(|t| **t <= rel_time).count();
+        i.checked_sub(1)
     }
 }
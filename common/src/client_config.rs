@@ -1,18 +1,31 @@
 use serde::{Deserialize, Serialize};
 
-use crate::animation::AnimationSet;
+use crate::{
+    animation::{AnimationSet, TileAnimation},
+    equipment::EquipmentItem,
+    room::WorldMapRoom,
+};
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ClientConfig {
     pub server_git_sha: String,
     pub asset_paths: AssetPaths,
     pub animations: Vec<AnimationSet>,
+    pub tile_animations: Vec<TileAnimation>,
     pub player_attack_animation_index: u8,
+    /// Without equipment
+    pub player_velocity: f32,
+    pub slow_terrain_velocity_factor: f32,
+    pub tick_interval: f32,
+    pub world_map: Vec<WorldMapRoom>,
+    /// Equipment every player carries
+    pub equipment: Vec<EquipmentItem>,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct AssetPaths {
-    pub tileset: String,
+    /// Indexed by `TilesetId`
+    pub tilesets: Vec<String>,
     pub charset: String,
     pub font: String,
     pub font_meta: String,
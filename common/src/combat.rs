@@ -0,0 +1,36 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum DamageType {
+    #[default]
+    Physical,
+    Fire,
+    Poison,
+}
+
+/// The fraction of damage of each type that is ignored, negative values take extra damage
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Resistances {
+    pub physical: f32,
+    pub fire: f32,
+    pub poison: f32,
+}
+
+impl Resistances {
+    pub fn get(&self, damage_type: DamageType) -> f32 {
+        match damage_type {
+            DamageType::Physical => self.physical,
+            DamageType::Fire => self.fire,
+            DamageType::Poison => self.poison,
+        }
+    }
+
+    pub fn add(&self, other: &Resistances) -> Self {
+        Self {
+            physical: self.physical + other.physical,
+            fire: self.fire + other.fire,
+            poison: self.poison + other.poison,
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use serde::{Deserialize, Serialize};
+
+use crate::combat::{DamageType, Resistances};
+
+/// Overlays are drawn in this order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+pub enum EquipmentSlot {
+    Armour,
+    Weapon,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct EquipmentItem {
+    pub id: String,
+    pub name: String,
+    pub slot: EquipmentSlot,
+    pub modifiers: StatModifiers,
+}
+
+/// Added to the base stats of a player while the item is equipped
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct StatModifiers {
+    pub max_health: i32,
+    pub damage: i32,
+    pub attack_range: f32,
+    pub velocity: f32,
+    pub defence: i32,
+    pub resistances: Resistances,
+    pub critical_chance: f32,
+    /// Replaces the damage type of the player's attacks
+    pub damage_type: Option<DamageType>,
+}
+
+/// Combat stats of a player with their equipment applied
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct PlayerStats {
+    pub max_health: i32,
+    pub damage: i32,
+    pub attack_range: f32,
+    pub velocity: f32,
+    /// Subtracted from the damage of every hit taken
+    pub defence: i32,
+    pub resistances: Resistances,
+    /// From 0.0 to 1.0
+    pub critical_chance: f32,
+    pub damage_type: DamageType,
+}
+
+impl PlayerStats {
+    pub fn with_modifiers(self, modifiers: &StatModifiers) -> Self {
+        Self {
+            max_health: (self.max_health + modifiers.max_health).max(1),
+            damage: (self.damage + modifiers.damage).max(0),
+            attack_range: (self.attack_range + modifiers.attack_range).max(0.0),
+            velocity: (self.velocity + modifiers.velocity).max(0.0),
+            defence: (self.defence + modifiers.defence).max(0),
+            resistances: self.resistances.add(&modifiers.resistances),
+            critical_chance: (self.critical_chance + modifiers.critical_chance).clamp(0.0, 1.0),
+            damage_type: modifiers.damage_type.unwrap_or(self.damage_type),
+        }
+    }
+}
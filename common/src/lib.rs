@@ -1,7 +1,10 @@
 pub mod animation;
 pub mod client_config;
+pub mod combat;
+pub mod equipment;
 pub mod object;
 pub mod player_command;
 pub mod player_event;
 pub mod rle;
 pub mod room;
+pub mod transport;
@@ -2,12 +2,16 @@ use nalgebra::Vector2;
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    equipment::EquipmentSlot,
     object::{Direction4, Direction8},
     room::RoomId,
 };
 
 const HANDSHAKE_MAGIC: [u8; 8] = [111, 197, 49, 147, 243, 227, 34, 189];
 
+pub static MIN_PLAYER_NAME_LENGTH: usize = 3;
+pub static MAX_PLAYER_NAME_LENGTH: usize = 16;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PlayerHandshake {
     pub magic: [u8; 8],
@@ -25,6 +29,12 @@ impl PlayerHandshake {
     }
 }
 
+impl Default for PlayerHandshake {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PlayerCommandEnvelope {
     pub room_id: RoomId,
@@ -39,17 +49,53 @@ pub enum PlayerCommand {
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub enum GlobalCommand {
-    Ping { sequence_number: u32 },
+    Ping {
+        sequence_number: u32,
+    },
+    /// Sent as soon as the `Pong` arrives, so the server can measure the round trip time itself
+    PongReceived {
+        sequence_number: u32,
+    },
+    /// Sent once after the handshake, the player enters the world when it's accepted
+    Join {
+        name: String,
+        /// Index into the appearances offered in `JoinOptions`
+        appearance: u32,
+    },
+}
+
+/// The server additionally checks that the name is unused and not offensive
+pub fn check_player_name(name: &str) -> Result<(), String> {
+    let length = name.chars().count();
+    if length < MIN_PLAYER_NAME_LENGTH || length > MAX_PLAYER_NAME_LENGTH {
+        return Err(format!(
+            "Name must be {MIN_PLAYER_NAME_LENGTH} to {MAX_PLAYER_NAME_LENGTH} characters long"
+        ));
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return Err("Name may only contain letters, digits, _ and -".to_string());
+    }
+    Ok(())
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub enum RoomCommand {
     Move {
+        sequence_number: u32,
         position: Vector2<f32>,
         direction: Option<Direction8>,
         look_direction: Direction4,
     },
     Attack,
+    Equip {
+        item_id: String,
+    },
+    Unequip {
+        slot: EquipmentSlot,
+    },
 }
 
 impl From<GlobalCommand> for PlayerCommand {
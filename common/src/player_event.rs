@@ -3,10 +3,24 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     client_config::ClientConfig,
+    combat::DamageType,
+    equipment::PlayerStats,
     object::{Direction4, Direction8, ObjectId, ObjectType},
     room::RoomSync,
 };
 
+pub static INTERPOLATION_MIN_DELAY: f32 = 0.1;
+static INTERPOLATION_MAX_DELAY: f32 = 0.5;
+static INTERPOLATION_JITTER_FACTOR: f32 = 2.0;
+
+/// How far behind the server clients render remote objects, in seconds. The client eases
+/// towards it, the server uses it to rewind mobs to where an attacker saw them.
+pub fn interpolation_delay(ping_rtt: f32, ping_jitter: f32) -> f32 {
+    // Snapshots are timestamped on the server, so they arrive about half a round trip late
+    (INTERPOLATION_MIN_DELAY + ping_rtt / 2.0 + INTERPOLATION_JITTER_FACTOR * ping_jitter)
+        .min(INTERPOLATION_MAX_DELAY)
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PlayerEventEnvelope<T>
 where
@@ -17,12 +31,25 @@ where
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum PlayerEvent {
+    /// Sent after the handshake, before the player has joined
+    JoinOptions {
+        /// Names of the player animations to choose from
+        appearances: Vec<String>,
+    },
+    JoinRejected {
+        reason: String,
+    },
     Initial {
         self_id: ObjectId,
         client_config: Box<ClientConfig>,
     },
+    ClientConfigChanged {
+        client_config: Box<ClientConfig>,
+    },
     Pong {
         sequence_number: u32,
+        tick: u32,
+        since_tick: f32,
     },
     RoomEntered {
         room: Box<RoomSync>,
@@ -30,7 +57,10 @@ pub enum PlayerEvent {
     ObjectAppeared {
         object_id: ObjectId,
         object_type: ObjectType,
+        name: String,
         animation_id: u32,
+        /// Equipment drawn over the animation, using the same frames
+        overlay_animation_ids: Vec<u32>,
         health: i32,
         max_health: i32,
     },
@@ -43,22 +73,80 @@ pub enum PlayerEvent {
         velocity: f32,
         direction: Option<Direction8>,
         look_direction: Direction4,
+        tick: u32,
+    },
+    MovementAcknowledged {
+        sequence_number: u32,
+        position: Vector2<f32>,
+        direction: Option<Direction8>,
     },
     ObjectAnimationAction {
         object_id: ObjectId,
         animation_index: u8,
+        tick: u32,
     },
     ObjectHealthChanged {
         object_id: ObjectId,
         change: i32,
         health: i32,
+        /// None when healing
+        damage_type: Option<DamageType>,
+        critical: bool,
+    },
+    ObjectEquipmentChanged {
+        object_id: ObjectId,
+        overlay_animation_ids: Vec<u32>,
+        health: i32,
+        max_health: i32,
+    },
+    /// Sent to the player when entering a room and whenever their equipment changes
+    StatsChanged {
+        /// Item ids
+        equipped: Vec<String>,
+        stats: PlayerStats,
     },
     AttackTargeted {
         attacker_object_id: ObjectId,
         position: Vector2<f32>,
         radius: f32,
         length: f32,
+        tick: u32,
+    },
+    CheckpointActivated {
+        name: String,
+    },
+    Died {
+        respawn_in: f32,
+    },
+    /// Sent to the whole room when a boss engages, changes phase or enrages, and with `None`
+    /// when it is defeated or gives up
+    BossHealthBar {
+        boss: Option<BossHealthBar>,
     },
+    WorldClock {
+        /// From 0.0 to 1.0, starting at midnight
+        time_of_day: f32,
+        /// In seconds
+        day_length: f32,
+    },
+    /// The server disconnects everyone once the countdown is over
+    ServerShutdown {
+        seconds: f32,
+    },
+    /// Announcement from the server operators
+    Notice {
+        text: String,
+    },
+}
+
+/// Health changes come as `ObjectHealthChanged` of the boss object
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BossHealthBar {
+    pub object_id: ObjectId,
+    pub name: String,
+    pub health: i32,
+    pub max_health: i32,
+    pub enraged: bool,
 }
 
 impl AsRef<PlayerEvent> for PlayerEvent {
@@ -1,10 +1,16 @@
 use std::num::NonZeroU16;
 
-use nalgebra::Vector2;
+use nalgebra::{Vector2, Vector3};
 use serde::{Deserialize, Serialize};
 
 use crate::rle::Rle;
 
+/// Limited by the texture units the client binds tilesets to
+pub static MAX_TILESETS: usize = 7;
+
+/// Size of a tile in the tilesets and the charset
+pub static PIXELS_PER_TILE: u32 = 16;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub struct RoomId(pub u64);
 
@@ -16,9 +22,42 @@ pub struct RoomSync {
     pub bg_sparse_layer: Vec<(Vector2<u32>, TileIndex)>,
     pub fg_sparse_layer: Vec<ForegroundTile>,
     pub collisions: Rle<bool>,
+    pub terrain: Rle<Terrain>,
+    pub neighbours: Vec<NeighbourSync>,
+    pub portals: Vec<Vector2<u32>>,
+    pub lights: Vec<Light>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct Light {
+    pub position: Vector2<f32>,
+    /// In tiles, the light fades out towards it
+    pub radius: f32,
+    pub color: Vector3<u8>,
+}
+
+/// A level bordering the room, which is entered by walking over the edge
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct NeighbourSync {
+    /// Origin of the neighbour in room coordinates
+    pub offset: Vector2<i32>,
+    /// Without neighbours of its own
+    pub room: RoomSync,
+}
+
+/// A room as laid out in the world, for the world map
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct WorldMapRoom {
+    pub room_id: RoomId,
+    pub name: String,
+    /// Top left corner in world tiles
+    pub position: Vector2<i32>,
+    pub size: Vector2<u32>,
+    /// Rooms reachable through a portal or across an edge
+    pub connections: Vec<RoomId>,
 }
 
-#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
 pub struct ForegroundTile {
     pub position: Vector2<u32>,
     pub height: u32,
@@ -26,21 +65,75 @@ pub struct ForegroundTile {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
-pub struct TileIndex(pub Option<NonZeroU16>);
+pub struct TilesetId(pub u8);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct TileIndex {
+    pub tileset: TilesetId,
+    pub index: Option<NonZeroU16>,
+}
 
 impl TileIndex {
     pub fn empty() -> Self {
-        Self(None)
+        Self {
+            tileset: TilesetId(0),
+            index: None,
+        }
     }
 }
 
+/// Tile properties painted with IntGrid layers, in addition to collisions
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Terrain {
+    /// Mobs can't enter water, players wade through it slowly
+    pub water: bool,
+    pub slow: bool,
+    pub no_pvp: bool,
+}
+
+impl Terrain {
+    pub fn player_velocity_factor(self, slow_factor: f32) -> f32 {
+        if self.water || self.slow {
+            slow_factor
+        } else {
+            1.0
+        }
+    }
+
+    pub fn mob_velocity_factor(self, slow_factor: f32) -> f32 {
+        if self.slow {
+            slow_factor
+        } else {
+            1.0
+        }
+    }
+}
+
+pub fn in_bounds(size: Vector2<u32>, position: Vector2<f32>) -> bool {
+    position.x >= 0.0
+        && position.y >= 0.0
+        && (position.x as u32) < size.x
+        && (position.y as u32) < size.y
+}
+
 pub fn collision_at(size: Vector2<u32>, collisions: &[bool], position: Vector2<f32>) -> bool {
     let x = position.x as u32;
     let y = position.y as u32;
-    if position.x < 0.0 || position.y < 0.0 || x >= size.x || y >= size.y {
+    if !in_bounds(size, position) {
         true
     } else {
         let index = y * size.x + x;
         collisions[index as usize]
     }
 }
+
+pub fn terrain_at(size: Vector2<u32>, terrain: &[Terrain], position: Vector2<f32>) -> Terrain {
+    let x = position.x as u32;
+    let y = position.y as u32;
+    if !in_bounds(size, position) {
+        Terrain::default()
+    } else {
+        let index = y * size.x + x;
+        terrain[index as usize]
+    }
+}
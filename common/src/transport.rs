@@ -0,0 +1,33 @@
+use serde::{Deserialize, Serialize};
+
+pub static WEBTRANSPORT_PATH: &str = "/api/wt";
+
+pub static MAX_FRAME_LEN: usize = 1024 * 1024;
+
+/// Served at `/api/webtransport`, `null` if the server has WebTransport disabled.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct WebTransportInfo {
+    pub port: u16,
+    /// SHA-256 of the server certificate, only set for self-signed certificates
+    pub certificate_hash: Option<Vec<u8>>,
+}
+
+/// Reliable streams carry postcard messages prefixed with their length as u32 LE.
+pub fn encode_frame(bytes: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(4 + bytes.len());
+    frame.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    frame.extend_from_slice(bytes);
+    frame
+}
+
+/// Removes the first complete frame from the buffer, if any.
+pub fn decode_frame(buffer: &mut Vec<u8>) -> Option<Vec<u8>> {
+    let len_bytes = buffer.get(0..4)?;
+    let len = u32::from_le_bytes(len_bytes.try_into().unwrap()) as usize;
+    if buffer.len() < 4 + len {
+        return None;
+    }
+    let frame = buffer[4..4 + len].to_vec();
+    buffer.drain(..4 + len);
+    Some(frame)
+}
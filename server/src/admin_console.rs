@@ -0,0 +1,171 @@
+use std::io::BufRead;
+
+use nalgebra::Vector2;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::server_actor;
+
+static HELP: &str = "\
+Commands:
+  rooms                                list running rooms
+  players                              list joined players
+  teleport <player> <room> [<x> <y>]   without a position to a start point or checkpoint
+  spawn <room> <mob template> <x> <y>  the mob doesn't respawn
+  kill <player>
+  heal <player>
+  kick <player>
+  ban <name>                           also kicks the player if online
+  unban <name>
+  broadcast <text>                     shown to every player
+  freeze <room>                        stops mobs and players until unfrozen
+  unfreeze <room>
+Rooms are given by id or name, positions in tiles.";
+
+/// Players and rooms are given by name and resolved by the server actor
+#[derive(Debug)]
+pub enum AdminCommand {
+    ListRooms,
+    ListPlayers,
+    Teleport {
+        player: String,
+        room: String,
+        position: Option<Vector2<f32>>,
+    },
+    SpawnMob {
+        room: String,
+        mob_template: String,
+        tile: Vector2<u32>,
+    },
+    Kill {
+        player: String,
+    },
+    Heal {
+        player: String,
+    },
+    Kick {
+        player: String,
+    },
+    Ban {
+        name: String,
+    },
+    Unban {
+        name: String,
+    },
+    Broadcast {
+        text: String,
+    },
+    SetFrozen {
+        room: String,
+        frozen: bool,
+    },
+}
+
+/// Output for the operator, or what went wrong
+pub type AdminReply = Result<String, String>;
+
+/// Reads commands from stdin, so only whoever started the server can use them.
+/// Uses a thread of its own, since a blocking read would keep the runtime from shutting down.
+pub fn spawn(server_actor_sender: mpsc::Sender<server_actor::Message>) {
+    let result = std::thread::Builder::new()
+        .name("admin-console".to_string())
+        .spawn(move || run(server_actor_sender));
+    if let Err(err) = result {
+        tracing::error!("Failed to start admin console: {err}");
+    }
+}
+
+fn run(server_actor_sender: mpsc::Sender<server_actor::Message>) {
+    for line in std::io::stdin().lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                tracing::error!("Failed to read admin command: {err}");
+                break;
+            }
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line == "help" {
+            println!("{HELP}");
+            continue;
+        }
+        let command = match parse(line) {
+            Some(command) => command,
+            None => {
+                println!("Unknown command or wrong arguments, see help");
+                continue;
+            }
+        };
+
+        tracing::info!("Admin command: {line}");
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let message = server_actor::Message::AdminCommand {
+            command,
+            reply: reply_sender,
+        };
+        if server_actor_sender.blocking_send(message).is_err() {
+            break;
+        }
+        match reply_receiver.blocking_recv() {
+            Ok(Ok(output)) => println!("{output}"),
+            Ok(Err(err)) => println!("Error: {err}"),
+            Err(_) => println!("Error: no reply"),
+        }
+    }
+    tracing::debug!("Admin console closed");
+}
+
+fn parse(line: &str) -> Option<AdminCommand> {
+    let (name, rest) = line.split_once(' ').unwrap_or((line, ""));
+    let rest = rest.trim();
+    let args = rest.split_whitespace().collect::<Vec<_>>();
+    let command = match (name, args.as_slice()) {
+        ("rooms", []) => AdminCommand::ListRooms,
+        ("players", []) => AdminCommand::ListPlayers,
+        ("teleport", [player, room]) => AdminCommand::Teleport {
+            player: player.to_string(),
+            room: room.to_string(),
+            position: None,
+        },
+        ("teleport", [player, room, x, y]) => AdminCommand::Teleport {
+            player: player.to_string(),
+            room: room.to_string(),
+            position: Some(Vector2::new(x.parse().ok()?, y.parse().ok()?)),
+        },
+        ("spawn", [room, mob_template, x, y]) => AdminCommand::SpawnMob {
+            room: room.to_string(),
+            mob_template: mob_template.to_string(),
+            tile: Vector2::new(x.parse().ok()?, y.parse().ok()?),
+        },
+        ("kill", [player]) => AdminCommand::Kill {
+            player: player.to_string(),
+        },
+        ("heal", [player]) => AdminCommand::Heal {
+            player: player.to_string(),
+        },
+        ("kick", [player]) => AdminCommand::Kick {
+            player: player.to_string(),
+        },
+        ("ban", [name]) => AdminCommand::Ban {
+            name: name.to_string(),
+        },
+        ("unban", [name]) => AdminCommand::Unban {
+            name: name.to_string(),
+        },
+        ("broadcast", [_, ..]) => AdminCommand::Broadcast {
+            text: rest.to_string(),
+        },
+        ("freeze", [room]) => AdminCommand::SetFrozen {
+            room: room.to_string(),
+            frozen: true,
+        },
+        ("unfreeze", [room]) => AdminCommand::SetFrozen {
+            room: room.to_string(),
+            frozen: false,
+        },
+        _ => return None,
+    };
+    Some(command)
+}
@@ -0,0 +1,141 @@
+use std::sync::Arc;
+
+use mmo_common::{object::ObjectId, player_event::PlayerEvent, room::RoomId};
+use nalgebra::Vector2;
+
+use crate::{
+    admin_console::AdminReply,
+    mob_logic, room_logic,
+    room_state::{MobSpawn, Player, RoomState},
+    room_writer::{RoomWriter, RoomWriterTarget},
+};
+
+/// Operator commands carried out by a room, validated by the server actor as far as it can
+#[derive(Debug)]
+pub enum RoomAdminCommand {
+    Teleport {
+        player_id: ObjectId,
+        room_id: RoomId,
+        position: Vector2<f32>,
+    },
+    SpawnMob {
+        mob_template: String,
+        tile: Vector2<u32>,
+    },
+    Kill {
+        player_id: ObjectId,
+    },
+    Heal {
+        player_id: ObjectId,
+    },
+}
+
+pub fn on_command(
+    command: RoomAdminCommand,
+    state: &mut RoomState,
+    writer: &mut RoomWriter,
+) -> AdminReply {
+    match command {
+        RoomAdminCommand::Teleport {
+            player_id,
+            room_id,
+            position,
+        } => {
+            let name = find_player_mut(player_id, state)?.name.clone();
+            room_logic::teleport_player(player_id, room_id, position, state, writer);
+            tracing::info!(
+                "Teleported {name:?} to room {} at ({}, {})",
+                room_id.0,
+                position.x,
+                position.y
+            );
+            Ok(format!("Teleported {name}"))
+        }
+        RoomAdminCommand::SpawnMob { mob_template, tile } => {
+            let position = tile.cast().add_scalar(0.5);
+            if mob_logic::mob_blocked_at(&state.map, position) {
+                return Err(format!("Tile ({}, {}) is blocked", tile.x, tile.y));
+            }
+            let mob_spawn = Arc::new(MobSpawn {
+                position: tile,
+                mob_template: mob_template.clone(),
+            });
+            let mut mob = mob_logic::spawn_mob(
+                &mob_spawn,
+                &state.server_context,
+                state.last_tick.monotonic_time,
+            )
+            .ok_or_else(|| format!("Failed to spawn {mob_template}"))?;
+            mob.summoned = true;
+            writer.tell_many(
+                RoomWriterTarget::All,
+                &mob_logic::mob_appeared_events(&mob, state.last_tick.tick),
+            );
+            state.mobs.push(mob);
+            tracing::info!("Spawned {mob_template} at ({}, {})", tile.x, tile.y);
+            Ok(format!("Spawned {mob_template}"))
+        }
+        RoomAdminCommand::Kill { player_id } => {
+            let player = find_player_mut(player_id, state)?;
+            if player.is_dead() {
+                return Err(format!("{} is already dead", player.name));
+            }
+            // Dies on the next tick like any other player
+            let change = -player.health;
+            player.health = 0;
+            tell_health_changed(player, change, writer);
+            tracing::info!("Killed {:?}", player.name);
+            Ok(format!("Killed {}", player.name))
+        }
+        RoomAdminCommand::Heal { player_id } => {
+            let player = find_player_mut(player_id, state)?;
+            if player.is_dead() {
+                return Err(format!("{} is dead", player.name));
+            }
+            let change = player.stats.max_health - player.health;
+            player.health = player.stats.max_health;
+            tell_health_changed(player, change, writer);
+            tracing::info!("Healed {:?}", player.name);
+            Ok(format!("Healed {}", player.name))
+        }
+    }
+}
+
+/// Stops everything in the room, so it stays as it is until unfrozen
+pub fn set_frozen(frozen: bool, state: &mut RoomState, writer: &mut RoomWriter) {
+    if state.frozen == frozen {
+        return;
+    }
+    state.frozen = frozen;
+    if frozen {
+        let tick = state.last_tick;
+        for player in state.players.values_mut() {
+            room_logic::stop_player(player, tick.monotonic_time, tick, writer);
+        }
+        mob_logic::stop_mobs(state, writer);
+        tracing::info!("Froze room");
+    } else {
+        tracing::info!("Unfroze room");
+    }
+}
+
+/// Players are briefly in no room while moving between rooms
+fn find_player_mut(player_id: ObjectId, state: &mut RoomState) -> Result<&mut Player, String> {
+    state
+        .players
+        .get_mut(&player_id)
+        .ok_or_else(|| "Player is changing rooms, try again".to_string())
+}
+
+fn tell_health_changed(player: &Player, change: i32, writer: &mut RoomWriter) {
+    writer.tell(
+        RoomWriterTarget::All,
+        PlayerEvent::ObjectHealthChanged {
+            object_id: player.id,
+            health: player.health,
+            change,
+            damage_type: None,
+            critical: false,
+        },
+    );
+}
@@ -1,6 +1,6 @@
-use std::{collections::HashMap, io::Read};
+use std::{collections::HashMap, io::Read, path::Path};
 
-use eyre::Result;
+use eyre::{eyre, Result};
 use sha1::{Digest, Sha1};
 
 #[derive(Debug, Clone)]
@@ -9,6 +9,14 @@ pub struct AssetPaths {
     pub paths: mmo_common::client_config::AssetPaths,
 }
 
+impl AssetPaths {
+    pub fn find_by_local_path(&self, path: &Path) -> Option<&AssetPath> {
+        self.lookup.values().find(|asset| {
+            std::fs::canonicalize(&asset.local_path).is_ok_and(|local_path| local_path == path)
+        })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct AssetPath {
     pub request_filename: String,
@@ -17,45 +25,60 @@ pub struct AssetPath {
 }
 
 struct LocalAssetPaths {
-    tileset: &'static str,
     charset: &'static str,
     font: &'static str,
     font_meta: &'static str,
 }
 
+static ASSETS_DIR: &str = "assets";
+
 const ASSETS: LocalAssetPaths = LocalAssetPaths {
-    tileset: "tileset.png",
     charset: "charset.png",
     font: "notosans.png",
     font_meta: "notosans.json",
 };
 
+/// Serves every file in the assets directory, since the tilesets are only known from the map.
+/// Tilesets are filled in by the server context.
 pub fn load_assets() -> Result<AssetPaths> {
-    let tileset = load_asset(ASSETS.tileset)?;
-    let charset = load_asset(ASSETS.charset)?;
-    let font = load_asset(ASSETS.font)?;
-    let font_meta = load_asset(ASSETS.font_meta)?;
+    let mut lookup = HashMap::new();
+    for entry in std::fs::read_dir(ASSETS_DIR)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        let local_filename = entry
+            .file_name()
+            .into_string()
+            .map_err(|name| eyre!("Invalid asset filename: {name:?}"))?;
+        let asset = load_asset(&local_filename)?;
+        lookup.insert(asset.request_filename.clone(), asset);
+    }
 
+    let request_path = |local_filename: &str| {
+        let local_path = local_path(local_filename);
+        lookup
+            .values()
+            .find(|asset| asset.local_path == local_path)
+            .map(|asset| asset.request_path.clone())
+            .ok_or_else(|| eyre!("Asset not found: {local_path}"))
+    };
     let paths = mmo_common::client_config::AssetPaths {
-        tileset: tileset.request_path.clone(),
-        charset: charset.request_path.clone(),
-        font: font.request_path.clone(),
-        font_meta: font_meta.request_path.clone(),
+        tilesets: vec![],
+        charset: request_path(ASSETS.charset)?,
+        font: request_path(ASSETS.font)?,
+        font_meta: request_path(ASSETS.font_meta)?,
     };
 
-    let lookup = [
-        (tileset.request_filename.clone(), tileset),
-        (charset.request_filename.clone(), charset),
-        (font.request_filename.clone(), font),
-        (font_meta.request_filename.clone(), font_meta),
-    ]
-    .into();
-
     Ok(AssetPaths { lookup, paths })
 }
 
+pub fn charset_local_path() -> String {
+    local_path(ASSETS.charset)
+}
+
 fn load_asset(local_filename: &str) -> Result<AssetPath> {
-    let local_path = format!("assets/{}", local_filename);
+    let local_path = local_path(local_filename);
     let hash = hash_file_content(&local_path)?;
     let request_filename = generate_filename(local_filename, &hash);
     let request_path = format!("/assets/{}", request_filename);
@@ -67,6 +90,10 @@ fn load_asset(local_filename: &str) -> Result<AssetPath> {
     })
 }
 
+fn local_path(local_filename: &str) -> String {
+    format!("{ASSETS_DIR}/{local_filename}")
+}
+
 fn hash_file_content(path: &str) -> Result<String> {
     let mut hasher = Sha1::new();
     {
@@ -1,10 +1,13 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Duration};
 
+use fastrand::Rng;
 use mmo_common::{
+    combat::{DamageType, Resistances},
     object::{Direction4, ObjectId},
     player_event::PlayerEvent,
 };
 use nalgebra::Vector2;
+use tokio::time::Instant;
 
 use crate::{
     mob::MobAttack,
@@ -14,6 +17,19 @@ use crate::{
     util,
 };
 
+static MAX_LAG_COMPENSATION: Duration = Duration::from_millis(300);
+/// Damage varies randomly by up to this fraction in either direction
+static DAMAGE_VARIANCE: f32 = 0.1;
+static CRITICAL_MULTIPLIER: f32 = 1.5;
+
+/// Damage of a single hit after defence and resistances
+#[derive(Debug, Clone, Copy)]
+struct Hit {
+    damage: i32,
+    damage_type: DamageType,
+    critical: bool,
+}
+
 pub fn player_attack(player_id: ObjectId, state: &mut RoomState, writer: &mut RoomWriter) {
     let player = if let Some(player) = state.players.get(&player_id) {
         player
@@ -21,22 +37,38 @@ pub fn player_attack(player_id: ObjectId, state: &mut RoomState, writer: &mut Ro
         return;
     };
 
+    // Hit mobs where the attacker saw them, not where they are now
+    let rewound_at = Instant::now() - player.latency.min(MAX_LAG_COMPENSATION);
+
     for mob in state.mobs.iter_mut() {
+        let mob_position = mob
+            .position_history
+            .position_at(rewound_at)
+            .unwrap_or(mob.movement.position);
         if hit_reaches(
             player.local_movement.position,
             player.remote_movement.look_direction,
-            state.server_context.player.attack_range,
-            mob.movement.position,
+            player.stats.attack_range,
+            mob_position,
         ) {
-            let damage = state.server_context.player.damage;
-            mob.health = (mob.health - damage).max(0);
+            let hit = roll_hit(
+                player.stats.damage,
+                player.stats.damage_type,
+                player.stats.critical_chance,
+                mob.template.defence,
+                &mob.template.resistances,
+                &mut state.rng,
+            );
+            mob.health = (mob.health - hit.damage).max(0);
 
             writer.tell(
                 RoomWriterTarget::All,
                 PlayerEvent::ObjectHealthChanged {
                     object_id: mob.id,
                     health: mob.health,
-                    change: -damage,
+                    change: -hit.damage,
+                    damage_type: Some(hit.damage_type),
+                    critical: hit.critical,
                 },
             );
 
@@ -45,6 +77,12 @@ pub fn player_attack(player_id: ObjectId, state: &mut RoomState, writer: &mut Ro
                     RoomWriterTarget::All,
                     PlayerEvent::ObjectDisappeared { object_id: mob.id },
                 );
+                if mob.boss.is_some_and(|boss| boss.engaged_at.is_some()) {
+                    writer.tell(
+                        RoomWriterTarget::All,
+                        PlayerEvent::BossHealthBar { boss: None },
+                    );
+                }
             }
         }
     }
@@ -53,6 +91,8 @@ pub fn player_attack(player_id: ObjectId, state: &mut RoomState, writer: &mut Ro
     state.mobs.retain(|mob| {
         if mob.health > 0 {
             true
+        } else if mob.summoned || mob.spawn_area.is_some() {
+            false
         } else {
             let respawn = MobRespawn {
                 spawn: mob.spawn.clone(),
@@ -69,6 +109,7 @@ pub fn mob_attack_player(
     player: &mut Player,
     mob: &Mob,
     attack: &MobAttack,
+    rng: &mut Rng,
     writer: &mut RoomWriter,
 ) {
     let attack_direction =
@@ -79,7 +120,7 @@ pub fn mob_attack_player(
         attack.range,
     );
     if in_attack_range && attack_direction == mob.movement.look_direction {
-        hurt_player(player, attack.damage, tick.tick, writer);
+        hurt_player(player, attack, tick.tick, rng, writer);
     }
 }
 
@@ -89,6 +130,7 @@ pub fn mob_attack_area(
     attack_position: Vector2<f32>,
     attack_radius: f32,
     players: &mut HashMap<ObjectId, Player>,
+    rng: &mut Rng,
     writer: &mut RoomWriter,
 ) {
     for player in players.values_mut() {
@@ -98,13 +140,30 @@ pub fn mob_attack_area(
             attack_radius,
         );
         if in_attack_range {
-            hurt_player(player, attack.damage, tick.tick, writer);
+            hurt_player(player, attack, tick.tick, rng, writer);
         }
     }
 }
 
-fn hurt_player(player: &mut Player, damage: i32, tick: Tick, writer: &mut RoomWriter) {
-    player.health = (player.health - damage).max(0);
+fn hurt_player(
+    player: &mut Player,
+    attack: &MobAttack,
+    tick: Tick,
+    rng: &mut Rng,
+    writer: &mut RoomWriter,
+) {
+    if player.is_dead() {
+        return;
+    }
+    let hit = roll_hit(
+        attack.damage,
+        attack.damage_type,
+        attack.critical_chance,
+        player.stats.defence,
+        &player.stats.resistances,
+        rng,
+    );
+    player.health = (player.health - hit.damage).max(0);
     player.last_damaged_at = tick;
 
     writer.tell(
@@ -112,20 +171,46 @@ fn hurt_player(player: &mut Player, damage: i32, tick: Tick, writer: &mut RoomWr
         PlayerEvent::ObjectHealthChanged {
             object_id: player.id,
             health: player.health,
-            change: -damage,
+            change: -hit.damage,
+            damage_type: Some(hit.damage_type),
+            critical: hit.critical,
         },
     );
 }
 
+/// Every hit deals at least one damage
+fn roll_hit(
+    damage: i32,
+    damage_type: DamageType,
+    critical_chance: f32,
+    defence: i32,
+    resistances: &Resistances,
+    rng: &mut Rng,
+) -> Hit {
+    let critical = rng.f32() < critical_chance;
+    let variance = 1.0 + DAMAGE_VARIANCE * (2.0 * rng.f32() - 1.0);
+    let mut damage = damage as f32 * variance;
+    if critical {
+        damage *= CRITICAL_MULTIPLIER;
+    }
+    damage *= 1.0 - resistances.get(damage_type).min(1.0);
+    Hit {
+        damage: (damage.round() as i32 - defence).max(1),
+        damage_type,
+        critical,
+    }
+}
+
 pub fn heal_players(state: &mut RoomState, writer: &mut RoomWriter) {
     let tick = state.last_tick.tick;
     if tick.is_nth(state.server_context.player.heal_rate) {
         for player in state.players.values_mut() {
-            if player.health < player.max_health
+            if !player.is_dead()
+                && player.health < player.stats.max_health
                 && tick - player.last_damaged_at > state.server_context.player.heal_after
             {
                 let heal = (state.server_context.player.heal_amount as i32)
-                    .min(player.max_health - player.health);
+                    .min(player.stats.max_health - player.health);
                 player.health += heal;
 
                 writer.tell(
@@ -134,6 +219,8 @@ pub fn heal_players(state: &mut RoomState, writer: &mut RoomWriter) {
                         object_id: player.id,
                         health: player.health,
                         change: heal,
+                        damage_type: None,
+                        critical: false,
                     },
                 );
             }
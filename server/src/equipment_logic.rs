@@ -0,0 +1,102 @@
+use mmo_common::{equipment::EquipmentSlot, object::ObjectId, player_event::PlayerEvent};
+use tokio::time::Instant;
+
+use crate::{
+    room_logic,
+    room_state::{Player, RoomMap, RoomState},
+    room_writer::{RoomWriter, RoomWriterTarget},
+    server_context::ServerContext,
+    tick::TickEvent,
+};
+
+pub fn equip(player_id: ObjectId, item_id: String, state: &mut RoomState, writer: &mut RoomWriter) {
+    let ctx = state.server_context.clone();
+    let Some(player) = state.players.get_mut(&player_id) else {
+        return;
+    };
+    if !ctx.player.carried_equipment.contains(&item_id) {
+        tracing::debug!("Player does not carry {item_id}");
+        return;
+    }
+    let Some(equipment) = ctx.equipment.get(&item_id) else {
+        tracing::error!("Carried equipment not found: {item_id}");
+        return;
+    };
+    if player.equipment.get(&equipment.slot) == Some(&item_id) {
+        return;
+    }
+
+    player.equipment.insert(equipment.slot, item_id);
+    equipment_changed(player, &state.map, &ctx, state.last_tick, writer);
+}
+
+pub fn unequip(
+    player_id: ObjectId,
+    slot: EquipmentSlot,
+    state: &mut RoomState,
+    writer: &mut RoomWriter,
+) {
+    let ctx = state.server_context.clone();
+    let Some(player) = state.players.get_mut(&player_id) else {
+        return;
+    };
+    if player.equipment.remove(&slot).is_some() {
+        equipment_changed(player, &state.map, &ctx, state.last_tick, writer);
+    }
+}
+
+/// After a reload, items may have been removed, moved to another slot or no longer be carried
+pub fn remove_unknown_equipment(player: &mut Player, ctx: &ServerContext) {
+    player.equipment.retain(|slot, item_id| {
+        ctx.player.carried_equipment.contains(item_id)
+            && ctx
+                .equipment
+                .get(item_id)
+                .is_some_and(|equipment| equipment.slot == *slot)
+    });
+}
+
+/// Recalculates the stats and tells everyone how the player looks now
+pub fn equipment_changed(
+    player: &mut Player,
+    map: &RoomMap,
+    ctx: &ServerContext,
+    tick: TickEvent,
+    writer: &mut RoomWriter,
+) {
+    player.stats = ctx.player_stats(&player.equipment);
+    player.health = player.health.min(player.stats.max_health);
+
+    let velocity =
+        room_logic::player_velocity(map, ctx, &player.stats, player.local_movement.position);
+    if velocity != player.velocity {
+        // Continue from here, so the new velocity only applies to the rest of the movement
+        let now = Instant::now();
+        player.remote_movement.position =
+            room_logic::interpolate_position(player.remote_movement, player.velocity, now).position;
+        player.remote_movement.received_at = now;
+        player.velocity = velocity;
+        writer.tell(
+            RoomWriterTarget::AllExcept(player.id),
+            room_logic::player_movement_changed(player, tick),
+        );
+    }
+
+    writer.tell(RoomWriterTarget::Player(player.id), stats_changed(player));
+    writer.tell(
+        RoomWriterTarget::All,
+        PlayerEvent::ObjectEquipmentChanged {
+            object_id: player.id,
+            overlay_animation_ids: ctx.overlay_animation_ids(&player.equipment),
+            health: player.health,
+            max_health: player.stats.max_health,
+        },
+    );
+}
+
+pub fn stats_changed(player: &Player) -> PlayerEvent {
+    PlayerEvent::StatsChanged {
+        equipped: player.equipment.values().cloned().collect(),
+        stats: player.stats,
+    }
+}
@@ -0,0 +1,55 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use tokio::sync::mpsc;
+use tracing::instrument;
+
+use crate::assets::AssetPaths;
+use crate::server_actor;
+use crate::server_context::{ServerContext, CONFIG_PATH, MAP_PATH, PROFANITY_PATH};
+
+static POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Reloads the server context whenever one of the data files changes.
+/// Invalid files are reported and the previous context stays in use.
+#[instrument(skip_all)]
+pub async fn watch_data_files(
+    asset_paths: AssetPaths,
+    server_actor_sender: mpsc::Sender<server_actor::Message>,
+) {
+    let mut last_modified = modified_times();
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        let modified = modified_times();
+        if modified == last_modified {
+            continue;
+        }
+        last_modified = modified;
+
+        tracing::info!("Data files changed, reloading...");
+        // Parsing the map takes a while, so it stays off the runtime's worker threads
+        let load_asset_paths = asset_paths.clone();
+        let loaded = tokio::task::spawn_blocking(move || ServerContext::load(load_asset_paths))
+            .await
+            .unwrap_or_else(|err| Err(eyre::eyre!("Reload task failed: {err}")));
+        match loaded {
+            Ok(server_context) => {
+                let message = server_actor::Message::ServerContextReloaded {
+                    server_context: Arc::new(server_context),
+                };
+                if server_actor_sender.send(message).await.is_err() {
+                    break;
+                }
+            }
+            Err(err) => tracing::error!("Failed to reload, keeping previous data: {err}"),
+        }
+    }
+}
+
+fn modified_times() -> Vec<Option<SystemTime>> {
+    [MAP_PATH, CONFIG_PATH, PROFANITY_PATH]
+        .iter()
+        .map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect()
+}
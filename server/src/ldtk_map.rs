@@ -1,77 +1,167 @@
 use std::{
     collections::{HashMap, HashSet},
+    num::NonZeroU16,
+    path::Path,
     sync::Arc,
 };
 
-use eyre::Result;
-use mmo_common::room::{ForegroundTile, RoomId, TileIndex};
-use nalgebra::Vector2;
+use eyre::{Result, WrapErr};
+use mmo_common::room::{
+    ForegroundTile, Light, RoomId, Terrain, TileIndex, TilesetId, MAX_TILESETS,
+};
+use nalgebra::{Vector2, Vector3};
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    room_state::{MobSpawn, Portal, RoomMap},
-    server_context::World,
+    room_state::{MobSpawn, MobSpawnArea, Neighbour, Portal, RoomMap, SpawnPoint},
+    server_context::{Tileset, World},
+    tick::TickDuration,
 };
 
+static DEFAULT_LIGHT_RADIUS: f32 = 4.0;
+
 pub fn load(path: &str) -> Result<World> {
+    let mut problems = vec![];
+    let world = load_lenient(path, &mut problems)?;
+    if !problems.is_empty() {
+        return Err(eyre::eyre!(problems.join("\n")));
+    }
+    Ok(world)
+}
+
+/// Leaves out broken levels and entities, reporting each of them instead of stopping at the
+/// first one. Only fails if the file itself can't be used.
+pub fn load_lenient(path: &str, problems: &mut Vec<String>) -> Result<World> {
     let json = std::fs::read_to_string(path)?;
     let ldtk_map: LdtkMap = serde_json::from_str(&json)?;
 
-    let maps: Result<Vec<ParsedMap>> = ldtk_map
+    let map_dir = Path::new(path).parent().unwrap_or(Path::new(""));
+    let tilesets = collect_tilesets(&ldtk_map, map_dir)?;
+
+    // Indexed by room id, with `None` for broken levels
+    let mut maps = ldtk_map
         .levels
         .iter()
-        .map(|ldtk_level| convert_map(&ldtk_map, ldtk_level))
-        .collect();
-
-    let mut maps = maps?;
-    resolve_portals(&mut maps)?;
+        .enumerate()
+        .map(|(i, ldtk_level)| {
+            let map = convert_map(&ldtk_map, ldtk_level, RoomId(i as u64), problems);
+            map.map_err(|err| {
+                problems.push(format!("Invalid level {}: {err:#}", ldtk_level.identifier));
+            })
+            .ok()
+        })
+        .collect::<Vec<_>>();
+    resolve_portals(&mut maps, problems);
 
-    let (start_room_id, start_position) = find_start_position(&maps)?;
-    let start_position = start_position.cast().add_scalar(0.5);
+    let start_points = collect_start_points(&maps);
+    if start_points.is_empty() {
+        problems.push("No player start found".to_string());
+    }
 
     let maps = maps
         .into_iter()
         .enumerate()
-        .map(|(i, map)| (RoomId(i as u64), Arc::new(map.map)))
+        .filter_map(|(i, map)| Some((RoomId(i as u64), Arc::new(map?.map))))
         .collect();
+    let instance_groups = collect_instance_groups(&maps);
 
     Ok(World {
         maps,
-        start_room_id,
-        start_position,
+        start_points,
+        tilesets,
+        instance_groups,
     })
 }
 
+/// Levels reachable from an instance entrance without taking an exit portal
+fn collect_instance_groups(maps: &HashMap<RoomId, Arc<RoomMap>>) -> Vec<HashSet<RoomId>> {
+    let entrance_targets = maps
+        .values()
+        .flat_map(|map| &map.portals)
+        .filter(|portal| portal.instance_entrance)
+        .map(|portal| portal.target_room_id)
+        .collect::<HashSet<_>>();
+    entrance_targets
+        .into_iter()
+        .map(|target_room_id| {
+            let mut group = HashSet::from([target_room_id]);
+            let mut queue = vec![target_room_id];
+            while let Some(room_id) = queue.pop() {
+                let Some(map) = maps.get(&room_id) else {
+                    continue;
+                };
+                let portal_targets = map
+                    .portals
+                    .iter()
+                    .filter(|portal| !portal.instance_entrance && !portal.instance_exit)
+                    .map(|portal| portal.target_room_id);
+                let neighbours = map.neighbours.iter().map(|neighbour| neighbour.room_id);
+                for room_id in portal_targets.chain(neighbours) {
+                    if group.insert(room_id) {
+                        queue.push(room_id);
+                    }
+                }
+            }
+            group
+        })
+        .collect()
+}
+
 struct ParsedMap {
     map: RoomMap,
     portals: Vec<ParsedPortal>,
-    player_starts: Vec<Vector2<u32>>,
+    player_starts: Vec<SpawnPoint>,
 }
 
-fn convert_map(ldtk_map: &LdtkMap, ldtk_level: &LdtkLevel) -> Result<ParsedMap> {
+fn convert_map(
+    ldtk_map: &LdtkMap,
+    ldtk_level: &LdtkLevel,
+    room_id: RoomId,
+    problems: &mut Vec<String>,
+) -> Result<ParsedMap> {
     let foreground_tile_ids: HashMap<TileIndex, u32> = {
-        let h1 = collect_enum_tile_ids(ldtk_map, "Foreground_h1")?;
-        let h2 = collect_enum_tile_ids(ldtk_map, "Foreground_h2")?;
+        let h1 = collect_enum_tile_ids(ldtk_map, "Foreground_h1");
+        let h2 = collect_enum_tile_ids(ldtk_map, "Foreground_h2");
         [(h1, 1), (h2, 2)]
             .into_iter()
             .flat_map(|(set, h)| set.into_iter().map(move |id| (id, h)))
             .collect()
     };
-    let blocked_tile_ids = collect_enum_tile_ids(ldtk_map, "Blocked")?;
+    let blocked_tile_ids = collect_enum_tile_ids(ldtk_map, "Blocked");
+    let grid_size = ldtk_map.default_grid_size;
+    let neighbours = collect_neighbours(ldtk_map, ldtk_level)?;
 
     let mut size = Vector2::new(0, 0);
     let mut bg_dense_layers = vec![];
     let mut bg_sparse_layer = vec![];
     let mut fg_sparse_layer = vec![];
     let mut mob_spawns = vec![];
+    let mut mob_spawn_areas = vec![];
     let mut portals = vec![];
     let mut player_starts = vec![];
+    let mut checkpoints = vec![];
+    let mut lights = vec![];
+    let mut int_grid_cells = vec![];
 
     for ldtk_layer in &ldtk_level.layer_instances {
         let tiles = ldtk_layer.tiles();
-        if !tiles.is_empty() {
+        if !tiles.is_empty() || ldtk_layer.typ == "IntGrid" {
+            if ldtk_layer.grid_size != grid_size {
+                return Err(eyre::eyre!(
+                    "Layer {} grid size is not the default grid size",
+                    ldtk_layer.identifier
+                ));
+            }
             let layer_size = Vector2::new(ldtk_layer.width, ldtk_layer.height);
             size = size.zip_map(&layer_size, |a, b| a.max(b));
+        }
+
+        if ldtk_layer.typ == "IntGrid" {
+            int_grid_cells.extend(collect_int_grid_cells(ldtk_map, ldtk_layer)?);
+        }
+
+        if !tiles.is_empty() {
+            let tileset = layer_tileset_id(ldtk_map, ldtk_layer)?;
 
             let has_non_divisible_pos = tiles.iter().any(|tile| {
                 tile.px[0] % ldtk_map.default_grid_size != 0
@@ -92,55 +182,136 @@ fn convert_map(ldtk_map: &LdtkMap, ldtk_level: &LdtkLevel) -> Result<ParsedMap>
             };
             let has_foreground_tiles = tiles
                 .iter()
-                .any(|tile| foreground_tile_ids.contains_key(&tile.t));
+                .any(|tile| foreground_tile_ids.contains_key(&tile.tile_index(tileset)));
 
             if has_duplicate_positions || has_foreground_tiles {
-                let (bg, fg) = convert_sparse_layer(ldtk_map, ldtk_layer, &foreground_tile_ids);
+                let (bg, fg) =
+                    convert_sparse_layer(ldtk_map, ldtk_layer, tileset, &foreground_tile_ids);
                 bg_sparse_layer.extend(bg);
                 fg_sparse_layer.extend(fg);
             } else {
-                let layer = convert_dense_layer(ldtk_map, ldtk_layer);
+                let layer = convert_dense_layer(ldtk_map, ldtk_layer, tileset);
                 bg_dense_layers.push(layer);
             }
         }
 
         if !ldtk_layer.entity_instances.is_empty() {
-            for entity in collect_entities(&ldtk_layer.entity_instances) {
+            let entities = collect_entities(
+                ldtk_level,
+                &ldtk_layer.entity_instances,
+                grid_size,
+                problems,
+            );
+            for entity in entities {
                 match entity {
                     ParsedEntity::MobSpawn(mob_spawn) => mob_spawns.push(Arc::new(mob_spawn)),
+                    ParsedEntity::MobSpawnArea(area) => mob_spawn_areas.push(area),
                     ParsedEntity::Portal(portal) => portals.push(portal),
-                    ParsedEntity::PlayerStart(position) => player_starts.push(position),
+                    ParsedEntity::PlayerStart { name, position } => {
+                        player_starts.push(spawn_point(ldtk_level, room_id, name, position))
+                    }
+                    ParsedEntity::Checkpoint { name, position } => {
+                        checkpoints.push(spawn_point(ldtk_level, room_id, name, position))
+                    }
+                    ParsedEntity::Light(light) => lights.push(light),
                 }
             }
         }
     }
-    let collisions = collect_collisions(
+    let mut collisions = collect_collisions(
         size,
         &bg_dense_layers,
         &bg_sparse_layer,
         &fg_sparse_layer,
         &blocked_tile_ids,
     );
+    let mut terrain = vec![Terrain::default(); (size.x * size.y) as usize];
+    for (position, mask) in int_grid_cells {
+        let i = (position.y * size.x + position.x) as usize;
+        match mask {
+            IntGridMask::Blocked => collisions[i] = true,
+            IntGridMask::Water => terrain[i].water = true,
+            IntGridMask::Slow => terrain[i].slow = true,
+            IntGridMask::NoPvp => terrain[i].no_pvp = true,
+        }
+    }
 
     Ok(ParsedMap {
         map: RoomMap {
+            name: ldtk_level.identifier.clone(),
+            world_position: Vector2::new(ldtk_level.world_x, ldtk_level.world_y) / grid_size as i32,
             size,
             bg_dense_layers,
             bg_sparse_layer,
             fg_sparse_layer,
             collisions,
+            terrain,
+            neighbours,
             portals: vec![],
             mob_spawns,
+            mob_spawn_areas,
+            checkpoints,
+            lights,
         },
         portals,
         player_starts,
     })
 }
 
-fn resolve_portals(maps: &mut Vec<ParsedMap>) -> Result<()> {
+/// Corner and depth neighbours can't be walked into, so only levels sharing an edge are kept
+fn collect_neighbours(ldtk_map: &LdtkMap, ldtk_level: &LdtkLevel) -> Result<Vec<Neighbour>> {
+    let grid_size = ldtk_map.default_grid_size as i32;
+    ldtk_level
+        .neighbours
+        .iter()
+        .filter(|neighbour| ["n", "e", "s", "w"].contains(&neighbour.dir.as_str()))
+        .map(|neighbour| {
+            let (i, neighbour_level) = ldtk_map
+                .levels
+                .iter()
+                .enumerate()
+                .find(|(_, level)| level.iid == neighbour.level_iid)
+                .ok_or_else(|| eyre::eyre!("Neighbour level {} not found", neighbour.level_iid))?;
+            let offset = Vector2::new(
+                neighbour_level.world_x - ldtk_level.world_x,
+                neighbour_level.world_y - ldtk_level.world_y,
+            );
+            if offset.x % grid_size != 0 || offset.y % grid_size != 0 {
+                return Err(eyre::eyre!(
+                    "Neighbour level {} is not aligned to the grid",
+                    neighbour_level.identifier
+                ));
+            }
+            Ok(Neighbour {
+                room_id: RoomId(i as u64),
+                offset: offset / grid_size,
+            })
+        })
+        .collect()
+}
+
+/// Unnamed points are named after their location
+fn spawn_point(
+    ldtk_level: &LdtkLevel,
+    room_id: RoomId,
+    name: Option<String>,
+    position: Vector2<u32>,
+) -> SpawnPoint {
+    let name = name
+        .unwrap_or_else(|| format!("{} ({}, {})", ldtk_level.identifier, position.x, position.y));
+    SpawnPoint {
+        name,
+        room_id,
+        position: position.cast().add_scalar(0.5),
+    }
+}
+
+/// Portals to broken levels are left out as well
+fn resolve_portals(maps: &mut [Option<ParsedMap>], problems: &mut Vec<String>) {
     let portals: HashMap<String, (usize, Vector2<u32>)> = maps
         .iter()
         .enumerate()
+        .filter_map(|(i, map)| Some((i, map.as_ref()?)))
         .flat_map(|(i, map)| {
             map.portals
                 .iter()
@@ -148,34 +319,32 @@ fn resolve_portals(maps: &mut Vec<ParsedMap>) -> Result<()> {
         })
         .collect();
 
-    for map in maps {
-        let map_portals: Result<Vec<Portal>> = map
-            .portals
-            .iter()
-            .map(|portal| {
-                if let Some((target_map, target_position)) = portals.get(&portal.target_entity_iid)
-                {
-                    Ok(Portal {
-                        position: portal.position,
-                        target_room_id: RoomId(*target_map as u64),
-                        target_position: target_position.cast(),
-                    })
-                } else {
-                    Err(eyre::eyre!(format!(
-                        "Portal target not found: {}",
-                        portal.entity_iid
-                    )))
-                }
-            })
-            .collect();
-        map.map.portals = map_portals?;
+    for map in maps.iter_mut().flatten() {
+        let mut map_portals = vec![];
+        for portal in &map.portals {
+            if let Some((target_map, target_position)) = portals.get(&portal.target_entity_iid) {
+                map_portals.push(Portal {
+                    position: portal.position,
+                    target_room_id: RoomId(*target_map as u64),
+                    target_position: target_position.cast(),
+                    instance_entrance: portal.instance_entrance,
+                    instance_exit: portal.instance_exit,
+                });
+            } else {
+                problems.push(format!(
+                    "{} ({}, {}): portal target not found: {}",
+                    map.map.name, portal.position.x, portal.position.y, portal.target_entity_iid
+                ));
+            }
+        }
+        map.map.portals = map_portals;
     }
-    Ok(())
 }
 
 fn convert_sparse_layer(
     ldtk_map: &LdtkMap,
     ldtk_layer: &LdtkLayerInstance,
+    tileset: TilesetId,
     foreground_tile_ids: &HashMap<TileIndex, u32>,
 ) -> (Vec<(Vector2<u32>, TileIndex)>, Vec<ForegroundTile>) {
     let grid_size = ldtk_map.default_grid_size;
@@ -183,30 +352,122 @@ fn convert_sparse_layer(
     let mut fg = vec![];
     for tile in ldtk_layer.tiles() {
         let position = Vector2::new(tile.px[0] / grid_size, tile.px[1] / grid_size);
-        if let Some(height) = foreground_tile_ids.get(&tile.t) {
+        let tile_index = tile.tile_index(tileset);
+        if let Some(height) = foreground_tile_ids.get(&tile_index) {
             fg.push(ForegroundTile {
                 position,
                 height: *height,
-                tile_index: tile.t,
+                tile_index,
             });
         } else {
-            bg.push((position, tile.t));
+            bg.push((position, tile_index));
         }
     }
     (bg, fg)
 }
 
-fn convert_dense_layer(ldtk_map: &LdtkMap, ldtk_layer: &LdtkLayerInstance) -> Vec<TileIndex> {
+fn convert_dense_layer(
+    ldtk_map: &LdtkMap,
+    ldtk_layer: &LdtkLayerInstance,
+    tileset: TilesetId,
+) -> Vec<TileIndex> {
     let grid_size = ldtk_map.default_grid_size;
     let mut tiles = vec![TileIndex::empty(); (ldtk_layer.width * ldtk_layer.height) as usize];
     for tile in ldtk_layer.tiles() {
         let x = tile.px[0] / grid_size;
         let y = tile.px[1] / grid_size;
-        tiles[(y * ldtk_layer.width + x) as usize] = tile.t;
+        tiles[(y * ldtk_layer.width + x) as usize] = tile.tile_index(tileset);
     }
     tiles
 }
 
+#[derive(Debug, Clone, Copy)]
+enum IntGridMask {
+    Blocked,
+    Water,
+    Slow,
+    NoPvp,
+}
+
+/// Values are recognized by their identifier, others are only used for auto-layer rules
+fn collect_int_grid_cells(
+    ldtk_map: &LdtkMap,
+    ldtk_layer: &LdtkLayerInstance,
+) -> Result<Vec<(Vector2<u32>, IntGridMask)>> {
+    let layer_def = ldtk_map
+        .defs
+        .layers
+        .iter()
+        .find(|layer_def| layer_def.uid == ldtk_layer.layer_def_uid)
+        .ok_or_else(|| eyre::eyre!("Layer definition {} not found", ldtk_layer.identifier))?;
+    let masks: HashMap<u32, IntGridMask> = layer_def
+        .int_grid_values
+        .iter()
+        .filter_map(|int_grid_value| {
+            let mask = match int_grid_value.identifier.as_deref()? {
+                "Blocked" => IntGridMask::Blocked,
+                "Water" => IntGridMask::Water,
+                "Slow" => IntGridMask::Slow,
+                "No_PvP" => IntGridMask::NoPvp,
+                _ => return None,
+            };
+            Some((int_grid_value.value, mask))
+        })
+        .collect();
+
+    let cells = ldtk_layer
+        .int_grid_csv
+        .iter()
+        .enumerate()
+        .filter_map(|(i, value)| {
+            let mask = masks.get(value)?;
+            let i = i as u32;
+            let position = Vector2::new(i % ldtk_layer.width, i / ldtk_layer.width);
+            Some((position, *mask))
+        })
+        .collect();
+    Ok(cells)
+}
+
+fn collect_tilesets(ldtk_map: &LdtkMap, map_dir: &Path) -> Result<Vec<Tileset>> {
+    if ldtk_map.defs.tilesets.len() > MAX_TILESETS {
+        return Err(eyre::eyre!("At most {MAX_TILESETS} tilesets are supported"));
+    }
+    ldtk_map
+        .defs
+        .tilesets
+        .iter()
+        .map(|tileset| {
+            let rel_path = tileset
+                .rel_path
+                .as_ref()
+                .ok_or_else(|| eyre::eyre!("Tileset {} has no image", tileset.identifier))?;
+            let path = map_dir
+                .join(rel_path)
+                .canonicalize()
+                .wrap_err_with(|| format!("Tileset {} image not found", tileset.identifier))?;
+            Ok(Tileset {
+                identifier: tileset.identifier.clone(),
+                path,
+            })
+        })
+        .collect()
+}
+
+fn layer_tileset_id(ldtk_map: &LdtkMap, ldtk_layer: &LdtkLayerInstance) -> Result<TilesetId> {
+    ldtk_layer
+        .tileset_def_uid
+        .and_then(|uid| {
+            ldtk_map
+                .defs
+                .tilesets
+                .iter()
+                .position(|tileset| tileset.uid == uid)
+        })
+        .map(|i| TilesetId(i as u8))
+        .ok_or_else(|| eyre::eyre!("Layer {} has no tileset", ldtk_layer.identifier))
+}
+
 fn collect_collisions(
     size: Vector2<u32>,
     dense_layers: &[Vec<TileIndex>],
@@ -237,75 +498,177 @@ fn collect_collisions(
     collisions
 }
 
-fn collect_enum_tile_ids(ldtk_map: &LdtkMap, enum_value: &str) -> Result<HashSet<TileIndex>> {
-    let tileset = ldtk_map
-        .defs
-        .tilesets
-        .first()
-        .ok_or_else(|| eyre::eyre!("No first tileset"))?;
-    if let Some(enum_tags) = tileset
-        .enum_tags
-        .iter()
-        .find(|tag| tag.enum_value_id == enum_value)
-    {
-        Ok(enum_tags.tile_ids.iter().copied().collect())
-    } else {
-        Ok(HashSet::new())
+fn collect_enum_tile_ids(ldtk_map: &LdtkMap, enum_value: &str) -> HashSet<TileIndex> {
+    let mut tile_ids = HashSet::new();
+    for (i, tileset) in ldtk_map.defs.tilesets.iter().enumerate() {
+        let tileset_id = TilesetId(i as u8);
+        if let Some(enum_tags) = tileset
+            .enum_tags
+            .iter()
+            .find(|tag| tag.enum_value_id == enum_value)
+        {
+            tile_ids.extend(enum_tags.tile_ids.iter().map(|index| TileIndex {
+                tileset: tileset_id,
+                index: Some(*index),
+            }));
+        }
     }
+    tile_ids
 }
 
-fn collect_entities(entities: &[LdtkEntityInstance]) -> Vec<ParsedEntity> {
-    entities.iter().filter_map(collect_entity).collect()
+fn collect_entities(
+    ldtk_level: &LdtkLevel,
+    entities: &[LdtkEntityInstance],
+    grid_size: u32,
+    problems: &mut Vec<String>,
+) -> Vec<ParsedEntity> {
+    let mut parsed_entities = vec![];
+    for entity in entities {
+        match collect_entity(entity, grid_size) {
+            Ok(parsed_entity) => parsed_entities.extend(parsed_entity),
+            Err(err) => problems.push(format!(
+                "{} ({}, {}): Invalid {} entity: {err:#}",
+                ldtk_level.identifier, entity.grid.x, entity.grid.y, entity.identifier
+            )),
+        }
+    }
+    parsed_entities
 }
 
-fn collect_entity(entity: &LdtkEntityInstance) -> Option<ParsedEntity> {
+fn collect_entity(entity: &LdtkEntityInstance, grid_size: u32) -> Result<Option<ParsedEntity>> {
     match entity.identifier.as_str() {
-        "Mob" => match entity.field("mob")? {
-            LdtkEntityFieldInstance::String { value, .. } => {
-                Some(ParsedEntity::MobSpawn(MobSpawn {
-                    position: entity.grid,
-                    mob_template: value.clone(),
-                }))
-            }
-            _ => None,
+        "Mob" => match entity.field("mob") {
+            Some(LdtkEntityFieldInstance::String {
+                value: Some(value), ..
+            }) => Ok(Some(ParsedEntity::MobSpawn(MobSpawn {
+                position: entity.grid,
+                mob_template: value.clone(),
+            }))),
+            _ => Err(eyre::eyre!("Missing mob field")),
         },
-        "Portal" => match entity.field("target")? {
-            LdtkEntityFieldInstance::EntityRef { value, .. } => {
-                Some(ParsedEntity::Portal(ParsedPortal {
+        "Mob_Spawn_Area" => {
+            let mobs = match entity.field("mobs") {
+                Some(LdtkEntityFieldInstance::StringArray { value, .. }) => value
+                    .iter()
+                    .flatten()
+                    .map(|mob| parse_weighted_template(mob))
+                    .collect::<Result<Vec<_>>>()?,
+                _ => return Err(eyre::eyre!("Missing mobs field")),
+            };
+            let max_population = match entity.field("max_population") {
+                Some(LdtkEntityFieldInstance::Int {
+                    value: Some(value), ..
+                }) if *value >= 0 => *value as u32,
+                _ => return Err(eyre::eyre!("Missing max_population field")),
+            };
+            let respawn_interval = match entity.field("respawn_interval") {
+                Some(LdtkEntityFieldInstance::Float {
+                    value: Some(value), ..
+                }) if *value >= 0.0 => TickDuration::from(*value),
+                _ => return Err(eyre::eyre!("Missing respawn_interval field")),
+            };
+            Ok(Some(ParsedEntity::MobSpawnArea(MobSpawnArea {
+                position: entity.grid,
+                size: Vector2::new(entity.width, entity.height) / grid_size,
+                mobs,
+                max_population,
+                respawn_interval,
+            })))
+        }
+        "Portal" => match entity.field("target") {
+            Some(LdtkEntityFieldInstance::EntityRef { value, .. }) => {
+                Ok(Some(ParsedEntity::Portal(ParsedPortal {
                     position: entity.grid,
                     entity_iid: entity.iid.clone(),
                     target_entity_iid: value.entity_iid.clone(),
-                }))
+                    instance_entrance: matches!(
+                        entity.field("instance_entrance"),
+                        Some(LdtkEntityFieldInstance::Bool { value: true, .. })
+                    ),
+                    instance_exit: matches!(
+                        entity.field("instance_exit"),
+                        Some(LdtkEntityFieldInstance::Bool { value: true, .. })
+                    ),
+                })))
             }
-            _ => None,
+            _ => Err(eyre::eyre!("Missing target field")),
         },
-        "Player_Start" => Some(ParsedEntity::PlayerStart(entity.grid)),
-        _ => None,
+        "Player_Start" => Ok(Some(ParsedEntity::PlayerStart {
+            name: entity.string_field("name"),
+            position: entity.grid,
+        })),
+        "Checkpoint" => Ok(Some(ParsedEntity::Checkpoint {
+            name: entity.string_field("name"),
+            position: entity.grid,
+        })),
+        "Light" => {
+            let color = match entity.field("color") {
+                Some(LdtkEntityFieldInstance::Color { value, .. }) => parse_color(value)?,
+                _ => Vector3::new(0xff, 0xff, 0xff),
+            };
+            let radius = match entity.field("radius") {
+                Some(LdtkEntityFieldInstance::Float {
+                    value: Some(value), ..
+                }) => *value,
+                _ => DEFAULT_LIGHT_RADIUS,
+            };
+            Ok(Some(ParsedEntity::Light(Light {
+                position: entity.grid.cast().add_scalar(0.5),
+                radius,
+                color,
+            })))
+        }
+        _ => Ok(None),
     }
 }
 
-fn find_start_position(maps: &[ParsedMap]) -> Result<(RoomId, Vector2<u32>)> {
-    let player_start_entities = maps
-        .iter()
-        .enumerate()
-        .flat_map(|(i, map)| {
-            map.player_starts
-                .iter()
-                .map(move |position| (RoomId(i as u64), *position))
-        })
-        .collect::<Vec<_>>();
-    match player_start_entities.as_slice() {
-        &[(room_id, position)] => Ok((room_id, position)),
-        &[] => Err(eyre::eyre!("No player start found")),
-        _ => Err(eyre::eyre!("Multiple player starts found")),
+/// Written as `template` or `template:weight`, the weight defaults to 1
+fn parse_weighted_template(value: &str) -> Result<(String, u32)> {
+    match value.split_once(':') {
+        Some((template, weight)) => {
+            let weight = weight
+                .trim()
+                .parse()
+                .wrap_err_with(|| format!("Invalid weight in {value}"))?;
+            Ok((template.trim().to_string(), weight))
+        }
+        None => Ok((value.trim().to_string(), 1)),
     }
 }
 
+/// LDtk colors are written as `#rrggbb`
+fn parse_color(value: &str) -> Result<Vector3<u8>> {
+    let hex = value
+        .strip_prefix('#')
+        .filter(|hex| hex.len() == 6)
+        .ok_or_else(|| eyre::eyre!("Invalid color {value}"))?;
+    let channel = |i: usize| {
+        u8::from_str_radix(&hex[i..i + 2], 16).wrap_err_with(|| format!("Invalid color {value}"))
+    };
+    Ok(Vector3::new(channel(0)?, channel(2)?, channel(4)?))
+}
+
+fn collect_start_points(maps: &[Option<ParsedMap>]) -> Vec<SpawnPoint> {
+    maps.iter()
+        .flatten()
+        .flat_map(|map| map.player_starts.iter().cloned())
+        .collect()
+}
+
 #[derive(Debug, Clone)]
 enum ParsedEntity {
     MobSpawn(MobSpawn),
+    MobSpawnArea(MobSpawnArea),
     Portal(ParsedPortal),
-    PlayerStart(Vector2<u32>),
+    PlayerStart {
+        name: Option<String>,
+        position: Vector2<u32>,
+    },
+    Checkpoint {
+        name: Option<String>,
+        position: Vector2<u32>,
+    },
+    Light(Light),
 }
 
 #[derive(Debug, Clone)]
@@ -313,6 +676,8 @@ struct ParsedPortal {
     position: Vector2<u32>,
     entity_iid: String,
     target_entity_iid: String,
+    instance_entrance: bool,
+    instance_exit: bool,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -326,24 +691,60 @@ struct LdtkMap {
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct LdtkDefs {
+    layers: Vec<LdtkLayerDef>,
     tilesets: Vec<LdtkTileset>,
 }
 
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LdtkLayerDef {
+    uid: u32,
+    int_grid_values: Vec<LdtkIntGridValue>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LdtkIntGridValue {
+    value: u32,
+    identifier: Option<String>,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct LdtkLevel {
+    identifier: String,
+    iid: String,
+    world_x: i32,
+    world_y: i32,
+    #[serde(rename = "__neighbours")]
+    neighbours: Vec<LdtkNeighbour>,
     layer_instances: Vec<LdtkLayerInstance>,
 }
 
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LdtkNeighbour {
+    level_iid: String,
+    dir: String,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct LdtkLayerInstance {
+    #[serde(rename = "__identifier")]
+    identifier: String,
     #[serde(rename = "__type")]
     typ: String,
     #[serde(rename = "__cWid")]
     width: u32,
     #[serde(rename = "__cHei")]
     height: u32,
+    #[serde(rename = "__gridSize")]
+    grid_size: u32,
+    #[serde(rename = "__tilesetDefUid")]
+    tileset_def_uid: Option<u32>,
+    layer_def_uid: u32,
+    int_grid_csv: Vec<u32>,
     grid_tiles: Vec<LdtkTileInstance>,
     auto_layer_tiles: Vec<LdtkTileInstance>,
     entity_instances: Vec<LdtkEntityInstance>,
@@ -363,7 +764,16 @@ impl LdtkLayerInstance {
 #[serde(rename_all = "camelCase")]
 struct LdtkTileInstance {
     px: [u32; 2],
-    t: TileIndex,
+    t: NonZeroU16,
+}
+
+impl LdtkTileInstance {
+    fn tile_index(&self, tileset: TilesetId) -> TileIndex {
+        TileIndex {
+            tileset,
+            index: Some(self.t),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -374,6 +784,9 @@ struct LdtkEntityInstance {
     #[serde(rename = "__grid")]
     grid: Vector2<u32>,
     iid: String,
+    /// In pixels
+    width: u32,
+    height: u32,
     field_instances: Vec<LdtkEntityFieldInstance>,
 }
 
@@ -382,8 +795,20 @@ impl LdtkEntityInstance {
         self.field_instances.iter().find(|field| match field {
             LdtkEntityFieldInstance::String { identifier: id, .. } => id == identifier,
             LdtkEntityFieldInstance::EntityRef { identifier: id, .. } => id == identifier,
+            LdtkEntityFieldInstance::Float { identifier: id, .. } => id == identifier,
+            LdtkEntityFieldInstance::Int { identifier: id, .. } => id == identifier,
+            LdtkEntityFieldInstance::Bool { identifier: id, .. } => id == identifier,
+            LdtkEntityFieldInstance::StringArray { identifier: id, .. } => id == identifier,
+            LdtkEntityFieldInstance::Color { identifier: id, .. } => id == identifier,
         })
     }
+
+    pub fn string_field(&self, identifier: &str) -> Option<String> {
+        match self.field(identifier)? {
+            LdtkEntityFieldInstance::String { value, .. } => value.clone(),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -393,7 +818,7 @@ enum LdtkEntityFieldInstance {
         #[serde(rename = "__identifier")]
         identifier: String,
         #[serde(rename = "__value")]
-        value: String,
+        value: Option<String>,
     },
     EntityRef {
         #[serde(rename = "__identifier")]
@@ -401,6 +826,37 @@ enum LdtkEntityFieldInstance {
         #[serde(rename = "__value")]
         value: LdtkEntityRef,
     },
+    Float {
+        #[serde(rename = "__identifier")]
+        identifier: String,
+        #[serde(rename = "__value")]
+        value: Option<f32>,
+    },
+    Int {
+        #[serde(rename = "__identifier")]
+        identifier: String,
+        #[serde(rename = "__value")]
+        value: Option<i32>,
+    },
+    Bool {
+        #[serde(rename = "__identifier")]
+        identifier: String,
+        #[serde(rename = "__value")]
+        value: bool,
+    },
+    #[serde(rename = "Array<String>")]
+    StringArray {
+        #[serde(rename = "__identifier")]
+        identifier: String,
+        #[serde(rename = "__value")]
+        value: Vec<Option<String>>,
+    },
+    Color {
+        #[serde(rename = "__identifier")]
+        identifier: String,
+        #[serde(rename = "__value")]
+        value: String,
+    },
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -412,6 +868,9 @@ struct LdtkEntityRef {
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct LdtkTileset {
+    uid: u32,
+    identifier: String,
+    rel_path: Option<String>,
     enum_tags: Vec<LdtkEnumTag>,
 }
 
@@ -419,5 +878,5 @@ struct LdtkTileset {
 #[serde(rename_all = "camelCase")]
 struct LdtkEnumTag {
     enum_value_id: String,
-    tile_ids: Vec<TileIndex>,
+    tile_ids: Vec<NonZeroU16>,
 }
@@ -1,11 +1,17 @@
+mod admin_console;
+mod admin_logic;
 mod assets;
 mod client_connection;
 mod combat_logic;
+mod equipment_logic;
+mod hot_reload;
 mod ldtk_map;
+mod metrics;
 mod mob;
 mod mob_logic;
 mod object;
 mod player;
+mod player_save;
 mod room_actor;
 mod room_logic;
 mod room_state;
@@ -14,22 +20,36 @@ mod server_actor;
 mod server_context;
 mod tick;
 mod util;
+mod validate;
+mod ws_transport;
+mod wt_transport;
 
 use std::sync::Arc;
+use std::time::Duration;
 
 use axum::extract::{Path, State, WebSocketUpgrade};
-use axum::http::{HeaderValue, Response};
+use axum::http::{header, HeaderValue, Response};
 use axum::response::{ErrorResponse, IntoResponse};
 use axum::routing::get;
-use axum::Router;
-use server_context::{ServerConfig, ServerContext};
+use axum::{Json, Router};
+use mmo_common::transport::WebTransportInfo;
+use serde::Serialize;
+use server_context::ServerContext;
 use tokio::net::TcpSocket;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::time::Instant;
 use tower_http::services::ServeDir;
 
+/// Players are warned this long before the server stops
+static SHUTDOWN_COUNTDOWN: Duration = Duration::from_secs(10);
+/// A busy or stuck server actor shouldn't hold up monitoring requests
+static STATUS_TIMEOUT: Duration = Duration::from_secs(2);
+
 struct AppState {
+    started_at: Instant,
     server_actor_sender: mpsc::Sender<server_actor::Message>,
     server_context: Arc<ServerContext>,
+    webtransport_info: Option<WebTransportInfo>,
 }
 
 #[tokio::main]
@@ -41,37 +61,78 @@ async fn main() -> eyre::Result<()> {
         .with_thread_ids(true)
         .init();
 
-    let port = std::env::var("MMO_PORT").unwrap_or_else(|_| "8081".to_string());
+    if std::env::args().nth(1).as_deref() == Some("validate") {
+        return validate::run();
+    }
+    let started_at = Instant::now();
 
-    tracing::info!("Loading maps...");
-    let room_maps = ldtk_map::load("data/map.ldtk")?;
-    tracing::info!("Loaded maps");
+    let port = std::env::var("MMO_PORT").unwrap_or_else(|_| "8081".to_string());
 
     tracing::info!("Loading assets...");
     let asset_paths = assets::load_assets()?;
     tracing::info!("Loaded assets");
 
-    tracing::info!("Loading config...");
-    let config = ServerConfig::load("data/config.toml")?;
-    tracing::info!("Loaded config");
-
-    let server_context = Arc::new(ServerContext::new(config, asset_paths, room_maps)?);
+    let server_context = Arc::new(ServerContext::load(asset_paths.clone())?);
 
     let (tick_sender, _) = tick::spawn_producer();
 
     let (server_actor_sender, server_actor_receiver) = mpsc::channel::<server_actor::Message>(4096);
-    tokio::spawn({
+    let server_actor_task = tokio::spawn({
         let server_context = server_context.clone();
         async move { server_actor::run(server_context, server_actor_receiver, tick_sender).await }
     });
 
+    let (shutdown_sender, shutdown_receiver) = watch::channel(false);
+    tokio::spawn({
+        let server_actor_sender = server_actor_sender.clone();
+        async move {
+            wait_for_signal().await;
+            tracing::info!("Shutdown requested");
+            shutdown_sender.send_replace(true);
+            let shutdown = server_actor::Message::Shutdown {
+                countdown: SHUTDOWN_COUNTDOWN,
+            };
+            if let Err(err) = server_actor_sender.send(shutdown).await {
+                tracing::error!("Failed to request shutdown: {err}");
+            }
+        }
+    });
+    admin_console::spawn(server_actor_sender.clone());
+    tokio::spawn(hot_reload::watch_data_files(
+        asset_paths,
+        server_actor_sender.clone(),
+    ));
+
+    let webtransport_config = wt_transport::WebTransportConfig::from_env().await?;
+    let webtransport_info = webtransport_config
+        .as_ref()
+        .map(|config| config.info())
+        .transpose()?;
+    if let Some(webtransport_config) = webtransport_config {
+        let server_actor_sender = server_actor_sender.clone();
+        let shutdown_receiver = shutdown_receiver.clone();
+        tokio::spawn(async move {
+            if let Err(err) =
+                wt_transport::serve(webtransport_config, server_actor_sender, shutdown_receiver)
+                    .await
+            {
+                tracing::error!("WebTransport server failed: {err}");
+            }
+        });
+    }
+
     let app_state = Box::leak(Box::new(AppState {
+        started_at,
         server_actor_sender,
         server_context,
+        webtransport_info,
     }));
 
     let app = Router::new()
         .route("/api/ws", get(ws_handler))
+        .route("/api/webtransport", get(webtransport_info_handler))
+        .route("/api/status", get(status_handler))
+        .route("/metrics", get(metrics_handler))
         .route("/assets/:filename", get(serve_file_handler))
         .nest_service("/", ServeDir::new("webroot"))
         .with_state(app_state);
@@ -83,17 +144,113 @@ async fn main() -> eyre::Result<()> {
         socket.bind(format!("0.0.0.0:{port}").parse()?)?;
         socket.listen(1024)?
     };
-    axum::serve(listener, app).await?;
+    let mut http_shutdown_receiver = shutdown_receiver.clone();
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async move {
+            let _ = http_shutdown_receiver.wait_for(|shutdown| *shutdown).await;
+        })
+        .await?;
+    tracing::info!("Stopped accepting connections");
 
+    server_actor_task.await?;
+    tracing::info!("Shut down");
     Ok(())
 }
 
+/// SIGINT or SIGTERM
+async fn wait_for_signal() {
+    let ctrl_c = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            tracing::error!("Failed to listen for ctrl-c: {err}");
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(err) => {
+                tracing::error!("Failed to listen for SIGTERM: {err}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
 async fn ws_handler(
     ws_upgrade: WebSocketUpgrade,
     State(app): State<&'static AppState>,
 ) -> impl IntoResponse {
     let message_sender = app.server_actor_sender.clone();
-    ws_upgrade.on_upgrade(move |ws| client_connection::handle(ws, message_sender))
+    ws_upgrade.on_upgrade(move |ws| ws_transport::handle(ws, message_sender))
+}
+
+async fn webtransport_info_handler(
+    State(app): State<&'static AppState>,
+) -> Json<Option<WebTransportInfo>> {
+    Json(app.webtransport_info.clone())
+}
+
+#[derive(Serialize)]
+struct StatusResponse {
+    version: &'static str,
+    uptime_seconds: f64,
+    #[serde(flatten)]
+    server: server_actor::ServerStatus,
+}
+
+async fn status_handler(
+    State(app): State<&'static AppState>,
+) -> Result<Json<StatusResponse>, ErrorResponse> {
+    let server = server_status(app)
+        .await
+        .ok_or_else(|| ErrorResponse::from(axum::http::StatusCode::SERVICE_UNAVAILABLE))?;
+    Ok(Json(StatusResponse {
+        version: util::git_sha(),
+        uptime_seconds: app.started_at.elapsed().as_secs_f64(),
+        server,
+    }))
+}
+
+/// The counters don't depend on the server actor, so they are rendered even if it doesn't answer
+async fn metrics_handler(State(app): State<&'static AppState>) -> impl IntoResponse {
+    // Before the status request adds to the queue
+    let server_queue_depth = server_actor::queue_depth(&app.server_actor_sender);
+    let status = server_status(app).await;
+    let body = metrics::render(
+        status.as_ref(),
+        app.started_at.elapsed(),
+        server_queue_depth,
+    );
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body)
+}
+
+/// `None` once the server actor stopped or if it doesn't answer in time
+async fn server_status(app: &AppState) -> Option<server_actor::ServerStatus> {
+    let request = async {
+        let (reply, reply_receiver) = oneshot::channel();
+        app.server_actor_sender
+            .send(server_actor::Message::Status { reply })
+            .await
+            .ok()?;
+        reply_receiver.await.ok()
+    };
+    match tokio::time::timeout(STATUS_TIMEOUT, request).await {
+        Ok(status) => status,
+        Err(_) => {
+            tracing::warn!("Server actor didn't answer the status request in time");
+            None
+        }
+    }
 }
 
 async fn serve_file_handler(
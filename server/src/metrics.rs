@@ -0,0 +1,246 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::server_actor::{RoomStatus, ServerStatus};
+use crate::util;
+
+/// Upper bounds of the tick duration histogram buckets, in seconds
+static TICK_DURATION_BUCKETS: [f64; 8] = [0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1];
+
+static CLIENTS_CONNECTED: AtomicU64 = AtomicU64::new(0);
+static CLIENTS_DISCONNECTED: AtomicU64 = AtomicU64::new(0);
+static HANDSHAKE_FAILURES: AtomicU64 = AtomicU64::new(0);
+static EVENTS_SENT: AtomicU64 = AtomicU64::new(0);
+static BYTES_SENT: AtomicU64 = AtomicU64::new(0);
+/// Per bucket, not cumulative
+static TICK_DURATION_BUCKET_COUNTS: [AtomicU64; 8] = [const { AtomicU64::new(0) }; 8];
+static TICK_DURATION_COUNT: AtomicU64 = AtomicU64::new(0);
+static TICK_DURATION_SUM_NANOS: AtomicU64 = AtomicU64::new(0);
+
+pub fn client_connected() {
+    CLIENTS_CONNECTED.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn client_disconnected() {
+    CLIENTS_DISCONNECTED.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn handshake_failed() {
+    HANDSHAKE_FAILURES.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn events_sent(events: usize, bytes: usize) {
+    EVENTS_SENT.fetch_add(events as u64, Ordering::Relaxed);
+    BYTES_SENT.fetch_add(bytes as u64, Ordering::Relaxed);
+}
+
+pub fn record_tick_duration(duration: Duration) {
+    let seconds = duration.as_secs_f64();
+    if let Some(bucket) = TICK_DURATION_BUCKETS
+        .iter()
+        .position(|bound| seconds <= *bound)
+    {
+        TICK_DURATION_BUCKET_COUNTS[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+    TICK_DURATION_COUNT.fetch_add(1, Ordering::Relaxed);
+    TICK_DURATION_SUM_NANOS.fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+}
+
+/// Prometheus text format. Rates like events per second are left to the queries.
+/// Without a status only the metrics that don't need the server actor are rendered.
+pub fn render(
+    status: Option<&ServerStatus>,
+    uptime: Duration,
+    server_queue_depth: usize,
+) -> String {
+    let mut out = String::new();
+    let git_sha = util::git_sha();
+
+    header(&mut out, "mmo_build_info", "gauge", "Server version");
+    sample(
+        &mut out,
+        "mmo_build_info",
+        &format!("{{version=\"{}\"}}", escape_label(git_sha)),
+        1,
+    );
+    header(&mut out, "mmo_uptime_seconds", "gauge", "Time since start");
+    sample(&mut out, "mmo_uptime_seconds", "", uptime.as_secs_f64());
+    header(
+        &mut out,
+        "mmo_server_actor_up",
+        "gauge",
+        "Whether the server actor answered the status request",
+    );
+    sample(
+        &mut out,
+        "mmo_server_actor_up",
+        "",
+        u8::from(status.is_some()),
+    );
+
+    header(
+        &mut out,
+        "mmo_queue_depth",
+        "gauge",
+        "Messages waiting in the actor channels",
+    );
+    sample(
+        &mut out,
+        "mmo_queue_depth",
+        "{queue=\"server_actor\"}",
+        server_queue_depth,
+    );
+    if let Some(status) = status {
+        render_status(&mut out, status);
+    }
+
+    let counters = [
+        (
+            "mmo_clients_connected_total",
+            "Clients that completed the handshake",
+            &CLIENTS_CONNECTED,
+        ),
+        (
+            "mmo_clients_disconnected_total",
+            "Clients that disconnected after the handshake",
+            &CLIENTS_DISCONNECTED,
+        ),
+        (
+            "mmo_handshake_failures_total",
+            "Connections that failed before the handshake completed",
+            &HANDSHAKE_FAILURES,
+        ),
+        (
+            "mmo_events_sent_total",
+            "Events sent to clients, repeated datagrams included",
+            &EVENTS_SENT,
+        ),
+        (
+            "mmo_bytes_sent_total",
+            "Serialized event bytes sent to clients",
+            &BYTES_SENT,
+        ),
+    ];
+    for (name, help, counter) in counters {
+        header(&mut out, name, "counter", help);
+        sample(&mut out, name, "", counter.load(Ordering::Relaxed));
+    }
+
+    let name = "mmo_room_tick_duration_seconds";
+    header(
+        &mut out,
+        name,
+        "histogram",
+        "Time rooms spend handling a tick",
+    );
+    let mut cumulative = 0;
+    for (bound, count) in TICK_DURATION_BUCKETS
+        .iter()
+        .zip(&TICK_DURATION_BUCKET_COUNTS)
+    {
+        cumulative += count.load(Ordering::Relaxed);
+        sample(
+            &mut out,
+            &format!("{name}_bucket"),
+            &format!("{{le=\"{bound}\"}}"),
+            cumulative,
+        );
+    }
+    let count = TICK_DURATION_COUNT.load(Ordering::Relaxed);
+    sample(&mut out, &format!("{name}_bucket"), "{le=\"+Inf\"}", count);
+    let sum = Duration::from_nanos(TICK_DURATION_SUM_NANOS.load(Ordering::Relaxed));
+    sample(&mut out, &format!("{name}_sum"), "", sum.as_secs_f64());
+    sample(&mut out, &format!("{name}_count"), "", count);
+
+    out
+}
+
+fn render_status(out: &mut String, status: &ServerStatus) {
+    sample(
+        out,
+        "mmo_queue_depth",
+        "{queue=\"room_upstream\"}",
+        status.upstream_queue_depth,
+    );
+    header(
+        out,
+        "mmo_room_queue_depth",
+        "gauge",
+        "Messages waiting for each room, its instances included",
+    );
+    for (labels, queue_depth) in per_room(status, |room| room.queue_depth) {
+        sample(out, "mmo_room_queue_depth", &labels, queue_depth);
+    }
+    header(
+        out,
+        "mmo_player_event_queue_depth_max",
+        "gauge",
+        "Longest queue of event batches waiting to be sent to a player",
+    );
+    sample(
+        out,
+        "mmo_player_event_queue_depth_max",
+        "",
+        status.max_event_queue_depth,
+    );
+
+    header(out, "mmo_players", "gauge", "Players that joined");
+    sample(out, "mmo_players", "", status.players);
+    header(
+        out,
+        "mmo_pending_players",
+        "gauge",
+        "Connected players that didn't join yet",
+    );
+    sample(out, "mmo_pending_players", "", status.pending_players);
+    header(
+        out,
+        "mmo_rooms",
+        "gauge",
+        "Running rooms, idle ones included",
+    );
+    sample(out, "mmo_rooms", "", status.rooms.len());
+    header(
+        out,
+        "mmo_room_players",
+        "gauge",
+        "Players per room, its instances included",
+    );
+    for (labels, players) in per_room(status, |room| room.players) {
+        sample(out, "mmo_room_players", &labels, players);
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn sample(out: &mut String, name: &str, labels: &str, value: impl std::fmt::Display) {
+    let _ = writeln!(out, "{name}{labels} {value}");
+}
+
+/// Instances are summed into their room, a series per instance owner would grow with
+/// the players
+fn per_room(status: &ServerStatus, value: impl Fn(&RoomStatus) -> usize) -> Vec<(String, usize)> {
+    let mut rooms: BTreeMap<(u64, &str), usize> = BTreeMap::new();
+    for room in &status.rooms {
+        *rooms.entry((room.room_id, &room.name)).or_default() += value(room);
+    }
+    rooms
+        .into_iter()
+        .map(|((room_id, name), value)| {
+            let labels = format!("{{room_id=\"{room_id}\",room=\"{}\"}}", escape_label(name));
+            (labels, value)
+        })
+        .collect()
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -1,3 +1,4 @@
+use mmo_common::combat::{DamageType, Resistances};
 use serde::Deserialize;
 
 use crate::tick::TickDuration;
@@ -11,8 +12,15 @@ pub struct MobTemplate {
     pub chase_velocity: f32,
     pub movement_range: f32,
     pub max_health: i32,
+    /// Subtracted from the damage of every hit taken
+    #[serde(default)]
+    pub defence: i32,
+    #[serde(default)]
+    pub resistances: Resistances,
     pub attack_cooldown: TickDuration,
     pub attacks: Vec<MobAttack>,
+    #[serde(default)]
+    pub boss: Option<BossConfig>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -20,6 +28,11 @@ pub struct MobAttack {
     pub target_type: MobAttackTargetType,
     pub range: f32,
     pub damage: i32,
+    #[serde(default)]
+    pub damage_type: DamageType,
+    /// From 0.0 to 1.0
+    #[serde(default)]
+    pub critical_chance: f32,
     pub telegraph_length: TickDuration,
     pub length: TickDuration,
     pub animation_index: u8,
@@ -31,3 +44,45 @@ pub enum MobAttackTargetType {
     Single,
     Area { radius: f32 },
 }
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BossConfig {
+    /// Shown in the boss health bar
+    pub name: String,
+    /// Ordered by descending health threshold, the first phase is active from the start
+    pub phases: Vec<BossPhase>,
+    #[serde(default)]
+    pub enrage: Option<BossEnrage>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BossPhase {
+    /// The phase starts when health falls to this fraction of max health
+    pub health_threshold: f32,
+    pub rotation: AttackRotation,
+    /// Spawned near the boss when the phase starts
+    #[serde(default)]
+    pub summons: Vec<BossSummon>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type")]
+pub enum AttackRotation {
+    /// One weight per attack of the template
+    Weighted { weights: Vec<u32> },
+    /// Attack indices, repeated from the start after the last one
+    Sequence { attacks: Vec<u8> },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BossSummon {
+    pub mob_template: String,
+    pub count: u32,
+}
+
+/// The boss hits harder after being in combat for too long
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct BossEnrage {
+    pub after: TickDuration,
+    pub damage_multiplier: f32,
+}
@@ -3,23 +3,34 @@ use std::sync::Arc;
 use fastrand::Rng;
 use mmo_common::{
     object::{Direction4, Direction8, ObjectType, ALL_DIRECTIONS_8},
-    player_event::PlayerEvent,
+    player_event::{BossHealthBar, PlayerEvent},
 };
+use nalgebra::Vector2;
 use tokio::time::Instant;
 
 use crate::{
     combat_logic,
-    mob::{MobAttackTargetType, MobTemplate},
+    mob::{AttackRotation, MobAttackTargetType, MobTemplate},
     object,
     room_state::{
-        Mob, MobAttackState, MobRespawn, MobSpawn, Player, RemoteMovement, RoomMap, RoomState,
+        BossState, Mob, MobAttackState, MobRespawn, MobSpawn, Player, PositionHistory,
+        RemoteMovement, RoomMap, RoomState,
     },
     room_writer::{RoomWriter, RoomWriterTarget},
     server_context::ServerContext,
-    tick::{self, Tick},
+    tick::{self, Tick, TickDuration},
     util,
 };
 
+/// Summoned mobs appear up to this many tiles away from the boss
+static SUMMON_DISTANCE: i32 = 2;
+/// Spawn areas don't spawn mobs within this many tiles of a player
+static SPAWN_AREA_MIN_PLAYER_DISTANCE: f32 = 10.0;
+/// Each player after the first shortens the spawn area interval by this fraction of it
+static SPAWN_AREA_SPEEDUP_PER_PLAYER: f32 = 0.5;
+
+/// Spawn areas start out empty and are filled by `refill_spawn_areas`, which keeps away from
+/// the players, since the player whose arrival created the room isn't in it yet
 pub fn populate_mobs(map: &RoomMap, ctx: &ServerContext, now: Instant) -> Vec<Mob> {
     map.mob_spawns
         .iter()
@@ -27,6 +38,46 @@ pub fn populate_mobs(map: &RoomMap, ctx: &ServerContext, now: Instant) -> Vec<Mo
         .collect()
 }
 
+/// Mobs of a room that stopped lose their targets, since every player left
+pub fn restore_mobs(mut mobs: Vec<Mob>, now: Instant) -> Vec<Mob> {
+    for mob in &mut mobs {
+        mob.attack_state = None;
+        mob.movement.direction = None;
+        mob.movement.received_at = now;
+        mob.position_history.clear();
+        if let Some(boss) = &mut mob.boss {
+            boss.engaged_at = None;
+            boss.enraged = false;
+        }
+    }
+    mobs
+}
+
+/// Mobs pick a new direction and target on their next tick
+pub fn stop_mobs(state: &mut RoomState, writer: &mut RoomWriter) {
+    let tick = state.last_tick.tick;
+    for mob in &mut state.mobs {
+        mob.attack_state = None;
+        if mob.movement.direction.take().is_some() {
+            writer.tell(
+                RoomWriterTarget::All,
+                PlayerEvent::ObjectMovementChanged {
+                    object_id: mob.id,
+                    position: mob.movement.position,
+                    velocity: mob.velocity,
+                    direction: None,
+                    look_direction: mob.movement.look_direction,
+                    tick: tick.0,
+                },
+            );
+        }
+    }
+}
+
+pub fn spawn_area_timers(map: &RoomMap) -> Vec<Tick> {
+    vec![Tick(0); map.mob_spawn_areas.len()]
+}
+
 pub fn respawn_mobs(state: &mut RoomState, writer: &mut RoomWriter) {
     let should_respawn = |respawn: &MobRespawn| respawn.respawn_at <= state.last_tick.tick;
     for mob_respawn in state.mob_respawns.iter() {
@@ -36,7 +87,10 @@ pub fn respawn_mobs(state: &mut RoomState, writer: &mut RoomWriter) {
                 &state.server_context,
                 state.last_tick.monotonic_time,
             ) {
-                writer.tell_many(RoomWriterTarget::All, &mob_appeared_events(&mob));
+                writer.tell_many(
+                    RoomWriterTarget::All,
+                    &mob_appeared_events(&mob, state.last_tick.tick),
+                );
                 state.mobs.push(mob);
             }
         }
@@ -44,14 +98,98 @@ pub fn respawn_mobs(state: &mut RoomState, writer: &mut RoomWriter) {
     state
         .mob_respawns
         .retain(|mob_respawn| !should_respawn(mob_respawn));
+
+    refill_spawn_areas(state, writer);
+}
+
+/// Spawns one mob per area at a time, the interval only starts once the area is below its cap
+fn refill_spawn_areas(state: &mut RoomState, writer: &mut RoomWriter) {
+    let tick = state.last_tick;
+    let map = state.map.clone();
+    let player_positions = state
+        .players
+        .values()
+        .map(|player| player.local_movement.position)
+        .collect::<Vec<_>>();
+    let speedup =
+        1.0 + SPAWN_AREA_SPEEDUP_PER_PLAYER * player_positions.len().saturating_sub(1) as f32;
+    for (area_index, area) in map.mob_spawn_areas.iter().enumerate() {
+        let interval = TickDuration((area.respawn_interval.0 as f32 / speedup) as u32);
+        let population = state
+            .mobs
+            .iter()
+            .filter(|mob| mob.spawn_area == Some(area_index))
+            .count();
+        if population >= area.max_population as usize {
+            state.spawn_area_timers[area_index] = tick.tick + interval;
+            continue;
+        }
+        if tick.tick < state.spawn_area_timers[area_index] {
+            continue;
+        }
+        if let Some(mob) = spawn_area_mob(
+            area_index,
+            &map,
+            &player_positions,
+            &state.server_context,
+            tick.monotonic_time,
+            &mut state.rng,
+        ) {
+            writer.tell_many(RoomWriterTarget::All, &mob_appeared_events(&mob, tick.tick));
+            state.mobs.push(mob);
+            state.spawn_area_timers[area_index] = tick.tick + interval;
+        }
+    }
+}
+
+/// Picks a weighted template and a free tile out of sight of the players
+fn spawn_area_mob(
+    area_index: usize,
+    map: &RoomMap,
+    player_positions: &[Vector2<f32>],
+    ctx: &ServerContext,
+    now: Instant,
+    rng: &mut Rng,
+) -> Option<Mob> {
+    let area = &map.mob_spawn_areas[area_index];
+    let weights = area
+        .mobs
+        .iter()
+        .map(|(_, weight)| *weight)
+        .collect::<Vec<_>>();
+    let (mob_template, _) = &area.mobs[choose_weighted(&weights, rng)?];
+    let candidates = (0..area.size.y)
+        .flat_map(|dy| (0..area.size.x).map(move |dx| Vector2::new(dx, dy)))
+        .map(|offset| area.position + offset)
+        .filter(|tile| !mob_blocked_at(map, tile.cast().add_scalar(0.5)))
+        .filter(|tile| {
+            player_positions.iter().all(|position| {
+                !util::in_distance(
+                    *position,
+                    tile.cast().add_scalar(0.5),
+                    SPAWN_AREA_MIN_PLAYER_DISTANCE,
+                )
+            })
+        })
+        .collect::<Vec<_>>();
+    let position = *rng.choice(&candidates)?;
+    let mob_spawn = Arc::new(MobSpawn {
+        position,
+        mob_template: mob_template.clone(),
+    });
+    let mut mob = spawn_mob(&mob_spawn, ctx, now)?;
+    mob.spawn_area = Some(area_index);
+    Some(mob)
 }
 
-pub fn mob_appeared_events(mob: &Mob) -> [PlayerEvent; 2] {
+pub fn mob_appeared_events(mob: &Mob, tick: Tick) -> [PlayerEvent; 2] {
     [
         PlayerEvent::ObjectAppeared {
             object_id: mob.id,
             object_type: ObjectType::Mob,
+            name: mob.template.id.clone(),
             animation_id: mob.animation_id,
+            overlay_animation_ids: vec![],
             health: mob.health,
             max_health: mob.template.max_health,
         },
@@ -61,46 +199,70 @@ pub fn mob_appeared_events(mob: &Mob) -> [PlayerEvent; 2] {
             velocity: mob.velocity,
             direction: mob.movement.direction,
             look_direction: mob.movement.look_direction,
+            tick: tick.0,
         },
     ]
 }
 
-fn spawn_mob(mob_spawn: &Arc<MobSpawn>, ctx: &ServerContext, now: Instant) -> Option<Mob> {
-    let resolve = || -> Option<(Arc<MobTemplate>, u32)> {
-        let mob_template = ctx.mob_templates.get(&mob_spawn.mob_template)?;
-        let animation_id = ctx.mob_animations.get(&mob_template.animation_id)?;
-        Some((mob_template.clone(), *animation_id))
+pub fn spawn_mob(mob_spawn: &Arc<MobSpawn>, ctx: &ServerContext, now: Instant) -> Option<Mob> {
+    let resolve = || -> Result<(Arc<MobTemplate>, u32), String> {
+        let mob_template = ctx
+            .mob_templates
+            .get(&mob_spawn.mob_template)
+            .ok_or_else(|| format!("unknown mob template {}", mob_spawn.mob_template))?;
+        let animation_id = ctx
+            .mob_animations
+            .get(&mob_template.animation_id)
+            .ok_or_else(|| format!("unknown animation {}", mob_template.animation_id))?;
+        Ok((mob_template.clone(), *animation_id))
     };
-    if let Some((mob_template, animation_id)) = resolve() {
-        let position = mob_spawn.position.cast().add_scalar(0.5);
-        let velocity = mob_template.velocity;
-        let health = mob_template.max_health;
-        let mob = Mob {
-            id: object::next_object_id(),
-            animation_id,
-            template: mob_template,
-            spawn: mob_spawn.clone(),
-            movement: RemoteMovement {
-                position,
-                direction: None,
-                look_direction: Direction4::Down,
-                received_at: now,
-            },
-            velocity,
-            attack_state: None,
-            health,
-            last_attacked_at: Tick(0),
-        };
-        Some(mob)
-    } else {
-        None
+    match resolve() {
+        Ok((mob_template, animation_id)) => {
+            let position = mob_spawn.position.cast().add_scalar(0.5);
+            let velocity = mob_template.velocity;
+            let health = mob_template.max_health;
+            let mob = Mob {
+                id: object::next_object_id(),
+                animation_id,
+                spawn: mob_spawn.clone(),
+                movement: RemoteMovement {
+                    position,
+                    direction: None,
+                    look_direction: Direction4::Down,
+                    received_at: now,
+                },
+                position_history: PositionHistory::default(),
+                velocity,
+                attack_state: None,
+                health,
+                last_attacked_at: Tick(0),
+                boss: mob_template.boss.as_ref().map(|_| BossState::default()),
+                summoned: false,
+                spawn_area: None,
+                template: mob_template,
+            };
+            Some(mob)
+        }
+        Err(err) => {
+            tracing::warn!(
+                "Skipping mob spawn at ({}, {}): {err}",
+                mob_spawn.position.x,
+                mob_spawn.position.y
+            );
+            None
+        }
     }
 }
 
 pub fn on_tick(state: &mut RoomState, writer: &mut RoomWriter) {
-    let mut rng = Rng::new();
+    let rng = &mut state.rng;
     let tick = state.last_tick;
+    let mut summons = vec![];
     for mob in &mut state.mobs {
+        if mob.boss.is_some() {
+            update_boss(mob, tick.tick, &state.map, rng, &mut summons, writer);
+        }
+
         let mut crossed_tile = false;
         let mut changed_direction = false;
 
@@ -108,8 +270,7 @@ pub fn on_tick(state: &mut RoomState, writer: &mut RoomWriter) {
             let new_position = mob.movement.position
                 + direction.to_unit_vector() * mob.velocity * tick::TICK_INTERVAL.as_secs_f32();
 
-            let collides =
-                mmo_common::room::collision_at(state.map.size, &state.map.collisions, new_position);
+            let collides = mob_blocked_at(&state.map, new_position);
             let in_movement_range = mob.in_movement_range(new_position);
 
             if !collides && in_movement_range {
@@ -141,11 +302,14 @@ pub fn on_tick(state: &mut RoomState, writer: &mut RoomWriter) {
                     .find(|player| is_valid_attack_target(mob, player));
                 if let Some(target) = target {
                     let target_id = target.id;
-                    let attack_index = choose_attack(mob);
+                    let attack_index = choose_attack(mob, rng);
                     mob.attack_state = Some(MobAttackState::Targeting {
                         target_id,
                         attack_index,
                     });
+                    set_boss_engaged(mob, Some(tick.tick), writer);
+                } else {
+                    set_boss_engaged(mob, None, writer);
                 }
             }
 
@@ -172,6 +336,7 @@ pub fn on_tick(state: &mut RoomState, writer: &mut RoomWriter) {
                                 PlayerEvent::ObjectAnimationAction {
                                     object_id: mob.id,
                                     animation_index: attack.animation_index,
+                                    tick: tick.tick.0,
                                 },
                             );
                             if let MobAttackTargetType::Area { radius } = attack.target_type {
@@ -182,6 +347,7 @@ pub fn on_tick(state: &mut RoomState, writer: &mut RoomWriter) {
                                         position: target.local_movement.position,
                                         radius,
                                         length: attack.telegraph_length.as_secs_f32(),
+                                        tick: tick.tick.0,
                                     },
                                 );
                             }
@@ -208,12 +374,14 @@ pub fn on_tick(state: &mut RoomState, writer: &mut RoomWriter) {
                 attack_started_at,
                 attack_position,
             }) => {
-                let attack = &mob.template.attacks[attack_index as usize];
+                let attack = &mob.attack(attack_index);
                 if tick.tick - attack_started_at >= attack.telegraph_length {
                     match attack.target_type {
                         MobAttackTargetType::Single => {
                             if let Some(target) = state.players.get_mut(&target_id) {
-                                combat_logic::mob_attack_player(tick, target, mob, attack, writer);
+                                combat_logic::mob_attack_player(
+                                    tick, target, mob, attack, rng, writer,
+                                );
                             }
                         }
                         MobAttackTargetType::Area { radius } => {
@@ -223,6 +391,7 @@ pub fn on_tick(state: &mut RoomState, writer: &mut RoomWriter) {
                                 attack_position,
                                 radius,
                                 &mut state.players,
+                                rng,
                                 writer,
                             );
                         }
@@ -246,11 +415,20 @@ pub fn on_tick(state: &mut RoomState, writer: &mut RoomWriter) {
         }
 
         let changed_velocity = {
-            if mob.attack_state.is_some() && mob.velocity != mob.template.chase_velocity {
-                mob.velocity = mob.template.chase_velocity;
-                true
-            } else if mob.attack_state.is_none() && mob.velocity != mob.template.velocity {
-                mob.velocity = mob.template.velocity;
+            let velocity = if mob.attack_state.is_some() {
+                mob.template.chase_velocity
+            } else {
+                mob.template.velocity
+            };
+            let terrain = mmo_common::room::terrain_at(
+                state.map.size,
+                &state.map.terrain,
+                mob.movement.position,
+            );
+            let velocity = velocity
+                * terrain.mob_velocity_factor(state.server_context.slow_terrain_velocity_factor);
+            if mob.velocity != velocity {
+                mob.velocity = velocity;
                 true
             } else {
                 false
@@ -266,21 +444,131 @@ pub fn on_tick(state: &mut RoomState, writer: &mut RoomWriter) {
                     velocity: mob.velocity,
                     direction: mob.movement.direction,
                     look_direction: mob.movement.look_direction,
+                    tick: tick.tick.0,
                 },
             );
         }
     }
+
+    let now = state.last_tick.monotonic_time;
+    for summon in summons {
+        if let Some(mut mob) = spawn_mob(&summon, &state.server_context, now) {
+            mob.summoned = true;
+            writer.tell_many(
+                RoomWriterTarget::All,
+                &mob_appeared_events(&mob, state.last_tick.tick),
+            );
+            state.mobs.push(mob);
+        }
+    }
+}
+
+/// Advances the phase by health and enrages the boss after being engaged for too long
+fn update_boss(
+    mob: &mut Mob,
+    tick: Tick,
+    map: &RoomMap,
+    rng: &mut Rng,
+    summons: &mut Vec<Arc<MobSpawn>>,
+    writer: &mut RoomWriter,
+) {
+    let template = mob.template.clone();
+    let (Some(boss), Some(boss_config)) = (&mut mob.boss, &template.boss) else {
+        return;
+    };
+    let mut changed = false;
+
+    let health_fraction = mob.health as f32 / template.max_health as f32;
+    let phase = boss_config
+        .phases
+        .iter()
+        .rposition(|phase| health_fraction <= phase.health_threshold)
+        .unwrap_or(0);
+    if phase > boss.phase {
+        boss.phase = phase;
+        boss.rotation_index = 0;
+        changed = true;
+        for summon in &boss_config.phases[phase].summons {
+            for _ in 0..summon.count {
+                summons.push(Arc::new(MobSpawn {
+                    position: summon_position(mob.movement.position, map, rng),
+                    mob_template: summon.mob_template.clone(),
+                }));
+            }
+        }
+    }
+
+    if let (Some(enrage), Some(engaged_at)) = (boss_config.enrage, boss.engaged_at) {
+        if !boss.enraged && tick - engaged_at >= enrage.after {
+            boss.enraged = true;
+            changed = true;
+        }
+    }
+
+    if changed && boss.engaged_at.is_some() {
+        writer.tell(
+            RoomWriterTarget::All,
+            PlayerEvent::BossHealthBar {
+                boss: boss_health_bar(mob),
+            },
+        );
+    }
+}
+
+/// Bosses enrage again from the start after losing all targets
+fn set_boss_engaged(mob: &mut Mob, engaged_at: Option<Tick>, writer: &mut RoomWriter) {
+    let Some(boss) = &mut mob.boss else {
+        return;
+    };
+    if boss.engaged_at.is_some() == engaged_at.is_some() {
+        return;
+    }
+    boss.engaged_at = engaged_at;
+    boss.enraged = false;
+    writer.tell(
+        RoomWriterTarget::All,
+        PlayerEvent::BossHealthBar {
+            boss: boss_health_bar(mob),
+        },
+    );
+}
+
+/// Only while the boss is engaged
+pub fn boss_health_bar(mob: &Mob) -> Option<BossHealthBar> {
+    let boss = mob.boss.filter(|boss| boss.engaged_at.is_some())?;
+    let boss_config = mob.template.boss.as_ref()?;
+    Some(BossHealthBar {
+        object_id: mob.id,
+        name: boss_config.name.clone(),
+        health: mob.health,
+        max_health: mob.template.max_health,
+        enraged: boss.enraged,
+    })
+}
+
+fn summon_position(position: Vector2<f32>, map: &RoomMap, rng: &mut Rng) -> Vector2<u32> {
+    let center = position.map(|c| c as i32);
+    let candidates = (-SUMMON_DISTANCE..=SUMMON_DISTANCE)
+        .flat_map(|dy| (-SUMMON_DISTANCE..=SUMMON_DISTANCE).map(move |dx| Vector2::new(dx, dy)))
+        .map(|offset| center + offset)
+        .filter(|tile| tile.x >= 0 && tile.y >= 0 && *tile != center)
+        .map(|tile| tile.map(|c| c as u32))
+        .filter(|tile| !mob_blocked_at(map, tile.cast().add_scalar(0.5)))
+        .collect::<Vec<_>>();
+    rng.choice(&candidates)
+        .copied()
+        .unwrap_or_else(|| position.map(|c| c as u32))
 }
 
 fn is_valid_attack_target(mob: &Mob, player: &Player) -> bool {
-    mob.in_movement_range(player.local_movement.position)
+    !player.is_dead() && mob.in_movement_range(player.local_movement.position)
 }
 
 fn change_direction_to_target(mob: &mut Mob, attack_target: &Player, map: &RoomMap) -> bool {
     let direction = attack_target.local_movement.position - mob.movement.position;
     let direction = Direction8::from_vector(direction);
     let next_tile = mob.movement.position + direction.to_unit_vector();
-    let can_move = !mmo_common::room::collision_at(map.size, &map.collisions, next_tile);
+    let can_move = !mob_blocked_at(map, next_tile);
     if can_move {
         if mob.movement.direction != Some(direction) {
             mob.movement.direction = Some(direction);
@@ -321,14 +609,57 @@ fn choose_direction(mob: &Mob, map: &RoomMap) -> Option<Direction8> {
         .filter(|dir| {
             let next_tile = current_tile + dir.to_neighbor_vector();
             let in_movement_range = mob.in_movement_range(next_tile);
-            let collides = mmo_common::room::collision_at(map.size, &map.collisions, next_tile);
+            let collides = mob_blocked_at(map, next_tile);
             in_movement_range && !collides
         })
         .collect::<Vec<_>>();
     rng.choice(&candidates).copied()
 }
 
-fn choose_attack(mob: &Mob) -> u8 {
-    let mut rng = fastrand::Rng::new();
-    rng.u8(0..mob.template.attacks.len() as u8)
+/// Mobs can't swim
+pub fn mob_blocked_at(map: &RoomMap, position: Vector2<f32>) -> bool {
+    mmo_common::room::collision_at(map.size, &map.collisions, position)
+        || mmo_common::room::terrain_at(map.size, &map.terrain, position).water
+}
+
+/// Bosses follow the rotation of their phase, other mobs pick any attack
+fn choose_attack(mob: &mut Mob, rng: &mut Rng) -> u8 {
+    let template = mob.template.clone();
+    let attack_count = template.attacks.len();
+    if let (Some(boss), Some(boss_config)) = (&mut mob.boss, &template.boss) {
+        let rotation = boss_config
+            .phases
+            .get(boss.phase)
+            .map(|phase| &phase.rotation);
+        let attack_index = match rotation {
+            Some(AttackRotation::Sequence { attacks }) if !attacks.is_empty() => {
+                let attack_index = attacks[boss.rotation_index % attacks.len()];
+                boss.rotation_index += 1;
+                Some(attack_index)
+            }
+            Some(AttackRotation::Weighted { weights }) => {
+                choose_weighted(weights, rng).map(|i| i as u8)
+            }
+            _ => None,
+        };
+        if let Some(attack_index) = attack_index.filter(|&i| (i as usize) < attack_count) {
+            return attack_index;
+        }
+    }
+    rng.u8(0..attack_count as u8)
+}
+
+fn choose_weighted(weights: &[u32], rng: &mut Rng) -> Option<usize> {
+    let total: u32 = weights.iter().sum();
+    if total == 0 {
+        return None;
+    }
+    let mut roll = rng.u32(0..total);
+    for (i, &weight) in weights.iter().enumerate() {
+        if roll < weight {
+            return Some(i);
+        }
+        roll -= weight;
+    }
+    None
 }
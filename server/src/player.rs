@@ -1,18 +1,54 @@
 use std::sync::Arc;
 
-use mmo_common::{client_config::ClientConfig, player_event::PlayerEvent};
+use mmo_common::{client_config::ClientConfig, player_event::PlayerEvent, room::WorldMapRoom};
 use tokio::sync::mpsc;
 
-use crate::server_context::ServerContext;
+use crate::{
+    server_context::{ServerContext, World},
+    tick, util,
+};
 
 pub type PlayerConnection = mpsc::Sender<Vec<Arc<PlayerEvent>>>;
 
 // TODO: use Arc?
 pub fn client_config(server_context: &ServerContext) -> ClientConfig {
     ClientConfig {
-        server_git_sha: option_env!("VERGEN_GIT_SHA").unwrap_or("???").to_string(),
+        server_git_sha: util::git_sha().to_string(),
         asset_paths: server_context.asset_paths.paths.clone(),
         animations: server_context.animations.clone(),
+        tile_animations: server_context.tile_animations.clone(),
         player_attack_animation_index: server_context.player.attack_animation_index,
+        player_velocity: server_context.player.velocity,
+        slow_terrain_velocity_factor: server_context.slow_terrain_velocity_factor,
+        tick_interval: tick::TICK_INTERVAL.as_secs_f32(),
+        world_map: world_map(&server_context.world),
+        equipment: server_context.carried_equipment(),
     }
 }
+
+fn world_map(world: &World) -> Vec<WorldMapRoom> {
+    let mut rooms: Vec<WorldMapRoom> = world
+        .maps
+        .iter()
+        .map(|(&room_id, map)| {
+            let mut connections: Vec<_> = map
+                .portals
+                .iter()
+                .map(|portal| portal.target_room_id)
+                .chain(map.neighbours.iter().map(|neighbour| neighbour.room_id))
+                .filter(|&target| target != room_id)
+                .collect();
+            connections.sort_unstable_by_key(|target| target.0);
+            connections.dedup();
+            WorldMapRoom {
+                room_id,
+                name: map.name.clone(),
+                position: map.world_position,
+                size: map.size,
+                connections,
+            }
+        })
+        .collect();
+    rooms.sort_unstable_by_key(|room| room.room_id.0);
+    rooms
+}
@@ -0,0 +1,112 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::Path,
+};
+
+use eyre::{Result, WrapErr};
+use mmo_common::room::{self, RoomId};
+use nalgebra::Vector2;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    equipment_logic,
+    room_state::{Player, SpawnPoint},
+    server_context::{PlayerEquipment, ServerContext},
+};
+
+pub static PLAYER_SAVE_PATH: &str = "saves/players.json";
+pub static BANS_PATH: &str = "saves/bans.json";
+
+/// State of a player that was online when the server last shut down with them, keyed by
+/// lowercase name. There are no accounts, so whoever joins first with the name gets the save.
+pub type SavedPlayers = HashMap<String, SavedPlayer>;
+
+/// Lowercase names that may not join
+pub type Bans = BTreeSet<String>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedPlayer {
+    /// `None` for players in an instance, they continue from a start point
+    pub location: Option<(RoomId, Vector2<f32>)>,
+    pub health: i32,
+    pub equipment: PlayerEquipment,
+    pub checkpoint: Option<SpawnPoint>,
+}
+
+impl SavedPlayer {
+    pub fn new(player: &Player, room_id: Option<RoomId>) -> Self {
+        Self {
+            location: room_id.map(|room_id| (room_id, player.local_movement.position)),
+            health: player.health,
+            equipment: player.equipment.clone(),
+            checkpoint: player.checkpoint.clone(),
+        }
+    }
+}
+
+pub fn load() -> Result<SavedPlayers> {
+    load_json(PLAYER_SAVE_PATH)
+}
+
+pub fn save(players: &SavedPlayers) -> Result<()> {
+    save_json(PLAYER_SAVE_PATH, players)
+}
+
+pub fn load_bans() -> Result<Bans> {
+    load_json(BANS_PATH)
+}
+
+pub fn save_bans(bans: &Bans) -> Result<()> {
+    save_json(BANS_PATH, bans)
+}
+
+fn load_json<T: DeserializeOwned + Default>(path: &str) -> Result<T> {
+    if !Path::new(path).exists() {
+        return Ok(T::default());
+    }
+    let json = std::fs::read_to_string(path)?;
+    serde_json::from_str(&json).wrap_err_with(|| format!("Invalid {path}"))
+}
+
+/// Written to a temporary file first, so a failed write doesn't lose the previous save
+fn save_json<T: Serialize>(path: &str, value: &T) -> Result<()> {
+    let path = Path::new(path);
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let tmp_path = path.with_extension("json.tmp");
+    std::fs::write(&tmp_path, serde_json::to_string_pretty(value)?)?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// Returns where the player continues, or `None` if the saved position is no longer valid
+pub fn restore(player: &mut Player, saved: SavedPlayer, ctx: &ServerContext) -> Option<RoomId> {
+    player.equipment = saved.equipment;
+    equipment_logic::remove_unknown_equipment(player, ctx);
+    player.stats = ctx.player_stats(&player.equipment);
+    // Dead players would have respawned by now
+    player.health = if saved.health > 0 {
+        saved.health.min(player.stats.max_health)
+    } else {
+        player.stats.max_health
+    };
+    player.velocity = player.stats.velocity;
+    player.checkpoint = saved.checkpoint.filter(|checkpoint| {
+        ctx.world
+            .maps
+            .get(&checkpoint.room_id)
+            .is_some_and(|map| map.checkpoints.contains(checkpoint))
+    });
+
+    let (room_id, position) = saved.location?;
+    let map = ctx.world.maps.get(&room_id)?;
+    let free = room::in_bounds(map.size, position)
+        && !room::collision_at(map.size, &map.collisions, position);
+    if !free {
+        return None;
+    }
+    player.local_movement.position = position;
+    player.remote_movement.position = position;
+    Some(room_id)
+}
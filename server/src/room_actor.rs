@@ -1,17 +1,21 @@
 use std::collections::HashMap;
+use std::ops::ControlFlow;
 use std::sync::Arc;
+use std::time::Duration;
 
 use mmo_common::object::ObjectId;
 use mmo_common::player_command::RoomCommand;
 use mmo_common::rle;
-use mmo_common::room::{RoomId, RoomSync};
-use tokio::sync::mpsc;
+use mmo_common::room::{NeighbourSync, RoomId, RoomSync};
+use tokio::sync::{mpsc, oneshot};
 use tracing::instrument;
 
-use crate::room_state::{Player, RoomMap, RoomState, UpstreamMessage};
+use crate::admin_console::AdminReply;
+use crate::admin_logic::{self, RoomAdminCommand};
+use crate::room_state::{Player, RoomMap, RoomSnapshot, RoomState, UpstreamMessage};
 use crate::room_writer::{RoomWriter, RoomWriterTarget};
-use crate::server_context::ServerContext;
-use crate::{mob_logic, room_logic, tick};
+use crate::server_context::{ServerContext, World};
+use crate::{metrics, mob_logic, room_logic, tick};
 
 #[derive(Debug)]
 pub enum Message {
@@ -25,12 +29,33 @@ pub enum Message {
         player_id: ObjectId,
         command: RoomCommand,
     },
+    PlayerLatencyMeasured {
+        player_id: ObjectId,
+        latency: Duration,
+    },
+    ServerContextReloaded {
+        server_context: Arc<ServerContext>,
+    },
+    AdminCommand {
+        command: RoomAdminCommand,
+        reply: oneshot::Sender<AdminReply>,
+    },
+    SetFrozen {
+        frozen: bool,
+    },
+    /// Hands over the remaining players and stops the room
+    Shutdown {
+        players: oneshot::Sender<Vec<Player>>,
+    },
 }
 
+/// Shared rooms send a snapshot upstream when they stop, instances don't
 #[instrument(skip_all, fields(room_id = room_id.0))]
 pub async fn run(
     room_id: RoomId,
+    instance_owner: Option<ObjectId>,
     server_context: Arc<ServerContext>,
+    snapshot: Option<RoomSnapshot>,
     mut messages: mpsc::Receiver<Message>,
     mut tick_receiver: tick::Receiver,
     upstream_sender: mpsc::Sender<UpstreamMessage>,
@@ -44,8 +69,22 @@ pub async fn run(
 
     let now = first_tick.monotonic_time;
     let map = server_context.world.maps.get(&room_id).unwrap().clone();
-    let room = make_room_sync(room_id, &map);
-    let mobs = mob_logic::populate_mobs(&map, &server_context, now);
+    let room = make_room_sync(room_id, &map, &server_context.world);
+    let snapshot = snapshot.filter(|snapshot| {
+        Arc::ptr_eq(&snapshot.server_context, &server_context) && snapshot.room_id == room_id
+    });
+    let (mobs, mob_respawns, spawn_area_timers) = match snapshot {
+        Some(snapshot) => {
+            tracing::debug!("Restoring snapshot");
+            let mobs = mob_logic::restore_mobs(snapshot.mobs, now);
+            (mobs, snapshot.mob_respawns, snapshot.spawn_area_timers)
+        }
+        None => (
+            mob_logic::populate_mobs(&map, &server_context, now),
+            vec![],
+            mob_logic::spawn_area_timers(&map),
+        ),
+    };
     let mut state = RoomState {
         server_context,
         map,
@@ -53,15 +92,25 @@ pub async fn run(
         last_tick: first_tick,
         players: HashMap::new(),
         mobs,
-        mob_respawns: vec![],
+        mob_respawns,
+        spawn_area_timers,
+        rng: fastrand::Rng::new(),
+        pending_server_context: None,
+        frozen: false,
+        instance_owner,
     };
     let mut writer = RoomWriter::new();
+    let mut shut_down = false;
 
     loop {
         tokio::select! {
             message = messages.recv() => {
                 if let Some(message) = message {
-                    handle_message(&mut state, &mut writer, &upstream_sender, message).await;
+                    let flow = handle_message(&mut state, &mut writer, &upstream_sender, message).await;
+                    if flow.is_break() {
+                        shut_down = true;
+                        break;
+                    }
                 } else {
                     break;
                 }
@@ -69,9 +118,11 @@ pub async fn run(
             tick = tick_receiver.recv() => {
                 match tick {
                     Ok(tick) => {
+                        let started_at = std::time::Instant::now();
                         state.last_tick = tick;
                         room_logic::on_tick(&mut state, &mut writer);
                         flush_writer(&mut writer, &state, &upstream_sender).await;
+                        metrics::record_tick_duration(started_at.elapsed());
                     }
                     Err(err) => {
                         tracing::error!("Error receiving tick: {err}");
@@ -84,6 +135,22 @@ pub async fn run(
     if !state.players.is_empty() {
         tracing::warn!("Terminating but still have {} players", state.players.len());
     }
+    // The whole server is stopping, so nothing would restore the snapshot
+    if instance_owner.is_none() && !shut_down {
+        let snapshot = RoomSnapshot {
+            room_id,
+            server_context: state.server_context,
+            mobs: state.mobs,
+            mob_respawns: state.mob_respawns,
+            spawn_area_timers: state.spawn_area_timers,
+        };
+        if let Err(err) = upstream_sender
+            .send(UpstreamMessage::RoomStopped { snapshot })
+            .await
+        {
+            tracing::warn!("Failed to send room snapshot: {err}");
+        }
+    }
 
     tracing::debug!("Terminated");
 }
@@ -93,7 +160,7 @@ async fn handle_message(
     writer: &mut RoomWriter,
     upstream_sender: &mpsc::Sender<UpstreamMessage>,
     message: Message,
-) {
+) -> ControlFlow<()> {
     match message {
         Message::PlayerConnected { player } => {
             room_logic::on_connect(player, state, writer);
@@ -113,7 +180,34 @@ async fn handle_message(
                 tracing::error!(player_id = player_id.0, "Player not found");
             }
         }
+
+        Message::PlayerLatencyMeasured { player_id, latency } => {
+            if let Some(player) = state.players.get_mut(&player_id) {
+                player.latency = latency;
+            }
+        }
+
+        Message::ServerContextReloaded { server_context } => {
+            state.pending_server_context = Some(server_context);
+        }
+
+        Message::AdminCommand { command, reply } => {
+            let result = admin_logic::on_command(command, state, writer);
+            flush_writer(writer, state, upstream_sender).await;
+            let _ = reply.send(result);
+        }
+
+        Message::SetFrozen { frozen } => {
+            admin_logic::set_frozen(frozen, state, writer);
+            flush_writer(writer, state, upstream_sender).await;
+        }
+
+        Message::Shutdown { players } => {
+            let _ = players.send(state.players.drain().map(|(_, player)| player).collect());
+            return ControlFlow::Break(());
+        }
     }
+    ControlFlow::Continue(())
 }
 
 // TODO: less awaits?
@@ -166,7 +260,23 @@ async fn flush_writer(
     }
 }
 
-fn make_room_sync(room_id: RoomId, map: &RoomMap) -> RoomSync {
+pub fn make_room_sync(room_id: RoomId, map: &RoomMap, world: &World) -> RoomSync {
+    let mut room = make_map_sync(room_id, map);
+    room.neighbours = map
+        .neighbours
+        .iter()
+        .filter_map(|neighbour| {
+            let neighbour_map = world.maps.get(&neighbour.room_id)?;
+            Some(NeighbourSync {
+                offset: neighbour.offset,
+                room: make_map_sync(neighbour.room_id, neighbour_map),
+            })
+        })
+        .collect();
+    room
+}
+
+fn make_map_sync(room_id: RoomId, map: &RoomMap) -> RoomSync {
     let bg_dense_layers = map
         .bg_dense_layers
         .iter()
@@ -175,6 +285,9 @@ fn make_room_sync(room_id: RoomId, map: &RoomMap) -> RoomSync {
     let bg_sparse_layer = map.bg_sparse_layer.clone();
     let fg_sparse_layer = map.fg_sparse_layer.clone();
     let collisions = rle::encode(&map.collisions);
+    let terrain = rle::encode(&map.terrain);
+    let portals = map.portals.iter().map(|portal| portal.position).collect();
+    let lights = map.lights.clone();
 
     RoomSync {
         room_id,
@@ -183,5 +296,9 @@ fn make_room_sync(room_id: RoomId, map: &RoomMap) -> RoomSync {
         bg_sparse_layer,
         fg_sparse_layer,
         collisions,
+        terrain,
+        neighbours: vec![],
+        portals,
+        lights,
     }
 }
@@ -1,25 +1,32 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 
 use mmo_common::{
+    equipment::PlayerStats,
     object::{Direction4, ObjectId, ObjectType},
     player_command::RoomCommand,
     player_event::PlayerEvent,
-    room,
+    room::{self, RoomId},
 };
 use nalgebra::Vector2;
 use tokio::time::Instant;
 use tracing::instrument;
 
 use crate::{
-    combat_logic, mob_logic,
+    combat_logic, equipment_logic, mob_logic, room_actor,
     room_state::{
-        LocalMovement, Player, Portal, RemoteMovement, RoomMap, RoomState, UpstreamMessage,
+        BossState, LocalMovement, Player, Portal, RemoteMovement, RoomMap, RoomState, SpawnPoint,
+        UpstreamMessage,
     },
     room_writer::{RoomWriter, RoomWriterTarget},
-    server_context::ServerContext,
-    tick::TickRate,
+    server_context::{RespawnLocation, ServerContext},
+    tick::{TickEvent, TickRate},
+    util,
 };
 
+static MAX_MOVE_POSITION_ERROR: f32 = 1.0;
+static WORLD_CLOCK_SYNC_RATE: TickRate = TickRate(300);
+
 #[instrument(skip_all, fields(player_id = player.id.0))]
 pub fn on_connect(mut player: Player, state: &mut RoomState, writer: &mut RoomWriter) {
     let now = Instant::now();
@@ -27,14 +34,18 @@ pub fn on_connect(mut player: Player, state: &mut RoomState, writer: &mut RoomWr
     player.remote_movement.received_at = now;
     player.remote_movement.direction = None;
     player.remote_movement.look_direction = Direction4::Down;
+    player.position_history.clear();
+    player.velocity = player_velocity(
+        &state.map,
+        &state.server_context,
+        &player.stats,
+        player.local_movement.position,
+    );
     player_entered(player, state, writer);
 }
 
 fn player_entered(player: Player, state: &mut RoomState, writer: &mut RoomWriter) {
     let player_id = player.id;
-    let player_local_movement = player.local_movement;
-    let player_remote_movement = player.remote_movement;
-    let now = Instant::now();
 
     writer.tell_many(
         RoomWriterTarget::AllExcept(player_id),
@@ -42,22 +53,24 @@ fn player_entered(player: Player, state: &mut RoomState, writer: &mut RoomWriter
             PlayerEvent::ObjectAppeared {
                 object_id: player_id,
                 object_type: ObjectType::Player,
-                animation_id: state.server_context.player_animation,
+                name: player.name.clone(),
+                animation_id: state.server_context.player_animation(player.appearance),
+                overlay_animation_ids: state
+                    .server_context
+                    .overlay_animation_ids(&player.equipment),
                 health: player.health,
-                max_health: player.max_health,
-            },
-            PlayerEvent::ObjectMovementChanged {
-                object_id: player_id,
-                position: player_local_movement.position,
-                velocity: state.server_context.player.velocity,
-                direction: player_remote_movement.direction,
-                look_direction: player_remote_movement.look_direction,
+                max_health: player.stats.max_health,
             },
+            player_movement_changed(&player, state.last_tick),
         ],
     );
 
     state.players.insert(player_id, player);
+    sync_room(player_id, state, writer);
+}
 
+/// Sends the whole room as seen by a player who just entered it
+fn sync_room(player_id: ObjectId, state: &RoomState, writer: &mut RoomWriter) {
     writer.tell(
         RoomWriterTarget::Player(player_id),
         PlayerEvent::RoomEntered {
@@ -65,34 +78,53 @@ fn player_entered(player: Player, state: &mut RoomState, writer: &mut RoomWriter
         },
     );
     for player_in_room in state.players.values() {
-        let position =
-            interpolate_position(&state.server_context, player_in_room.remote_movement, now);
         writer.tell_many(
             RoomWriterTarget::Player(player_id),
             &[
                 PlayerEvent::ObjectAppeared {
                     object_id: player_in_room.id,
                     object_type: ObjectType::Player,
-                    animation_id: state.server_context.player_animation,
+                    name: player_in_room.name.clone(),
+                    animation_id: state
+                        .server_context
+                        .player_animation(player_in_room.appearance),
+                    overlay_animation_ids: state
+                        .server_context
+                        .overlay_animation_ids(&player_in_room.equipment),
                     health: player_in_room.health,
-                    max_health: player_in_room.max_health,
-                },
-                PlayerEvent::ObjectMovementChanged {
-                    object_id: player_in_room.id,
-                    position: position.position,
-                    velocity: state.server_context.player.velocity,
-                    direction: player_in_room.remote_movement.direction,
-                    look_direction: player_in_room.remote_movement.look_direction,
+                    max_health: player_in_room.stats.max_health,
                 },
+                player_movement_changed(player_in_room, state.last_tick),
             ],
         );
     }
     for mob in state.mobs.iter() {
         writer.tell_many(
             RoomWriterTarget::Player(player_id),
-            &mob_logic::mob_appeared_events(mob),
+            &mob_logic::mob_appeared_events(mob, state.last_tick.tick),
+        );
+        if let Some(boss) = mob_logic::boss_health_bar(mob) {
+            writer.tell(
+                RoomWriterTarget::Player(player_id),
+                PlayerEvent::BossHealthBar { boss: Some(boss) },
+            );
+        }
+    }
+    if let Some(player) = state.players.get(&player_id) {
+        writer.tell(
+            RoomWriterTarget::Player(player_id),
+            equipment_logic::stats_changed(player),
         );
     }
+    writer.tell(RoomWriterTarget::Player(player_id), world_clock(state));
+}
+
+fn world_clock(state: &RoomState) -> PlayerEvent {
+    let world_clock = &state.server_context.world_clock;
+    PlayerEvent::WorldClock {
+        time_of_day: world_clock.time_of_day(state.last_tick.tick),
+        day_length: world_clock.day_length,
+    }
 }
 
 #[instrument(skip_all, fields(player_id = player_id.0))]
@@ -126,13 +158,37 @@ pub fn on_command(
     state: &mut RoomState,
     writer: &mut RoomWriter,
 ) {
+    if state
+        .players
+        .get(&player_id)
+        .is_some_and(|player| player.is_dead())
+    {
+        return;
+    }
+    // Players in a frozen room can still change their equipment
+    if state.frozen {
+        match &command {
+            RoomCommand::Move {
+                sequence_number, ..
+            } => {
+                if let Some(player) = state.players.get_mut(&player_id) {
+                    player.last_move_sequence_number = *sequence_number;
+                    stop_player(player, Instant::now(), state.last_tick, writer);
+                }
+                return;
+            }
+            RoomCommand::Attack => return,
+            RoomCommand::Equip { .. } | RoomCommand::Unequip { .. } => {}
+        }
+    }
+
     match command {
         RoomCommand::Move {
+            sequence_number,
             position,
             direction,
             look_direction,
         } => {
-            // TODO: at least a basic check whether the position is plausible
             let now = Instant::now();
 
             let player = if let Some(player) = state.players.get_mut(&player_id) {
@@ -141,35 +197,67 @@ pub fn on_command(
                 return;
             };
 
+            let expected_position =
+                interpolate_position(player.remote_movement, player.velocity, now).position;
+            let position =
+                if util::in_distance(position, expected_position, MAX_MOVE_POSITION_ERROR) {
+                    position
+                } else {
+                    tracing::debug!(
+                        "Implausible move position {position:?}, expected {expected_position:?}"
+                    );
+                    expected_position
+                };
+
+            player.last_move_sequence_number = sequence_number;
             player.remote_movement = RemoteMovement {
                 position,
                 direction,
                 look_direction,
                 received_at: now,
             };
+            player.velocity =
+                player_velocity(&state.map, &state.server_context, &player.stats, position);
 
-            if room::collision_at(state.map.size, &state.map.collisions, position) {
-                prevent_collision(player, now, &state.server_context, writer);
+            if let Some((target_room_id, target_position)) =
+                find_neighbour_target(&state.map, &state.server_context, position)
+            {
+                move_player_to_room(
+                    player_id,
+                    target_room_id,
+                    target_position,
+                    InstanceTransition::Stay,
+                    state,
+                    writer,
+                );
+            } else if room::collision_at(state.map.size, &state.map.collisions, position) {
+                stop_player(player, now, state.last_tick, writer);
             } else if let Some(portal) =
                 find_player_portal(&state.map, player.local_movement.position, position)
             {
-                let portal = portal.clone();
-                move_player_through_portal(player_id, &portal, state, writer);
+                let (target_room_id, target_position, transition) = portal_target(portal);
+                move_player_to_room(
+                    player_id,
+                    target_room_id,
+                    target_position,
+                    transition,
+                    state,
+                    writer,
+                );
             } else {
                 player.local_movement = LocalMovement {
                     position,
                     updated_at: now,
                 };
+                activate_checkpoint(player, &state.map, writer);
 
                 writer.tell(
                     RoomWriterTarget::AllExcept(player_id),
-                    PlayerEvent::ObjectMovementChanged {
-                        object_id: player_id,
-                        position: player.local_movement.position,
-                        velocity: state.server_context.player.velocity,
-                        direction: player.remote_movement.direction,
-                        look_direction: player.remote_movement.look_direction,
-                    },
+                    player_movement_changed(player, state.last_tick),
+                );
+                writer.tell(
+                    RoomWriterTarget::Player(player_id),
+                    movement_acknowledged(player),
                 );
             }
         }
@@ -180,21 +268,134 @@ pub fn on_command(
                 PlayerEvent::ObjectAnimationAction {
                     object_id: player_id,
                     animation_index: state.server_context.player.attack_animation_index,
+                    tick: state.last_tick.tick.0,
                 },
             );
         }
+        RoomCommand::Equip { item_id } => {
+            equipment_logic::equip(player_id, item_id, state, writer);
+        }
+        RoomCommand::Unequip { slot } => {
+            equipment_logic::unequip(player_id, slot, state, writer);
+        }
     }
 }
 
 pub fn on_tick(state: &mut RoomState, writer: &mut RoomWriter) {
+    if let Some(server_context) = state.pending_server_context.take() {
+        apply_server_context(server_context, state, writer);
+    }
+    if state.frozen {
+        return;
+    }
+
     if state.last_tick.tick.is_nth(TickRate(10)) {
         mob_logic::respawn_mobs(state, writer);
     }
+    // Clients advance the clock on their own, this only corrects drift
+    if state.last_tick.tick.is_nth(WORLD_CLOCK_SYNC_RATE) && !state.players.is_empty() {
+        writer.tell(RoomWriterTarget::All, world_clock(state));
+    }
 
     move_players(state, writer);
     combat_logic::heal_players(state, writer);
     mob_logic::on_tick(state, writer);
     handle_dead_players(state, writer);
+    record_position_history(state);
+}
+
+#[instrument(skip_all, fields(room_id = state.room.room_id.0))]
+fn apply_server_context(
+    server_context: Arc<ServerContext>,
+    state: &mut RoomState,
+    writer: &mut RoomWriter,
+) {
+    let room_id = state.room.room_id;
+    let old_server_context = std::mem::replace(&mut state.server_context, server_context);
+    let ctx = state.server_context.clone();
+
+    let Some(map) = ctx.world.maps.get(&room_id).cloned() else {
+        tracing::warn!("Room was removed, moving players to the start room");
+        let player_ids = state.players.keys().copied().collect::<Vec<_>>();
+        for player_id in player_ids {
+            if let Some(player) = remove_player(player_id, &mut state.players, writer) {
+                send_player_to_start(player, state, writer);
+            }
+        }
+        return;
+    };
+
+    let now = state.last_tick.monotonic_time;
+    for player in state.players.values_mut() {
+        equipment_logic::remove_unknown_equipment(player, &ctx);
+        equipment_logic::equipment_changed(player, &map, &ctx, state.last_tick, writer);
+    }
+    writer.tell(RoomWriterTarget::All, world_clock(state));
+
+    let map_changed = *map != *state.map;
+    let mut animations_changed = old_server_context.player_appearances != ctx.player_appearances;
+    // Neighbours may have changed even if this map didn't
+    state.room = room_actor::make_room_sync(room_id, &map, &ctx.world);
+    if map_changed {
+        state.map = map;
+        state.mobs = mob_logic::populate_mobs(&state.map, &ctx, now);
+        state.mob_respawns.clear();
+        state.spawn_area_timers = mob_logic::spawn_area_timers(&state.map);
+    } else {
+        for mob in state.mobs.iter_mut() {
+            if let Some(template) = ctx.mob_templates.get(&mob.spawn.mob_template) {
+                mob.template = template.clone();
+                mob.health = mob.health.min(template.max_health);
+                let was_engaged = |boss: BossState| boss.engaged_at.is_some();
+                if template.boss.is_none() && mob.boss.take().is_some_and(was_engaged) {
+                    writer.tell(
+                        RoomWriterTarget::All,
+                        PlayerEvent::BossHealthBar { boss: None },
+                    );
+                }
+            }
+            if let Some(&animation_id) = ctx.mob_animations.get(&mob.template.animation_id) {
+                animations_changed |= animation_id != mob.animation_id;
+                mob.animation_id = animation_id;
+            }
+        }
+    }
+
+    if map_changed || animations_changed {
+        tracing::info!("Resyncing players after reload");
+        let player_ids = state.players.keys().copied().collect::<Vec<_>>();
+        for player_id in player_ids {
+            let position = state.players[&player_id].local_movement.position;
+            if room::collision_at(state.map.size, &state.map.collisions, position) {
+                if let Some(player) = remove_player(player_id, &mut state.players, writer) {
+                    send_player_to_start(player, state, writer);
+                }
+            }
+        }
+        for player in state.players.values_mut() {
+            player.remote_movement = RemoteMovement {
+                position: player.local_movement.position,
+                direction: None,
+                look_direction: player.remote_movement.look_direction,
+                received_at: now,
+            };
+        }
+        for player_id in state.players.keys() {
+            sync_room(*player_id, state, writer);
+        }
+    }
+}
+
+fn record_position_history(state: &mut RoomState) {
+    let now = state.last_tick.monotonic_time;
+    for player in state.players.values_mut() {
+        player
+            .position_history
+            .record(now, player.local_movement.position);
+    }
+    for mob in state.mobs.iter_mut() {
+        mob.position_history.record(now, mob.movement.position);
+    }
 }
 
 fn move_players(state: &mut RoomState, writer: &mut RoomWriter) {
@@ -204,41 +405,64 @@ fn move_players(state: &mut RoomState, writer: &mut RoomWriter) {
 
     for player in state.players.values_mut() {
         let last_position = player.local_movement.position;
-        let local_movement =
-            interpolate_position(&state.server_context, player.remote_movement, now);
+        let local_movement = interpolate_position(player.remote_movement, player.velocity, now);
         let crossed_tile =
             last_position.map(|a| a as u32) != local_movement.position.map(|a| a as u32);
 
-        if room::collision_at(
+        if let Some((target_room_id, target_position)) =
+            find_neighbour_target(&state.map, &state.server_context, local_movement.position)
+        {
+            players_left.push((
+                player.id,
+                target_room_id,
+                target_position,
+                InstanceTransition::Stay,
+            ));
+        } else if room::collision_at(
             state.map.size,
             &state.map.collisions,
             local_movement.position,
         ) {
-            prevent_collision(player, now, &state.server_context, writer);
+            stop_player(player, now, state.last_tick, writer);
         } else if let Some(portal) =
             find_player_portal(&state.map, last_position, local_movement.position)
         {
-            players_left.push((player.id, portal.clone()));
+            let (target_room_id, target_position, transition) = portal_target(portal);
+            players_left.push((player.id, target_room_id, target_position, transition));
         } else {
             player.local_movement = local_movement;
 
             if crossed_tile {
+                let velocity = player_velocity(
+                    &state.map,
+                    &state.server_context,
+                    &player.stats,
+                    local_movement.position,
+                );
+                if velocity != player.velocity {
+                    // Continue from here, so the new velocity only applies to the rest of the movement
+                    player.remote_movement.position = local_movement.position;
+                    player.remote_movement.received_at = now;
+                    player.velocity = velocity;
+                }
+                activate_checkpoint(player, &state.map, writer);
                 writer.tell(
                     RoomWriterTarget::AllExcept(player.id),
-                    PlayerEvent::ObjectMovementChanged {
-                        object_id: player.id,
-                        position: local_movement.position,
-                        velocity: state.server_context.player.velocity,
-                        direction: player.remote_movement.direction,
-                        look_direction: player.remote_movement.look_direction,
-                    },
+                    player_movement_changed(player, state.last_tick),
                 );
             }
         }
     }
 
-    for (player_id, portal) in players_left {
-        move_player_through_portal(player_id, &portal, state, writer);
+    for (player_id, target_room_id, target_position, transition) in players_left {
+        move_player_to_room(
+            player_id,
+            target_room_id,
+            target_position,
+            transition,
+            state,
+            writer,
+        );
     }
 }
 
@@ -257,32 +481,110 @@ fn find_player_portal(
     }
 }
 
-fn move_player_through_portal(
+fn activate_checkpoint(player: &mut Player, map: &RoomMap, writer: &mut RoomWriter) {
+    let tile = player.local_movement.position.map(|a| a as u32);
+    let checkpoint = map
+        .checkpoints
+        .iter()
+        .find(|checkpoint| checkpoint.position.map(|a| a as u32) == tile);
+    if let Some(checkpoint) = checkpoint {
+        if player.checkpoint.as_ref() != Some(checkpoint) {
+            player.checkpoint = Some(checkpoint.clone());
+            writer.tell(
+                RoomWriterTarget::Player(player.id),
+                PlayerEvent::CheckpointActivated {
+                    name: checkpoint.name.clone(),
+                },
+            );
+        }
+    }
+}
+
+/// How moving to another room affects the instance a player is in
+#[derive(Debug, Clone, Copy)]
+enum InstanceTransition {
+    Enter,
+    Exit,
+    /// Stays in the current instance while the target belongs to it
+    Stay,
+}
+
+fn portal_target(portal: &Portal) -> (RoomId, Vector2<f32>, InstanceTransition) {
+    let transition = if portal.instance_entrance {
+        InstanceTransition::Enter
+    } else if portal.instance_exit {
+        InstanceTransition::Exit
+    } else {
+        InstanceTransition::Stay
+    };
+    (
+        portal.target_room_id,
+        portal.target_position.add_scalar(0.5),
+        transition,
+    )
+}
+
+/// Finds where a position past the edge of the room ends up in a neighbouring level
+fn find_neighbour_target(
+    map: &RoomMap,
+    ctx: &ServerContext,
+    position: Vector2<f32>,
+) -> Option<(RoomId, Vector2<f32>)> {
+    if room::in_bounds(map.size, position) {
+        return None;
+    }
+    map.neighbours.iter().find_map(|neighbour| {
+        let neighbour_map = ctx.world.maps.get(&neighbour.room_id)?;
+        let target_position = position - neighbour.offset.cast();
+        let blocked = room::collision_at(
+            neighbour_map.size,
+            &neighbour_map.collisions,
+            target_position,
+        );
+        (!blocked).then_some((neighbour.room_id, target_position))
+    })
+}
+
+pub fn teleport_player(
     player_id: ObjectId,
-    portal: &Portal,
+    target_room_id: RoomId,
+    target_position: Vector2<f32>,
     state: &mut RoomState,
     writer: &mut RoomWriter,
 ) {
+    move_player_to_room(
+        player_id,
+        target_room_id,
+        target_position,
+        InstanceTransition::Exit,
+        state,
+        writer,
+    );
+}
+
+fn move_player_to_room(
+    player_id: ObjectId,
+    target_room_id: RoomId,
+    target_position: Vector2<f32>,
+    transition: InstanceTransition,
+    state: &mut RoomState,
+    writer: &mut RoomWriter,
+) {
+    let instance_owner = target_instance_owner(player_id, target_room_id, transition, state);
     if let Some(player) = remove_player(player_id, &mut state.players, writer) {
-        let target_room_id = portal.target_room_id;
-        let target_position = portal.target_position.add_scalar(0.5);
         writer
             .upstream_messages
             .push(UpstreamMessage::PlayerLeftRoom {
-                sender_room_id: state.room.room_id,
                 player,
                 target_room_id,
                 target_position,
+                instance_owner,
             });
     }
 }
 
-fn prevent_collision(
-    player: &mut Player,
-    now: Instant,
-    ctx: &ServerContext,
-    writer: &mut RoomWriter,
-) {
+/// Keeps the player at its last valid position
+pub fn stop_player(player: &mut Player, now: Instant, tick: TickEvent, writer: &mut RoomWriter) {
     player.remote_movement = RemoteMovement {
         position: player.local_movement.position,
         direction: None,
@@ -290,26 +592,60 @@ fn prevent_collision(
         received_at: now, // TODO: mark that this was a correction?
     };
     writer.tell(
-        RoomWriterTarget::All,
-        PlayerEvent::ObjectMovementChanged {
-            object_id: player.id,
-            position: player.remote_movement.position,
-            velocity: ctx.player.velocity,
-            direction: player.remote_movement.direction,
-            look_direction: player.remote_movement.look_direction,
-        },
+        RoomWriterTarget::AllExcept(player.id),
+        player_movement_changed(player, tick),
+    );
+    writer.tell(
+        RoomWriterTarget::Player(player.id),
+        movement_acknowledged(player),
     );
 }
 
-fn interpolate_position(
+/// Reports the movement as of the tick, so clients can place it on the server timeline
+pub fn player_movement_changed(player: &Player, tick: TickEvent) -> PlayerEvent {
+    let movement =
+        interpolate_position(player.remote_movement, player.velocity, tick.monotonic_time);
+    PlayerEvent::ObjectMovementChanged {
+        object_id: player.id,
+        position: movement.position,
+        velocity: player.velocity,
+        direction: player.remote_movement.direction,
+        look_direction: player.remote_movement.look_direction,
+        tick: tick.tick.0,
+    }
+}
+
+fn movement_acknowledged(player: &Player) -> PlayerEvent {
+    PlayerEvent::MovementAcknowledged {
+        sequence_number: player.last_move_sequence_number,
+        position: player.remote_movement.position,
+        direction: player.remote_movement.direction,
+    }
+}
+
+pub fn player_velocity(
+    map: &RoomMap,
     ctx: &ServerContext,
+    stats: &PlayerStats,
+    position: Vector2<f32>,
+) -> f32 {
+    let terrain = room::terrain_at(map.size, &map.terrain, position);
+    stats.velocity * terrain.player_velocity_factor(ctx.slow_terrain_velocity_factor)
+}
+
+pub fn interpolate_position(
     remote_movement: RemoteMovement,
+    velocity: f32,
     now: Instant,
 ) -> LocalMovement {
     if let Some(direction) = remote_movement.direction {
-        let elapsed = now - remote_movement.received_at;
+        // Negative if the movement was received after `now`
+        let elapsed = match now.checked_duration_since(remote_movement.received_at) {
+            Some(elapsed) => elapsed.as_secs_f32(),
+            None => -(remote_movement.received_at - now).as_secs_f32(),
+        };
         let direction = direction.to_unit_vector();
-        let delta = direction * ctx.player.velocity * elapsed.as_secs_f32();
+        let delta = direction * velocity * elapsed;
         let position = remote_movement.position + delta;
         LocalMovement {
             position,
@@ -324,29 +660,127 @@ fn interpolate_position(
 }
 
 pub fn handle_dead_players(state: &mut RoomState, writer: &mut RoomWriter) {
-    let dead_player_ids = state
-        .players
-        .values()
-        .filter_map(|player| {
-            if player.health == 0 {
-                Some(player.id)
-            } else {
-                None
-            }
-        })
-        .collect::<Vec<_>>();
-
-    for dead_player_id in dead_player_ids {
-        if let Some(mut player) = remove_player(dead_player_id, &mut state.players, writer) {
-            player.health = player.max_health;
-            writer
-                .upstream_messages
-                .push(UpstreamMessage::PlayerLeftRoom {
-                    sender_room_id: state.room.room_id,
-                    player,
-                    target_room_id: state.server_context.world.start_room_id,
-                    target_position: state.server_context.world.start_position,
+    let tick = state.last_tick;
+    let respawn_delay = state.server_context.player.respawn_delay;
+
+    let mut respawning_player_ids = vec![];
+    for player in state.players.values_mut() {
+        if !player.is_dead() {
+            continue;
+        }
+        if player.respawn_at.is_none() {
+            // The player stays in the room until respawning, but can't act or be attacked
+            player.respawn_at = Some(tick.tick + respawn_delay);
+            player.remote_movement = RemoteMovement {
+                position: player.local_movement.position,
+                direction: None,
+                look_direction: player.remote_movement.look_direction,
+                received_at: tick.monotonic_time,
+            };
+            writer.tell(RoomWriterTarget::All, player_movement_changed(player, tick));
+            writer.tell(
+                RoomWriterTarget::Player(player.id),
+                PlayerEvent::Died {
+                    respawn_in: respawn_delay.as_secs_f32(),
+                },
+            );
+        }
+        if player
+            .respawn_at
+            .is_some_and(|respawn_at| tick.tick >= respawn_at)
+        {
+            respawning_player_ids.push(player.id);
+        }
+    }
+
+    for player_id in respawning_player_ids {
+        if let Some(mut player) = remove_player(player_id, &mut state.players, writer) {
+            player.health = player.stats.max_health;
+            player.respawn_at = None;
+            let respawn_point = find_respawn_point(&player, state);
+            send_player_to(
+                player,
+                &respawn_point,
+                InstanceTransition::Stay,
+                state,
+                writer,
+            );
+        }
+    }
+}
+
+fn find_respawn_point(player: &Player, state: &RoomState) -> SpawnPoint {
+    let world = &state.server_context.world;
+    let respawn_point = match state.server_context.player.respawn_location {
+        RespawnLocation::LastCheckpoint => player.checkpoint.as_ref(),
+        RespawnLocation::Nearest => {
+            let position = player.local_movement.position;
+            let room_start_points = world
+                .start_points
+                .iter()
+                .filter(|start_point| start_point.room_id == state.room.room_id);
+            state
+                .map
+                .checkpoints
+                .iter()
+                .chain(room_start_points)
+                .min_by(|a, b| {
+                    let distance_a = (a.position - position).norm_squared();
+                    let distance_b = (b.position - position).norm_squared();
+                    distance_a.total_cmp(&distance_b)
                 })
+                .or(player.checkpoint.as_ref())
         }
+    };
+    respawn_point
+        .unwrap_or_else(|| world.random_start_point())
+        .clone()
+}
+
+fn send_player_to_start(player: Player, state: &RoomState, writer: &mut RoomWriter) {
+    let start_point = state.server_context.world.random_start_point().clone();
+    send_player_to(
+        player,
+        &start_point,
+        InstanceTransition::Exit,
+        state,
+        writer,
+    );
+}
+
+fn send_player_to(
+    player: Player,
+    spawn_point: &SpawnPoint,
+    transition: InstanceTransition,
+    state: &RoomState,
+    writer: &mut RoomWriter,
+) {
+    let instance_owner = target_instance_owner(player.id, spawn_point.room_id, transition, state);
+    writer
+        .upstream_messages
+        .push(UpstreamMessage::PlayerLeftRoom {
+            player,
+            target_room_id: spawn_point.room_id,
+            target_position: spawn_point.position,
+            instance_owner,
+        })
+}
+
+/// Rooms outside the instance's level group are shared, so the player leaves the instance
+fn target_instance_owner(
+    player_id: ObjectId,
+    target_room_id: RoomId,
+    transition: InstanceTransition,
+    state: &RoomState,
+) -> Option<ObjectId> {
+    match transition {
+        InstanceTransition::Enter => Some(player_id),
+        InstanceTransition::Exit => None,
+        InstanceTransition::Stay => state.instance_owner.filter(|_| {
+            state
+                .server_context
+                .world
+                .in_same_instance_group(state.room.room_id, target_room_id)
+        }),
     }
 }
@@ -1,17 +1,23 @@
 use crate::{
-    mob::MobTemplate,
+    mob::{MobAttack, MobTemplate},
     player::PlayerConnection,
-    server_context::ServerContext,
-    tick::{Tick, TickEvent},
+    server_context::{PlayerEquipment, ServerContext},
+    tick::{Tick, TickDuration, TickEvent},
     util,
 };
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::Duration,
+};
 
 use mmo_common::{
+    equipment::PlayerStats,
     object::{Direction4, Direction8, ObjectId},
-    room::{ForegroundTile, RoomId, RoomSync, TileIndex},
+    room::{ForegroundTile, Light, RoomId, RoomSync, Terrain, TileIndex},
 };
 use nalgebra::Vector2;
+use serde::{Deserialize, Serialize};
 use tokio::time::Instant;
 
 #[derive(Debug, Clone)]
@@ -23,28 +29,75 @@ pub struct RoomState {
     pub players: HashMap<ObjectId, Player>,
     pub mobs: Vec<Mob>,
     pub mob_respawns: Vec<MobRespawn>,
+    /// When each spawn area of the map may spawn its next mob
+    pub spawn_area_timers: Vec<Tick>,
+    /// For combat rolls
+    pub rng: fastrand::Rng,
+    /// Applied at the start of the next tick
+    pub pending_server_context: Option<Arc<ServerContext>>,
+    /// Frozen by an operator, nothing moves or attacks
+    pub frozen: bool,
+    /// Set for instances, which don't keep a snapshot once stopped
+    pub instance_owner: Option<ObjectId>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct RoomMap {
+    pub name: String,
+    /// Top left corner in the LDtk world, in tiles
+    pub world_position: Vector2<i32>,
     pub size: Vector2<u32>,
     pub bg_dense_layers: Vec<Vec<TileIndex>>,
     pub bg_sparse_layer: Vec<(Vector2<u32>, TileIndex)>,
     pub fg_sparse_layer: Vec<ForegroundTile>,
     pub collisions: Vec<bool>,
+    pub terrain: Vec<Terrain>,
+    pub neighbours: Vec<Neighbour>,
     pub portals: Vec<Portal>,
     pub mob_spawns: Vec<Arc<MobSpawn>>,
+    pub mob_spawn_areas: Vec<MobSpawnArea>,
+    pub checkpoints: Vec<SpawnPoint>,
+    pub lights: Vec<Light>,
+}
+
+/// A player start point or a checkpoint
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct SpawnPoint {
+    pub name: String,
+    pub room_id: RoomId,
+    pub position: Vector2<f32>,
 }
 
 #[derive(Debug, Clone)]
 pub struct Player {
     pub id: ObjectId,
+    pub name: String,
+    /// Index into the player appearances of the server context
+    pub appearance: u32,
     pub connection: PlayerConnection,
     pub local_movement: LocalMovement,
     pub remote_movement: RemoteMovement,
+    /// Depends on the terrain and equipment
+    pub velocity: f32,
+    pub last_move_sequence_number: u32,
+    /// How long ago the player saw what it attacks, measured by the server: the round trip
+    /// plus the client's interpolation delay
+    pub latency: Duration,
+    pub position_history: PositionHistory,
     pub health: i32,
-    pub max_health: i32,
+    pub equipment: PlayerEquipment,
+    /// Derived from the equipment
+    pub stats: PlayerStats,
     pub last_damaged_at: Tick,
+    pub checkpoint: Option<SpawnPoint>,
+    /// Set while dead
+    pub respawn_at: Option<Tick>,
+}
+
+impl Player {
+    pub fn is_dead(&self) -> bool {
+        self.health == 0
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -54,10 +107,17 @@ pub struct Mob {
     pub spawn: Arc<MobSpawn>,
     pub animation_id: u32,
     pub movement: RemoteMovement,
+    pub position_history: PositionHistory,
     pub velocity: f32,
     pub attack_state: Option<MobAttackState>,
     pub health: i32,
     pub last_attacked_at: Tick,
+    /// Set for mobs with a boss template
+    pub boss: Option<BossState>,
+    /// Summoned and operator spawned mobs don't respawn
+    pub summoned: bool,
+    /// Index into the spawn areas of the map, area mobs are replaced by the area instead of respawning
+    pub spawn_area: Option<usize>,
 }
 
 impl Mob {
@@ -68,6 +128,28 @@ impl Mob {
             self.template.movement_range,
         )
     }
+
+    /// Enraged bosses hit harder
+    pub fn attack(&self, attack_index: u8) -> MobAttack {
+        let mut attack = self.template.attacks[attack_index as usize].clone();
+        let enrage = self.template.boss.as_ref().and_then(|boss| boss.enrage);
+        let enraged = self.boss.is_some_and(|boss| boss.enraged);
+        if let Some(enrage) = enrage.filter(|_| enraged) {
+            attack.damage = (attack.damage as f32 * enrage.damage_multiplier).round() as i32;
+        }
+        attack
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BossState {
+    /// Index into the phases of the boss template
+    pub phase: usize,
+    /// Position in a sequenced attack rotation
+    pub rotation_index: usize,
+    /// Set while the boss has a target
+    pub engaged_at: Option<Tick>,
+    pub enraged: bool,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -102,31 +184,110 @@ pub struct RemoteMovement {
     pub received_at: Instant,
 }
 
-#[derive(Debug, Clone)]
+static POSITION_HISTORY_LENGTH: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Default)]
+pub struct PositionHistory {
+    samples: VecDeque<(Instant, Vector2<f32>)>,
+}
+
+impl PositionHistory {
+    pub fn record(&mut self, at: Instant, position: Vector2<f32>) {
+        while let Some((sampled_at, _)) = self.samples.front() {
+            if at - *sampled_at > POSITION_HISTORY_LENGTH {
+                self.samples.pop_front();
+            } else {
+                break;
+            }
+        }
+        self.samples.push_back((at, position));
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    /// Returns `None` if `at` is more recent than the last sample
+    pub fn position_at(&self, at: Instant) -> Option<Vector2<f32>> {
+        let i = self
+            .samples
+            .partition_point(|(sampled_at, _)| *sampled_at <= at);
+        if i == 0 {
+            self.samples.front().map(|(_, position)| *position)
+        } else {
+            let (t0, p0) = self.samples[i - 1];
+            let (t1, p1) = *self.samples.get(i)?;
+            let t = (at - t0).as_secs_f32() / (t1 - t0).as_secs_f32();
+            Some(p0.lerp(&p1, t))
+        }
+    }
+}
+
+/// A level bordering this one in the LDtk world layout
+#[derive(Debug, Clone, PartialEq)]
+pub struct Neighbour {
+    pub room_id: RoomId,
+    /// Origin of the neighbour in room coordinates
+    pub offset: Vector2<i32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Portal {
     pub position: Vector2<u32>,
     pub target_room_id: RoomId,
     pub target_position: Vector2<f32>,
+    /// Leads to a private copy of the target room
+    pub instance_entrance: bool,
+    /// Leaves the instance for the shared target room
+    pub instance_exit: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct MobSpawn {
     pub position: Vector2<u32>,
     pub mob_template: String,
 }
 
+/// Keeps up to `max_population` mobs at random tiles of a rectangle
+#[derive(Debug, Clone, PartialEq)]
+pub struct MobSpawnArea {
+    /// Top left corner
+    pub position: Vector2<u32>,
+    pub size: Vector2<u32>,
+    /// Templates with their weights
+    pub mobs: Vec<(String, u32)>,
+    pub max_population: u32,
+    /// With a single player in the room, more players shorten it
+    pub respawn_interval: TickDuration,
+}
+
 #[derive(Debug, Clone)]
 pub struct MobRespawn {
     pub spawn: Arc<MobSpawn>,
     pub respawn_at: Tick,
 }
 
+/// Mob state of a stopped room, restored when the room is created again
+#[derive(Debug, Clone)]
+pub struct RoomSnapshot {
+    pub room_id: RoomId,
+    /// Only restored if nothing was reloaded in between
+    pub server_context: Arc<ServerContext>,
+    pub mobs: Vec<Mob>,
+    pub mob_respawns: Vec<MobRespawn>,
+    pub spawn_area_timers: Vec<Tick>,
+}
+
 #[derive(Debug, Clone)]
 pub enum UpstreamMessage {
     PlayerLeftRoom {
-        sender_room_id: RoomId,
         player: Player,
         target_room_id: RoomId,
         target_position: Vector2<f32>,
+        /// Owner of the instance the player continues in, `None` for the shared room
+        instance_owner: Option<ObjectId>,
+    },
+    RoomStopped {
+        snapshot: RoomSnapshot,
     },
 }
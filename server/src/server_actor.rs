@@ -1,27 +1,42 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::time::Duration;
 
 use eyre::Result;
 use mmo_common::object::ObjectId;
 use mmo_common::player_command::{
-    GlobalCommand, PlayerCommand, PlayerCommandEnvelope, RoomCommand,
+    self, GlobalCommand, PlayerCommand, PlayerCommandEnvelope, RoomCommand,
 };
-use mmo_common::player_event::PlayerEvent;
-use mmo_common::room::RoomId;
-use tokio::sync::mpsc;
+use mmo_common::player_event::{self, PlayerEvent};
+use mmo_common::room::{self, RoomId};
+use nalgebra::Vector2;
+use serde::Serialize;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
 use tracing::instrument;
 
+use crate::admin_console::{AdminCommand, AdminReply};
+use crate::admin_logic::RoomAdminCommand;
 use crate::player::{self, PlayerConnection};
-use crate::room_state::{LocalMovement, Player, RemoteMovement};
-use crate::server_context::ServerContext;
-use crate::tick::{self, Tick};
+use crate::player_save::{self, Bans, SavedPlayer, SavedPlayers};
+use crate::room_state::{
+    LocalMovement, Player, PositionHistory, RemoteMovement, RoomSnapshot, SpawnPoint,
+};
+use crate::server_context::{PlayerEquipment, ServerContext};
+use crate::tick::{self, Tick, TickEvent};
 use crate::{room_actor, room_state};
 
+/// How long connections get to send their remaining events when shutting down
+static EVENT_SINK_TIMEOUT: Duration = Duration::from_secs(3);
+
 #[derive(Debug)]
 pub enum Message {
     PlayerConnected {
         player_id: ObjectId,
         connection: PlayerConnection,
+        /// Finishes once the connection is dropped and the remaining events are sent
+        event_sink: JoinHandle<()>,
     },
     PlayerDisconnected {
         player_id: ObjectId,
@@ -30,34 +45,117 @@ pub enum Message {
         player_id: ObjectId,
         command: PlayerCommandEnvelope,
     },
+    ServerContextReloaded {
+        server_context: Arc<ServerContext>,
+    },
+    /// Stop accepting players, warn everyone and stop after the countdown
+    Shutdown {
+        countdown: Duration,
+    },
+    AdminCommand {
+        command: AdminCommand,
+        reply: oneshot::Sender<AdminReply>,
+    },
+    Status {
+        reply: oneshot::Sender<ServerStatus>,
+    },
 }
 
 impl Message {
-    pub fn player_id(&self) -> ObjectId {
+    pub fn player_id(&self) -> Option<ObjectId> {
         match self {
-            Message::PlayerConnected { player_id, .. } => *player_id,
-            Message::PlayerDisconnected { player_id } => *player_id,
-            Message::PlayerCommand { player_id, .. } => *player_id,
+            Message::PlayerConnected { player_id, .. } => Some(*player_id),
+            Message::PlayerDisconnected { player_id } => Some(*player_id),
+            Message::PlayerCommand { player_id, .. } => Some(*player_id),
+            Message::ServerContextReloaded { .. }
+            | Message::Shutdown { .. }
+            | Message::AdminCommand { .. }
+            | Message::Status { .. } => None,
         }
     }
 }
 
+/// For the status and metrics endpoints, queue depths only go to the metrics
+#[derive(Debug, Serialize)]
+pub struct ServerStatus {
+    pub players: usize,
+    pub pending_players: usize,
+    pub rooms: Vec<RoomStatus>,
+    #[serde(skip)]
+    pub upstream_queue_depth: usize,
+    #[serde(skip)]
+    pub max_event_queue_depth: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RoomStatus {
+    pub room_id: u64,
+    pub name: String,
+    /// Player id, for instances
+    pub instance_owner: Option<u64>,
+    pub players: usize,
+    pub frozen: bool,
+    pub idle: bool,
+    #[serde(skip)]
+    pub queue_depth: usize,
+}
+
 struct State {
     server_context: Arc<ServerContext>,
     players: HashMap<ObjectId, PlayerMeta>,
-    rooms: HashMap<RoomId, Room>,
+    /// Connected but not joined yet
+    pending_players: HashMap<ObjectId, PlayerConnection>,
+    event_sinks: HashMap<ObjectId, JoinHandle<()>>,
+    /// Restored when a player joins with the same name
+    saved_players: SavedPlayers,
+    bans: Bans,
+    /// Set once a shutdown was requested
+    shutdown_at: Option<Instant>,
+    rooms: HashMap<RoomKey, Room>,
+    /// Shared rooms that stopped, by their room id
+    room_snapshots: HashMap<RoomId, RoomSnapshot>,
+    /// Applies to the shared room and its instances, also once they are created again
+    frozen_rooms: HashSet<RoomId>,
+    /// Until when a player may not create a new instance of a room, by lowercase name and room
+    instance_lockouts: HashMap<(String, RoomId), Tick>,
     tick_sender: tick::Sender,
+    last_tick: TickEvent,
     room_actor_upstream_sender: mpsc::Sender<room_state::UpstreamMessage>,
 }
 
 struct PlayerMeta {
     id: ObjectId,
-    room_id: RoomId,
+    name: String,
+    room: RoomKey,
     connection: mpsc::Sender<Vec<Arc<PlayerEvent>>>,
+    /// Sequence number and send time of the last pong, until the client confirms it
+    last_pong: Option<(u32, Instant)>,
+    /// In seconds, estimated like the client does to know how far behind it renders
+    ping_rtt: f32,
+    ping_jitter: f32,
+}
+
+/// Rooms are shared, except for instances which belong to the player that entered them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct RoomKey {
+    room_id: RoomId,
+    instance_owner: Option<ObjectId>,
+}
+
+impl RoomKey {
+    fn shared(room_id: RoomId) -> Self {
+        Self {
+            room_id,
+            instance_owner: None,
+        }
+    }
 }
 
 struct Room {
     sender: mpsc::Sender<room_actor::Message>,
+    task: JoinHandle<()>,
+    /// Set while the room has no players
+    emptied_at: Option<Tick>,
 }
 
 #[instrument(skip_all)]
@@ -69,11 +167,35 @@ pub async fn run(
     let (room_actor_upstream_sender, mut room_actor_upstream_receiver) =
         mpsc::channel::<room_state::UpstreamMessage>(4096);
 
+    let mut tick_receiver = tick_sender.subscribe();
+    let first_tick = tick_receiver
+        .recv()
+        .await
+        .expect("Failed to receive first tick");
+
+    let saved_players = player_save::load().unwrap_or_else(|err| {
+        tracing::error!("Failed to load saved players: {err}");
+        SavedPlayers::new()
+    });
+    let bans = player_save::load_bans().unwrap_or_else(|err| {
+        tracing::error!("Failed to load bans: {err}");
+        Bans::new()
+    });
+
     let mut state = State {
         server_context,
         players: HashMap::new(),
+        pending_players: HashMap::new(),
+        event_sinks: HashMap::new(),
+        saved_players,
+        bans,
+        shutdown_at: None,
         rooms: HashMap::new(),
+        room_snapshots: HashMap::new(),
+        frozen_rooms: HashSet::new(),
+        instance_lockouts: HashMap::new(),
         tick_sender,
+        last_tick: first_tick,
         room_actor_upstream_sender,
     };
 
@@ -95,78 +217,99 @@ pub async fn run(
                     }
                 }
             }
+            tick = tick_receiver.recv() => {
+                match tick {
+                    Ok(tick) => {
+                        state.last_tick = tick;
+                        remove_idle_rooms(&mut state);
+                        if state.shutdown_at.is_some_and(|at| tick.monotonic_time >= at) {
+                            break;
+                        }
+                    }
+                    Err(err) => tracing::error!("Error receiving tick: {err}"),
+                }
+            }
         }
     }
+
+    shut_down(&mut state, &mut room_actor_upstream_receiver).await;
 }
 
-fn create_new_player(id: ObjectId, connection: PlayerConnection, ctx: &ServerContext) -> Player {
+fn create_new_player(
+    id: ObjectId,
+    name: String,
+    appearance: u32,
+    connection: PlayerConnection,
+    start_point: &SpawnPoint,
+    ctx: &ServerContext,
+) -> Player {
     let now = tokio::time::Instant::now();
-    let max_health = ctx.player.max_health;
+    let stats = ctx.player.base_stats();
     Player {
         id,
+        name,
+        appearance,
         connection,
         remote_movement: RemoteMovement {
-            position: ctx.world.start_position,
+            position: start_point.position,
             direction: None,
             look_direction: mmo_common::object::Direction4::Down,
             received_at: now,
         },
         local_movement: LocalMovement {
-            position: ctx.world.start_position,
+            position: start_point.position,
             updated_at: now,
         },
-        health: max_health,
-        max_health,
+        velocity: stats.velocity,
+        last_move_sequence_number: 0,
+        latency: Duration::ZERO,
+        position_history: PositionHistory::default(),
+        health: stats.max_health,
+        equipment: PlayerEquipment::new(),
+        stats,
         last_damaged_at: Tick(0),
+        checkpoint: None,
+        respawn_at: None,
     }
 }
 
-#[instrument(skip_all, fields(player_id = message.player_id().0))]
+#[instrument(skip_all, fields(player_id = message.player_id().map(|id| id.0)))]
 async fn handle_message(state: &mut State, message: Message) -> Result<()> {
     match message {
         Message::PlayerConnected {
             player_id,
             connection,
+            event_sink,
         } => {
-            let room_id = state.server_context.world.start_room_id;
-
-            let player_meta = PlayerMeta {
-                id: player_id,
-                room_id,
-                connection: connection.clone(),
-            };
-            state.players.insert(player_id, player_meta);
-
+            state.event_sinks.insert(player_id, event_sink);
+            if let Some(shutdown_at) = state.shutdown_at {
+                connection
+                    .send(vec![Arc::new(server_shutdown(shutdown_at))])
+                    .await?;
+            }
+            let appearances = state.server_context.player_appearance_names.clone();
             connection
-                .send(vec![Arc::new(PlayerEvent::Initial {
-                    self_id: player_id,
-                    client_config: Box::new(player::client_config(&state.server_context)),
-                })])
-                .await?;
-
-            let player = create_new_player(player_id, connection, &state.server_context);
-
-            let room = get_or_create_room(state, room_id);
-            room.sender
-                .send(room_actor::Message::PlayerConnected { player })
+                .send(vec![Arc::new(PlayerEvent::JoinOptions { appearances })])
                 .await?;
+            state.pending_players.insert(player_id, connection);
         }
         Message::PlayerDisconnected { player_id } => {
+            state.event_sinks.remove(&player_id);
+            if state.pending_players.remove(&player_id).is_some() {
+                return Ok(());
+            }
             if let Some(player) = state.players.remove(&player_id) {
-                let room_id = player.room_id;
-                if let Some(room) = state.rooms.get_mut(&room_id) {
+                let room_key = player.room;
+                if let Some(room) = state.rooms.get_mut(&room_key) {
                     room.sender
                         .send(room_actor::Message::PlayerDisconnected { player_id })
                         .await?;
-                    remove_room_if_empty(state, room_id);
+                    remove_room_if_empty(state, room_key);
                 } else {
-                    tracing::warn!(
-                        "Player disconnected but room {room_id:?} not found",
-                        room_id = player.room_id
-                    );
+                    tracing::warn!("Player disconnected but room {room_key:?} not found");
                 }
             } else {
-                tracing::warn!("Player disconnected but not found");
+                tracing::debug!("Player disconnected after being kicked");
             }
         }
         Message::PlayerCommand { player_id, command } => {
@@ -182,20 +325,404 @@ async fn handle_message(state: &mut State, message: Message) -> Result<()> {
                 }
             }
         }
+        Message::ServerContextReloaded { server_context } => {
+            state.server_context = server_context;
+            // Snapshots only fit the old maps and templates
+            state.room_snapshots.clear();
+
+            // Sent before the rooms resync, so clients know the new animations by then
+            let client_config_changed = Arc::new(PlayerEvent::ClientConfigChanged {
+                client_config: Box::new(player::client_config(&state.server_context)),
+            });
+            for player in state.players.values() {
+                if let Err(err) = player
+                    .connection
+                    .send(vec![client_config_changed.clone()])
+                    .await
+                {
+                    tracing::warn!("Failed to send client config: {err}");
+                }
+            }
+            for room in state.rooms.values() {
+                room.sender
+                    .send(room_actor::Message::ServerContextReloaded {
+                        server_context: state.server_context.clone(),
+                    })
+                    .await?;
+            }
+            tracing::info!("Reloaded server context");
+        }
+        Message::Shutdown { countdown } => {
+            if state.shutdown_at.is_some() {
+                return Ok(());
+            }
+            // Nobody needs a warning on an empty server
+            let countdown = if state.players.is_empty() {
+                Duration::ZERO
+            } else {
+                countdown
+            };
+            let shutdown_at = Instant::now() + countdown;
+            state.shutdown_at = Some(shutdown_at);
+            tracing::info!("Shutting down in {} seconds", countdown.as_secs_f32());
+
+            let event = Arc::new(server_shutdown(shutdown_at));
+            let connections = state
+                .players
+                .values()
+                .map(|player| &player.connection)
+                .chain(state.pending_players.values());
+            for connection in connections {
+                if let Err(err) = connection.send(vec![event.clone()]).await {
+                    tracing::warn!("Failed to send shutdown: {err}");
+                }
+            }
+        }
+        Message::Status { reply } => {
+            let _ = reply.send(server_status(state));
+        }
+        Message::AdminCommand { command, reply } => match admin_action(state, command).await {
+            Ok(AdminAction::Reply(output)) => {
+                let _ = reply.send(Ok(output));
+            }
+            Ok(AdminAction::Room(room_key, command)) => {
+                // Only players keep a room alive, an operator looking at an idle one doesn't
+                let emptied_at = state.rooms.get(&room_key).and_then(|room| room.emptied_at);
+                let room = get_or_create_room(state, room_key);
+                room.sender
+                    .send(room_actor::Message::AdminCommand { command, reply })
+                    .await?;
+                room.emptied_at = emptied_at;
+                remove_room_if_empty(state, room_key);
+            }
+            Err(err) => {
+                let _ = reply.send(Err(err));
+            }
+        },
     }
     Ok(())
 }
 
+fn server_shutdown(shutdown_at: Instant) -> PlayerEvent {
+    let seconds = shutdown_at.saturating_duration_since(Instant::now());
+    PlayerEvent::ServerShutdown {
+        seconds: seconds.as_secs_f32(),
+    }
+}
+
+fn server_status(state: &State) -> ServerStatus {
+    let mut rooms = state
+        .rooms
+        .iter()
+        .map(|(room_key, room)| RoomStatus {
+            room_id: room_key.room_id.0,
+            name: room_name(&state.server_context, room_key.room_id).to_string(),
+            instance_owner: room_key.instance_owner.map(|id| id.0),
+            players: state
+                .players
+                .values()
+                .filter(|player| player.room == *room_key)
+                .count(),
+            frozen: state.frozen_rooms.contains(&room_key.room_id),
+            idle: room.emptied_at.is_some(),
+            queue_depth: queue_depth(&room.sender),
+        })
+        .collect::<Vec<_>>();
+    rooms.sort_by_key(|room| (room.room_id, room.instance_owner));
+    ServerStatus {
+        players: state.players.len(),
+        pending_players: state.pending_players.len(),
+        rooms,
+        upstream_queue_depth: queue_depth(&state.room_actor_upstream_sender),
+        max_event_queue_depth: state
+            .players
+            .values()
+            .map(|player| queue_depth(&player.connection))
+            .max()
+            .unwrap_or(0),
+    }
+}
+
+pub fn queue_depth<T>(sender: &mpsc::Sender<T>) -> usize {
+    sender.max_capacity() - sender.capacity()
+}
+
+/// Most admin commands are carried out by the room the player or mob is in
+enum AdminAction {
+    Reply(String),
+    Room(RoomKey, RoomAdminCommand),
+}
+
+async fn admin_action(state: &mut State, command: AdminCommand) -> Result<AdminAction, String> {
+    let action = match command {
+        AdminCommand::ListRooms => AdminAction::Reply(list_rooms(state)),
+        AdminCommand::ListPlayers => AdminAction::Reply(list_players(state)),
+        AdminCommand::Teleport {
+            player,
+            room,
+            position,
+        } => {
+            let player_meta = find_player(state, &player)?;
+            let room_id = find_room_id(&state.server_context, &room)?;
+            let position = match position {
+                Some(position) => position,
+                None => default_teleport_position(&state.server_context, room_id)?,
+            };
+            let map = &state.server_context.world.maps[&room_id];
+            if !room::in_bounds(map.size, position)
+                || room::collision_at(map.size, &map.collisions, position)
+            {
+                return Err(format!(
+                    "Position ({}, {}) is blocked",
+                    position.x, position.y
+                ));
+            }
+            AdminAction::Room(
+                player_meta.room,
+                RoomAdminCommand::Teleport {
+                    player_id: player_meta.id,
+                    room_id,
+                    position,
+                },
+            )
+        }
+        AdminCommand::SpawnMob {
+            room,
+            mob_template,
+            tile,
+        } => {
+            let room_id = find_room_id(&state.server_context, &room)?;
+            if !state
+                .server_context
+                .mob_templates
+                .contains_key(&mob_template)
+            {
+                return Err(format!("Mob template {mob_template} not found"));
+            }
+            AdminAction::Room(
+                RoomKey::shared(room_id),
+                RoomAdminCommand::SpawnMob { mob_template, tile },
+            )
+        }
+        AdminCommand::Kill { player } => {
+            let player_meta = find_player(state, &player)?;
+            let player_id = player_meta.id;
+            AdminAction::Room(player_meta.room, RoomAdminCommand::Kill { player_id })
+        }
+        AdminCommand::Heal { player } => {
+            let player_meta = find_player(state, &player)?;
+            let player_id = player_meta.id;
+            AdminAction::Room(player_meta.room, RoomAdminCommand::Heal { player_id })
+        }
+        AdminCommand::Kick { player } => {
+            let player_id = find_player(state, &player)?.id;
+            kick_player(state, player_id, "You were kicked")
+                .await
+                .map_err(|err| err.to_string())?;
+            AdminAction::Reply(format!("Kicked {player}"))
+        }
+        AdminCommand::Ban { name } => {
+            state.bans.insert(name.to_lowercase());
+            tracing::info!("Banned {name:?}");
+            if let Ok(player_meta) = find_player(state, &name) {
+                let player_id = player_meta.id;
+                kick_player(state, player_id, "You were banned")
+                    .await
+                    .map_err(|err| err.to_string())?;
+            }
+            player_save::save_bans(&state.bans)
+                .map_err(|err| format!("Banned {name}, but failed to save bans: {err}"))?;
+            AdminAction::Reply(format!("Banned {name}"))
+        }
+        AdminCommand::Unban { name } => {
+            if !state.bans.remove(&name.to_lowercase()) {
+                return Err(format!("{name} is not banned"));
+            }
+            tracing::info!("Unbanned {name:?}");
+            player_save::save_bans(&state.bans)
+                .map_err(|err| format!("Unbanned {name}, but failed to save bans: {err}"))?;
+            AdminAction::Reply(format!("Unbanned {name}"))
+        }
+        AdminCommand::Broadcast { text } => {
+            let event = Arc::new(PlayerEvent::Notice { text: text.clone() });
+            for player in state.players.values() {
+                if let Err(err) = player.connection.send(vec![event.clone()]).await {
+                    tracing::warn!("Failed to send notice: {err}");
+                }
+            }
+            tracing::info!("Broadcast {text:?}");
+            AdminAction::Reply(format!("Sent to {} players", state.players.len()))
+        }
+        AdminCommand::SetFrozen { room, frozen } => {
+            let room_id = find_room_id(&state.server_context, &room)?;
+            let changed = if frozen {
+                state.frozen_rooms.insert(room_id)
+            } else {
+                state.frozen_rooms.remove(&room_id)
+            };
+            if !changed {
+                return Err(format!("Room {room} is already {}", frozen_text(frozen)));
+            }
+            for (room_key, room) in &state.rooms {
+                if room_key.room_id == room_id {
+                    room.sender
+                        .send(room_actor::Message::SetFrozen { frozen })
+                        .await
+                        .map_err(|err| err.to_string())?;
+                }
+            }
+            AdminAction::Reply(format!("Room {room} is {}", frozen_text(frozen)))
+        }
+    };
+    Ok(action)
+}
+
+fn frozen_text(frozen: bool) -> &'static str {
+    if frozen {
+        "frozen"
+    } else {
+        "unfrozen"
+    }
+}
+
+fn find_player<'a>(state: &'a State, name: &str) -> Result<&'a PlayerMeta, String> {
+    let lowercase = name.to_lowercase();
+    state
+        .players
+        .values()
+        .find(|player| player.name.to_lowercase() == lowercase)
+        .ok_or_else(|| format!("Player {name} not found"))
+}
+
+/// By id or case insensitive name
+fn find_room_id(ctx: &ServerContext, room: &str) -> Result<RoomId, String> {
+    let maps = &ctx.world.maps;
+    if let Some(room_id) = room.parse().ok().map(RoomId) {
+        if maps.contains_key(&room_id) {
+            return Ok(room_id);
+        }
+    }
+    maps.iter()
+        .find(|(_, map)| map.name.eq_ignore_ascii_case(room))
+        .map(|(room_id, _)| *room_id)
+        .ok_or_else(|| format!("Room {room} not found"))
+}
+
+fn default_teleport_position(ctx: &ServerContext, room_id: RoomId) -> Result<Vector2<f32>, String> {
+    let map = &ctx.world.maps[&room_id];
+    ctx.world
+        .start_points
+        .iter()
+        .chain(&map.checkpoints)
+        .find(|spawn_point| spawn_point.room_id == room_id)
+        .map(|spawn_point| spawn_point.position)
+        .ok_or_else(|| "Room has no start point or checkpoint, give a position".to_string())
+}
+
+/// The connection closes once the room drops the player as well
+async fn kick_player(state: &mut State, player_id: ObjectId, text: &str) -> Result<()> {
+    let Some(player) = state.players.remove(&player_id) else {
+        return Ok(());
+    };
+    let notice = PlayerEvent::Notice {
+        text: text.to_string(),
+    };
+    if let Err(err) = player.connection.send(vec![Arc::new(notice)]).await {
+        tracing::warn!("Failed to send notice: {err}");
+    }
+    if let Some(room) = state.rooms.get(&player.room) {
+        room.sender
+            .send(room_actor::Message::PlayerDisconnected { player_id })
+            .await?;
+        remove_room_if_empty(state, player.room);
+    }
+    tracing::info!("Kicked {:?}", player.name);
+    Ok(())
+}
+
+fn list_rooms(state: &State) -> String {
+    let mut room_keys = state.rooms.keys().copied().collect::<Vec<_>>();
+    room_keys.sort_by_key(|room_key| (room_key.room_id.0, room_key.instance_owner.map(|id| id.0)));
+    let lines = room_keys
+        .iter()
+        .map(|room_key| {
+            let players = state
+                .players
+                .values()
+                .filter(|player| player.room == *room_key)
+                .count();
+            let mut line = format!(
+                "{} {}: {players} players",
+                room_key.room_id.0,
+                room_name(&state.server_context, room_key.room_id)
+            );
+            if let Some(owner) = room_key.instance_owner {
+                let owner = state
+                    .players
+                    .get(&owner)
+                    .map_or("disconnected player", |player| player.name.as_str());
+                line += &format!(", instance of {owner}");
+            }
+            if state.frozen_rooms.contains(&room_key.room_id) {
+                line += ", frozen";
+            }
+            if state.rooms[room_key].emptied_at.is_some() {
+                line += ", idle";
+            }
+            line
+        })
+        .collect::<Vec<_>>();
+    if lines.is_empty() {
+        "No rooms running".to_string()
+    } else {
+        lines.join("\n")
+    }
+}
+
+fn list_players(state: &State) -> String {
+    let mut players = state.players.values().collect::<Vec<_>>();
+    players.sort_by_key(|player| player.id.0);
+    let lines = players
+        .iter()
+        .map(|player| {
+            let mut line = format!(
+                "{} {}: room {} {}",
+                player.id.0,
+                player.name,
+                player.room.room_id.0,
+                room_name(&state.server_context, player.room.room_id)
+            );
+            if player.room.instance_owner.is_some() {
+                line += " (instance)";
+            }
+            line
+        })
+        .collect::<Vec<_>>();
+    if lines.is_empty() {
+        "No players online".to_string()
+    } else {
+        lines.join("\n")
+    }
+}
+
+/// Rooms may outlive their map for a moment after a reload
+fn room_name(ctx: &ServerContext, room_id: RoomId) -> &str {
+    ctx.world
+        .maps
+        .get(&room_id)
+        .map_or("(removed)", |map| map.name.as_str())
+}
+
 async fn handle_room_command(
     state: &mut State,
     player_id: ObjectId,
     room_id: RoomId,
     command: RoomCommand,
 ) -> Result<()> {
-    let player_room_id = state.players.get(&player_id).map(|p| p.room_id);
-    match player_room_id {
-        Some(player_room_id) if player_room_id == room_id => {
-            get_or_create_room(state, room_id)
+    let player_room = state.players.get(&player_id).map(|p| p.room);
+    match player_room {
+        Some(player_room) if player_room.room_id == room_id => {
+            get_or_create_room(state, player_room)
                 .sender
                 .send(room_actor::Message::PlayerCommand { player_id, command })
                 .await?
@@ -214,13 +741,184 @@ async fn handle_global_command(
     message: GlobalCommand,
 ) -> Result<()> {
     match message {
+        GlobalCommand::Join { name, appearance } => {
+            let Some(connection) = state.pending_players.get(&player_id).cloned() else {
+                tracing::warn!("Player sent join but already joined");
+                return Ok(());
+            };
+            match check_join(state, &name, appearance) {
+                Ok(()) => {
+                    state.pending_players.remove(&player_id);
+                    join_player(state, player_id, name, appearance, connection).await?;
+                }
+                Err(reason) => {
+                    tracing::info!("Rejected join as {name:?}: {reason}");
+                    connection
+                        .send(vec![Arc::new(PlayerEvent::JoinRejected { reason })])
+                        .await?;
+                }
+            }
+        }
         GlobalCommand::Ping { sequence_number } => {
-            let pong = PlayerEvent::Pong { sequence_number };
-            if let Some(player) = state.players.get(&player_id) {
-                player.connection.send(vec![Arc::new(pong)]).await?
+            let now = Instant::now();
+            let since_tick = now - state.last_tick.monotonic_time;
+            let pong = PlayerEvent::Pong {
+                sequence_number,
+                tick: state.last_tick.tick.0,
+                since_tick: since_tick.as_secs_f32(),
+            };
+            if let Some(player) = state.players.get_mut(&player_id) {
+                player.last_pong = Some((sequence_number, now));
+                player.connection.send(vec![Arc::new(pong)]).await?;
+            }
+        }
+        GlobalCommand::PongReceived { sequence_number } => {
+            // Measured here rather than reported by the client, which could claim any latency
+            let Some(player) = state.players.get_mut(&player_id) else {
+                return Ok(());
+            };
+            let Some((pong_sequence_number, sent_at)) = player.last_pong else {
+                return Ok(());
+            };
+            if pong_sequence_number != sequence_number {
+                return Ok(());
+            }
+            player.last_pong = None;
+            let ping_rtt = sent_at.elapsed().as_secs_f32();
+            if player.ping_rtt > 0.0 {
+                let deviation = (ping_rtt - player.ping_rtt).abs();
+                player.ping_jitter += (deviation - player.ping_jitter) / 16.0;
+            }
+            player.ping_rtt = ping_rtt;
+            // The attacker saw the mobs a round trip plus the interpolation delay ago
+            let interpolation_delay =
+                player_event::interpolation_delay(player.ping_rtt, player.ping_jitter);
+            let latency = Duration::from_secs_f32(ping_rtt + interpolation_delay);
+            if let Some(room) = state.rooms.get(&player.room) {
+                room.sender
+                    .send(room_actor::Message::PlayerLatencyMeasured { player_id, latency })
+                    .await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Lowercase words of a name, split at `_`, `-`, between letters and digits and before
+/// an uppercase letter that follows a lowercase one, so "Dickens" stays one word
+fn name_words(name: &str) -> Vec<String> {
+    let mut words = vec![];
+    for part in name.split(['_', '-']).filter(|part| !part.is_empty()) {
+        let mut word = String::new();
+        let mut previous: Option<char> = None;
+        for c in part.chars() {
+            let boundary = previous.is_some_and(|previous| {
+                previous.is_ascii_digit() != c.is_ascii_digit()
+                    || (previous.is_ascii_lowercase() && c.is_ascii_uppercase())
+            });
+            if boundary {
+                words.push(std::mem::take(&mut word));
             }
+            word.push(c.to_ascii_lowercase());
+            previous = Some(c);
+        }
+        words.push(word);
+    }
+    words
+}
+
+/// Entries between asterisks also match at the start or end of a word, so `*cunt*`
+/// catches "xXcuntXx" but not "Scunthorpe"
+fn is_profane(word: &str, profanity: &str) -> bool {
+    match profanity
+        .strip_prefix('*')
+        .and_then(|profanity| profanity.strip_suffix('*'))
+    {
+        Some(stem) => word.starts_with(stem) || word.ends_with(stem),
+        None => word == profanity,
+    }
+}
+
+fn check_join(state: &State, name: &str, appearance: u32) -> Result<(), String> {
+    if state.shutdown_at.is_some() {
+        return Err("Server is shutting down".to_string());
+    }
+    player_command::check_player_name(name)?;
+
+    let ctx = &state.server_context;
+    let lowercase = name.to_lowercase();
+    if state.bans.contains(&lowercase) {
+        return Err("Name is banned".to_string());
+    }
+    if name_words(name).iter().any(|word| {
+        ctx.profanity
+            .iter()
+            .any(|profanity| is_profane(word, profanity))
+    }) {
+        return Err("Name is not allowed".to_string());
+    }
+    if state
+        .players
+        .values()
+        .any(|player| player.name.to_lowercase() == lowercase)
+    {
+        return Err("Name is already taken".to_string());
+    }
+    if appearance as usize >= ctx.player_appearances.len() {
+        return Err("Unknown appearance".to_string());
+    }
+    Ok(())
+}
+
+async fn join_player(
+    state: &mut State,
+    player_id: ObjectId,
+    name: String,
+    appearance: u32,
+    connection: PlayerConnection,
+) -> Result<()> {
+    let start_point = state.server_context.world.random_start_point().clone();
+    let mut player = create_new_player(
+        player_id,
+        name.clone(),
+        appearance,
+        connection.clone(),
+        &start_point,
+        &state.server_context,
+    );
+    let mut room_id = start_point.room_id;
+    // Kept until the next shutdown saves newer state, so leaving before then doesn't lose it
+    if let Some(saved) = state.saved_players.get(&name.to_lowercase()).cloned() {
+        tracing::info!("Restoring saved state of {name:?}");
+        if let Some(saved_room_id) = player_save::restore(&mut player, saved, &state.server_context)
+        {
+            room_id = saved_room_id;
         }
     }
+    let room_key = RoomKey::shared(room_id);
+
+    let player_meta = PlayerMeta {
+        id: player_id,
+        name: name.clone(),
+        room: room_key,
+        connection: connection.clone(),
+        last_pong: None,
+        ping_rtt: 0.0,
+        ping_jitter: 0.0,
+    };
+    state.players.insert(player_id, player_meta);
+
+    connection
+        .send(vec![Arc::new(PlayerEvent::Initial {
+            self_id: player_id,
+            client_config: Box::new(player::client_config(&state.server_context)),
+        })])
+        .await?;
+
+    let room = get_or_create_room(state, room_key);
+    room.sender
+        .send(room_actor::Message::PlayerConnected { player })
+        .await?;
     Ok(())
 }
 
@@ -230,45 +928,95 @@ async fn handle_upstream_message(
 ) -> Result<()> {
     match message {
         room_state::UpstreamMessage::PlayerLeftRoom {
-            sender_room_id,
             mut player,
-            target_room_id,
-            target_position,
+            mut target_room_id,
+            mut target_position,
+            mut instance_owner,
         } => {
+            // The target may have disappeared with a reload
+            let world = &state.server_context.world;
+            if !world.maps.contains_key(&target_room_id) {
+                let start_point = world.random_start_point();
+                target_room_id = start_point.room_id;
+                target_position = start_point.position;
+                instance_owner = None;
+            }
+            let mut target_room_key = RoomKey {
+                room_id: target_room_id,
+                instance_owner,
+            };
+            if let Some(text) = check_instance_lockout(state, player.id, target_room_key) {
+                // Back where the player stood before stepping onto the entrance
+                let Some(player_meta) = state.players.get(&player.id) else {
+                    tracing::error!("Player not found");
+                    return Ok(());
+                };
+                let event = Arc::new(PlayerEvent::Notice { text });
+                if let Err(err) = player_meta.connection.send(vec![event]).await {
+                    tracing::warn!("Failed to send notice: {err}");
+                }
+                target_room_key = player_meta.room;
+                target_position = player.local_movement.position;
+                player.remote_movement.direction = None;
+            }
             if let Some(player_meta) = state.players.get_mut(&player.id) {
-                player_meta.room_id = target_room_id;
+                let sender_room_key = std::mem::replace(&mut player_meta.room, target_room_key);
                 player.remote_movement.position = target_position;
                 player.local_movement.position = target_position;
 
-                let target_room = get_or_create_room(state, target_room_id);
+                let target_room = get_or_create_room(state, target_room_key);
                 target_room
                     .sender
                     .send(room_actor::Message::PlayerConnected { player })
                     .await?;
+                remove_room_if_empty(state, sender_room_key);
             } else {
                 tracing::error!("Player not found");
             }
-            remove_room_if_empty(state, sender_room_id);
+        }
+        room_state::UpstreamMessage::RoomStopped { snapshot } => {
+            // The room may have been created again before the snapshot arrived
+            if !state.rooms.contains_key(&RoomKey::shared(snapshot.room_id)) {
+                state.room_snapshots.insert(snapshot.room_id, snapshot);
+            }
         }
     }
     Ok(())
 }
 
-fn get_or_create_room(state: &mut State, room_id: RoomId) -> &mut Room {
+fn get_or_create_room(state: &mut State, room_key: RoomKey) -> &mut Room {
     let State {
         rooms,
+        room_snapshots,
         room_actor_upstream_sender,
         ..
     } = state;
-    rooms.entry(room_id).or_insert_with(|| {
+    let frozen = state.frozen_rooms.contains(&room_key.room_id);
+    let room = rooms.entry(room_key).or_insert_with(|| {
+        let room_id = room_key.room_id;
+        let persistent = room_key.instance_owner.is_none();
+        let snapshot = if persistent {
+            room_snapshots.remove(&room_id)
+        } else {
+            tracing::info!("Creating instance {room_key:?}");
+            None
+        };
         let server_context = state.server_context.clone();
         let upstream_sender = room_actor_upstream_sender.clone();
         let (room_actor_sender, room_actor_receiver) = mpsc::channel::<room_actor::Message>(4096);
+        if frozen {
+            let message = room_actor::Message::SetFrozen { frozen };
+            if let Err(err) = room_actor_sender.try_send(message) {
+                tracing::error!("Failed to freeze room: {err}");
+            }
+        }
         let tick_receiver = state.tick_sender.subscribe();
-        tokio::spawn(async move {
+        let task = tokio::spawn(async move {
             room_actor::run(
                 room_id,
+                room_key.instance_owner,
                 server_context,
+                snapshot,
                 room_actor_receiver,
                 tick_receiver,
                 upstream_sender,
@@ -277,16 +1025,153 @@ fn get_or_create_room(state: &mut State, room_id: RoomId) -> &mut Room {
         });
         Room {
             sender: room_actor_sender,
+            task,
+            emptied_at: None,
         }
-    })
+    });
+    room.emptied_at = None;
+    room
 }
 
-fn remove_room_if_empty(state: &mut State, room_id: RoomId) {
-    if !state
-        .players
-        .values()
-        .any(|player| player.room_id == room_id)
+/// Empty rooms are kept for a while, so leaving and re-entering doesn't reset them
+fn remove_room_if_empty(state: &mut State, room_key: RoomKey) {
+    if state.players.values().any(|player| player.room == room_key) {
+        return;
+    }
+    if let Some(room) = state.rooms.get_mut(&room_key) {
+        room.emptied_at.get_or_insert(state.last_tick.tick);
+    }
+}
+
+/// Entering a new instance starts the lockout, returning to a running one doesn't
+fn check_instance_lockout(
+    state: &mut State,
+    player_id: ObjectId,
+    target_room_key: RoomKey,
+) -> Option<String> {
+    let player = state.players.get(&player_id)?;
+    let entering = player.room.instance_owner != Some(player_id)
+        && target_room_key.instance_owner == Some(player_id);
+    if !entering || state.rooms.contains_key(&target_room_key) {
+        return None;
+    }
+    let name = player.name.to_lowercase();
+    let tick = state.last_tick.tick;
+    let key = (name, target_room_key.room_id);
+    if let Some(locked_until) = state.instance_lockouts.get(&key) {
+        if tick < *locked_until {
+            let remaining = (*locked_until - tick).as_secs_f32().ceil();
+            return Some(format!(
+                "You can enter a new instance in {remaining} seconds"
+            ));
+        }
+    }
+    state
+        .instance_lockouts
+        .insert(key, tick + state.server_context.instance_lockout);
+    None
+}
+
+fn remove_idle_rooms(state: &mut State) {
+    let tick = state.last_tick.tick;
+    state
+        .instance_lockouts
+        .retain(|_, locked_until| tick < *locked_until);
+    let ctx = &state.server_context;
+    state.rooms.retain(|room_key, room| {
+        let timeout = if room_key.instance_owner.is_some() {
+            ctx.instance_reset_time
+        } else {
+            ctx.room_idle_timeout
+        };
+        let idle = room
+            .emptied_at
+            .is_some_and(|emptied_at| emptied_at + timeout <= tick);
+        if idle && room_key.instance_owner.is_some() {
+            tracing::info!("Resetting instance {room_key:?}");
+        } else if idle {
+            tracing::debug!("Stopping idle room {}", room_key.room_id.0);
+        }
+        !idle
+    });
+}
+
+/// Stops the rooms, saves the players in them and lets the connections close.
+/// A failing room only loses its own players, the others are saved regardless.
+async fn shut_down(
+    state: &mut State,
+    upstream_receiver: &mut mpsc::Receiver<room_state::UpstreamMessage>,
+) {
+    let mut stopping_rooms = vec![];
+    for (room_key, room) in state.rooms.drain() {
+        let (players_sender, players_receiver) = oneshot::channel();
+        let message = room_actor::Message::Shutdown {
+            players: players_sender,
+        };
+        if let Err(err) = room.sender.send(message).await {
+            tracing::error!("Failed to stop room {room_key:?}: {err}");
+            continue;
+        }
+        stopping_rooms.push((room_key, players_receiver, room.task));
+    }
+
+    let mut players = vec![];
+    for (room_key, players_receiver, task) in stopping_rooms {
+        let saved_room_id = room_key
+            .instance_owner
+            .is_none()
+            .then_some(room_key.room_id);
+        match players_receiver.await {
+            Ok(room_players) => players.extend(
+                room_players
+                    .into_iter()
+                    .map(|player| (player, saved_room_id)),
+            ),
+            Err(_) => tracing::error!("Room {room_key:?} stopped without its players"),
+        }
+        if let Err(err) = task.await {
+            tracing::error!("Room {room_key:?} failed: {err}");
+        }
+    }
+    // Players that were on their way to another room
+    while let Ok(message) = upstream_receiver.try_recv() {
+        if let room_state::UpstreamMessage::PlayerLeftRoom {
+            mut player,
+            target_room_id,
+            target_position,
+            instance_owner,
+        } = message
+        {
+            player.local_movement.position = target_position;
+            players.push((player, instance_owner.is_none().then_some(target_room_id)));
+        }
+    }
+
+    for (player, room_id) in &players {
+        if let Some(player_meta) = state.players.get(&player.id) {
+            let saved = SavedPlayer::new(player, *room_id);
+            state
+                .saved_players
+                .insert(player_meta.name.to_lowercase(), saved);
+        }
+    }
+    match player_save::save(&state.saved_players) {
+        Ok(()) => tracing::info!("Saved {} players", players.len()),
+        Err(err) => tracing::error!("Failed to save players: {err}"),
+    }
+
+    // Dropping every sender ends the event sinks, which close their connections
+    drop(players);
+    state.players.clear();
+    state.pending_players.clear();
+    let event_sinks = state.event_sinks.drain().map(|(_, event_sink)| event_sink);
+    if tokio::time::timeout(
+        EVENT_SINK_TIMEOUT,
+        futures_util::future::join_all(event_sinks),
+    )
+    .await
+    .is_err()
     {
-        state.rooms.remove(&room_id);
+        tracing::warn!("Timeout waiting for connections to close");
     }
 }
@@ -1,17 +1,31 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    num::NonZeroU16,
+    path::PathBuf,
+    sync::Arc,
+};
 
 use eyre::{eyre, Result};
-use mmo_common::{animation::AnimationSet, room::RoomId};
-use nalgebra::Vector2;
+use mmo_common::{
+    animation::{AnimationSet, TileAnimation},
+    combat::{DamageType, Resistances},
+    equipment::{EquipmentItem, EquipmentSlot, PlayerStats, StatModifiers},
+    room::{RoomId, TileIndex, TilesetId},
+};
 use serde::Deserialize;
 
 use crate::{
     assets::AssetPaths,
+    ldtk_map,
     mob::MobTemplate,
-    room_state::RoomMap,
-    tick::{TickDuration, TickRate},
+    room_state::{RoomMap, SpawnPoint},
+    tick::{self, Tick, TickDuration, TickRate},
 };
 
+pub static MAP_PATH: &str = "data/map.ldtk";
+pub static CONFIG_PATH: &str = "data/config.toml";
+pub static PROFANITY_PATH: &str = "data/profanity.txt";
+
 #[derive(Debug, Clone)]
 pub struct ServerContext {
     pub asset_paths: AssetPaths,
@@ -19,12 +33,66 @@ pub struct ServerContext {
     pub mob_templates: HashMap<String, Arc<MobTemplate>>,
     pub animations: Vec<AnimationSet>,
     pub mob_animations: HashMap<String, u32>,
+    pub tile_animations: Vec<TileAnimation>,
+    pub equipment: HashMap<String, EquipmentConfig>,
+    pub equipment_animations: HashMap<String, u32>,
     pub player: PlayerConfig,
-    pub player_animation: u32,
+    /// Animations players choose from when joining, the first one is `player_animation`
+    pub player_appearances: Vec<u32>,
+    pub player_appearance_names: Vec<String>,
+    pub slow_terrain_velocity_factor: f32,
+    pub world_clock: WorldClockConfig,
+    /// How long an empty instance is kept, re-entering before then returns to the same instance
+    pub instance_reset_time: TickDuration,
+    /// How long after entering an instance a player has to wait before a new one is created
+    pub instance_lockout: TickDuration,
+    /// How long an empty shared room keeps running before it stops
+    pub room_idle_timeout: TickDuration,
+    /// Lowercase words that may not appear in player names
+    pub profanity: Vec<String>,
 }
 
 impl ServerContext {
-    pub fn new(server_config: ServerConfig, asset_paths: AssetPaths, world: World) -> Result<Self> {
+    pub fn load(asset_paths: AssetPaths) -> Result<Self> {
+        tracing::info!("Loading maps...");
+        let room_maps = ldtk_map::load(MAP_PATH)?;
+        tracing::info!("Loaded maps");
+
+        tracing::info!("Loading config...");
+        let config = ServerConfig::load(CONFIG_PATH)?;
+        tracing::info!("Loaded config");
+
+        let profanity = load_word_list(PROFANITY_PATH)?;
+
+        Self::new(config, asset_paths, room_maps, profanity)
+    }
+
+    pub fn new(
+        server_config: ServerConfig,
+        mut asset_paths: AssetPaths,
+        world: World,
+        profanity: Vec<String>,
+    ) -> Result<Self> {
+        // The world clock divides by it
+        if server_config.world_clock.day_length <= 0.0 {
+            return Err(eyre!("world_clock.day_length must be positive"));
+        }
+
+        // Assets are collected at startup, so new images need a restart
+        asset_paths.paths.tilesets = world
+            .tilesets
+            .iter()
+            .map(|tileset| {
+                asset_paths
+                    .find_by_local_path(&tileset.path)
+                    .map(|asset| asset.request_path.clone())
+                    .ok_or_else(|| {
+                        eyre!("Tileset {} is not a loaded asset", tileset.path.display())
+                    })
+            })
+            .collect::<Result<_>>()?;
+
+        let player_appearance_names = server_config.player_appearance_names();
         let mut animations: Vec<(String, AnimationSet)> =
             server_config.animations.into_iter().collect();
         animations.sort_by_key(|(name, _)| name.clone());
@@ -35,46 +103,256 @@ impl ServerContext {
             .map(|(_, animation)| animation)
             .collect();
 
-        let player_animation = animation_keys
+        let player_appearances = player_appearance_names
             .iter()
-            .position(|name| name == &server_config.player_animation)
-            .ok_or_else(|| eyre!("Player animation not found"))?
-            as u32;
+            .map(|appearance| {
+                animation_keys
+                    .iter()
+                    .position(|name| name == appearance)
+                    .map(|index| index as u32)
+                    .ok_or_else(|| eyre!("Player animation not found: {appearance}"))
+            })
+            .collect::<Result<_>>()?;
 
         let mut mob_animations = HashMap::new();
-        for name in server_config.mob_templates.keys() {
+        for (name, mob_template) in &server_config.mob_templates {
+            // Choosing an attack picks one at random
+            if mob_template.attacks.is_empty() {
+                return Err(eyre!("Mob template has no attacks: {name}"));
+            }
             let index = animation_keys
                 .iter()
-                .position(|animation_name| animation_name == name)
-                .ok_or_else(|| eyre!("Mob animation not found"))? as u32;
-            mob_animations.insert(name.clone(), index);
+                .position(|animation_name| animation_name == &mob_template.animation_id)
+                .ok_or_else(|| {
+                    eyre!(
+                        "Mob animation not found: {} (mob template {name})",
+                        mob_template.animation_id
+                    )
+                })? as u32;
+            mob_animations.insert(mob_template.animation_id.clone(), index);
+        }
+
+        let mut equipment_animations = HashMap::new();
+        for (name, equipment) in &server_config.equipment {
+            if let Some(overlay_animation) = &equipment.overlay_animation {
+                let index = animation_keys
+                    .iter()
+                    .position(|animation_name| animation_name == overlay_animation)
+                    .ok_or_else(|| {
+                        eyre!(
+                            "Equipment animation not found: {overlay_animation} (equipment {name})"
+                        )
+                    })? as u32;
+                equipment_animations.insert(overlay_animation.clone(), index);
+            }
+        }
+        for item_id in &server_config.player.carried_equipment {
+            if !server_config.equipment.contains_key(item_id) {
+                return Err(eyre!("Carried equipment not found: {item_id}"));
+            }
         }
 
+        let mut tile_animations: Vec<_> = server_config.tile_animations.iter().collect();
+        tile_animations.sort_by_key(|(name, _)| *name);
+        let tile_animations = tile_animations
+            .into_iter()
+            .map(|(name, tile_animation)| {
+                let tileset = world.tileset_id(&tile_animation.tileset).ok_or_else(|| {
+                    eyre!(
+                        "Tileset not found: {} (tile animation {name})",
+                        tile_animation.tileset
+                    )
+                })?;
+                Ok(TileAnimation {
+                    total_length: tile_animation.total_length,
+                    start_times: tile_animation.start_times.clone(),
+                    frames: tile_animation
+                        .frames
+                        .iter()
+                        .map(|&index| TileIndex {
+                            tileset,
+                            index: Some(index),
+                        })
+                        .collect(),
+                })
+            })
+            .collect::<Result<_>>()?;
+
         Ok(Self {
             asset_paths,
             world,
             mob_templates: server_config.mob_templates,
             animations,
             mob_animations,
+            tile_animations,
+            equipment: server_config.equipment,
+            equipment_animations,
             player: server_config.player,
-            player_animation,
+            player_appearances,
+            player_appearance_names,
+            slow_terrain_velocity_factor: server_config.slow_terrain_velocity_factor,
+            world_clock: server_config.world_clock,
+            instance_reset_time: server_config.instance_reset_time,
+            instance_lockout: server_config.instance_lockout,
+            room_idle_timeout: server_config.room_idle_timeout,
+            profanity,
         })
     }
+
+    /// Unknown appearances, for example after a reload removed them, fall back to the default
+    pub fn player_animation(&self, appearance: u32) -> u32 {
+        self.player_appearances
+            .get(appearance as usize)
+            .copied()
+            .unwrap_or(self.player_appearances[0])
+    }
+
+    /// Items that no longer exist are ignored
+    pub fn player_stats(&self, equipment: &PlayerEquipment) -> PlayerStats {
+        equipment
+            .values()
+            .filter_map(|item_id| self.equipment.get(item_id))
+            .fold(self.player.base_stats(), |stats, item| {
+                stats.with_modifiers(&item.modifiers)
+            })
+    }
+
+    /// In slot order
+    pub fn overlay_animation_ids(&self, equipment: &PlayerEquipment) -> Vec<u32> {
+        equipment
+            .values()
+            .filter_map(|item_id| self.equipment.get(item_id)?.overlay_animation.as_ref())
+            .filter_map(|animation| self.equipment_animations.get(animation).copied())
+            .collect()
+    }
+
+    pub fn carried_equipment(&self) -> Vec<EquipmentItem> {
+        self.player
+            .carried_equipment
+            .iter()
+            .filter_map(|item_id| {
+                let equipment = self.equipment.get(item_id)?;
+                Some(EquipmentItem {
+                    id: item_id.clone(),
+                    name: equipment.name.clone(),
+                    slot: equipment.slot,
+                    modifiers: equipment.modifiers,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Item ids by slot
+pub type PlayerEquipment = BTreeMap<EquipmentSlot, String>;
+
+/// One word per line, ignoring empty lines and `#` comments
+fn load_word_list(path: &str) -> Result<Vec<String>> {
+    let content =
+        std::fs::read_to_string(path).map_err(|err| eyre!("Could not read {path}: {err}"))?;
+    Ok(content
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.to_lowercase())
+        .collect())
 }
 
 #[derive(Debug, Clone)]
 pub struct World {
     pub maps: HashMap<RoomId, Arc<RoomMap>>,
-    pub start_room_id: RoomId,
-    pub start_position: Vector2<f32>,
+    pub start_points: Vec<SpawnPoint>,
+    /// Indexed by `TilesetId`
+    pub tilesets: Vec<Tileset>,
+    /// Levels that belong to the same instance, one group per instance entrance target
+    pub instance_groups: Vec<HashSet<RoomId>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Tileset {
+    /// As named in LDtk
+    pub identifier: String,
+    pub path: PathBuf,
+}
+
+impl World {
+    pub fn tileset_id(&self, identifier: &str) -> Option<TilesetId> {
+        self.tilesets
+            .iter()
+            .position(|tileset| tileset.identifier == identifier)
+            .map(|i| TilesetId(i as u8))
+    }
+
+    /// Moving between these rooms keeps the player in their instance
+    pub fn in_same_instance_group(&self, a: RoomId, b: RoomId) -> bool {
+        self.instance_groups
+            .iter()
+            .any(|group| group.contains(&a) && group.contains(&b))
+    }
+
+    /// New players are spread over the start points
+    pub fn random_start_point(&self) -> &SpawnPoint {
+        &self.start_points[fastrand::usize(..self.start_points.len())]
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct ServerConfig {
     pub animations: HashMap<String, AnimationSet>,
     pub mob_templates: HashMap<String, Arc<MobTemplate>>,
+    #[serde(default)]
+    pub tile_animations: HashMap<String, TileAnimationConfig>,
+    #[serde(default)]
+    pub equipment: HashMap<String, EquipmentConfig>,
     pub player: PlayerConfig,
     pub player_animation: String,
+    /// Animations players may pick instead of `player_animation` when joining
+    #[serde(default)]
+    pub player_appearances: Vec<String>,
+    pub slow_terrain_velocity_factor: f32,
+    pub world_clock: WorldClockConfig,
+    /// In seconds
+    pub instance_reset_time: TickDuration,
+    /// In seconds
+    pub instance_lockout: TickDuration,
+    /// In seconds
+    pub room_idle_timeout: TickDuration,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct WorldClockConfig {
+    /// In seconds
+    pub day_length: f32,
+    /// Time of day when the server starts, from 0.0 to 1.0 starting at midnight
+    pub start_time: f32,
+}
+
+impl WorldClockConfig {
+    /// Follows the global tick, so that every room agrees on it
+    pub fn time_of_day(&self, tick: Tick) -> f32 {
+        let elapsed = tick.0 as f64 * tick::TICK_INTERVAL.as_secs_f64();
+        (self.start_time as f64 + elapsed / self.day_length as f64).rem_euclid(1.0) as f32
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TileAnimationConfig {
+    /// As named in LDtk
+    pub tileset: String,
+    pub total_length: f32,
+    pub start_times: Vec<f32>,
+    /// Tile ids within the tileset, tiles painted with the first one are animated
+    pub frames: Vec<NonZeroU16>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct EquipmentConfig {
+    pub name: String,
+    pub slot: EquipmentSlot,
+    #[serde(flatten)]
+    pub modifiers: StatModifiers,
+    /// Drawn over the player animation, so it needs the same frames and custom animations
+    pub overlay_animation: Option<String>,
 }
 
 impl ServerConfig {
@@ -83,6 +361,13 @@ impl ServerConfig {
         let config = toml::from_str(&content)?;
         Ok(config)
     }
+
+    pub fn player_appearance_names(&self) -> Vec<String> {
+        std::iter::once(&self.player_animation)
+            .chain(&self.player_appearances)
+            .cloned()
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -92,7 +377,47 @@ pub struct PlayerConfig {
     pub max_health: i32,
     pub damage: i32,
     pub attack_range: f32,
+    #[serde(default)]
+    pub defence: i32,
+    #[serde(default)]
+    pub resistances: Resistances,
+    #[serde(default)]
+    pub critical_chance: f32,
+    #[serde(default)]
+    pub damage_type: DamageType,
+    /// Equipment every player carries and can equip
+    #[serde(default)]
+    pub carried_equipment: Vec<String>,
     pub heal_after: TickDuration,
     pub heal_rate: TickRate,
     pub heal_amount: u32,
+    #[serde(default)]
+    pub respawn_location: RespawnLocation,
+    #[serde(default)]
+    pub respawn_delay: TickDuration,
+}
+
+impl PlayerConfig {
+    /// Without equipment
+    pub fn base_stats(&self) -> PlayerStats {
+        PlayerStats {
+            max_health: self.max_health,
+            damage: self.damage,
+            attack_range: self.attack_range,
+            velocity: self.velocity,
+            defence: self.defence,
+            resistances: self.resistances,
+            critical_chance: self.critical_chance,
+            damage_type: self.damage_type,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub enum RespawnLocation {
+    /// The checkpoint the player last activated, or a start point
+    #[default]
+    LastCheckpoint,
+    /// The closest checkpoint or start point in the room the player died in
+    Nearest,
 }
@@ -41,7 +41,7 @@ impl Sub<Tick> for Tick {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
 #[serde(from = "f32")]
 pub struct TickDuration(pub u32);
 
@@ -76,13 +76,16 @@ pub fn spawn_producer() -> (broadcast::Sender<TickEvent>, JoinHandle<()>) {
     let (tick_sender, _) = broadcast::channel(8);
     let spawn_tick_sender = tick_sender.clone();
     let join_handle = tokio::spawn(async move {
-        let mut tick = Tick(0);
-        let mut interval = tokio::time::interval(TICK_INTERVAL);
+        let start = Instant::now();
+        let mut interval = tokio::time::interval_at(start, TICK_INTERVAL);
         interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
         loop {
             let monotonic_time = interval.tick().await;
 
-            tick.0 += 1;
+            // Derived from the elapsed time rather than counted, so ticks skipped under load
+            // also skip their numbers and tick * TICK_INTERVAL keeps tracking server time
+            let elapsed = monotonic_time - start;
+            let tick = Tick((elapsed.as_millis() / TICK_INTERVAL.as_millis()) as u32 + 1);
             let tick = TickEvent {
                 tick,
                 monotonic_time,
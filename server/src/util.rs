@@ -1,5 +1,10 @@
 use nalgebra::Vector2;
 
+/// Commit the server was built from
+pub fn git_sha() -> &'static str {
+    option_env!("VERGEN_GIT_SHA").unwrap_or("???")
+}
+
 pub fn in_distance(v1: Vector2<f32>, v2: Vector2<f32>, distance: f32) -> bool {
     (v1 - v2).norm_squared() <= distance * distance
 }
@@ -0,0 +1,521 @@
+use std::io::Read;
+
+use eyre::{eyre, Result};
+use mmo_common::animation::{AnimationSet, DirectionalAnimation, SpriteIndex};
+use mmo_common::room::{self, PIXELS_PER_TILE};
+use nalgebra::Vector2;
+
+use crate::mob::{AttackRotation, BossConfig, MobTemplate};
+use crate::room_state::RoomMap;
+use crate::server_context::{ServerConfig, World, CONFIG_PATH, MAP_PATH};
+use crate::{assets, ldtk_map};
+
+/// Checks the map and config for broken references without starting the server,
+/// reporting every problem instead of stopping at the first one.
+pub fn run() -> Result<()> {
+    let mut problems = vec![];
+
+    let mut map_problems = vec![];
+    let world = ldtk_map::load_lenient(MAP_PATH, &mut map_problems);
+    problems.extend(
+        map_problems
+            .into_iter()
+            .map(|problem| format!("{MAP_PATH}: {problem}")),
+    );
+    let world = match world {
+        Ok(world) => Some(world),
+        Err(err) => {
+            problems.push(format!("{MAP_PATH}: {err:#}"));
+            None
+        }
+    };
+    let config = match ServerConfig::load(CONFIG_PATH) {
+        Ok(config) => Some(config),
+        Err(err) => {
+            problems.push(format!("{CONFIG_PATH}: {err:#}"));
+            None
+        }
+    };
+    let charset_path = assets::charset_local_path();
+    let charset_tiles = match read_png_size(&charset_path) {
+        Ok(size) => Some(size / PIXELS_PER_TILE),
+        Err(err) => {
+            problems.push(format!("{charset_path}: {err:#}"));
+            None
+        }
+    };
+
+    if let Some(config) = &config {
+        check_config(config, charset_tiles, &mut problems);
+    }
+    if let Some(world) = &world {
+        check_world(world, config.as_ref(), &mut problems);
+    }
+
+    if problems.is_empty() {
+        println!("No problems found");
+        Ok(())
+    } else {
+        for problem in &problems {
+            println!("{problem}");
+        }
+        Err(eyre!("Found {} problems", problems.len()))
+    }
+}
+
+fn check_config(
+    config: &ServerConfig,
+    charset_tiles: Option<Vector2<u32>>,
+    problems: &mut Vec<String>,
+) {
+    let mut report = |location: String, message: String| {
+        problems.push(format!("{CONFIG_PATH}: {location}: {message}"));
+    };
+
+    for (i, appearance) in config.player_appearance_names().iter().enumerate() {
+        let location = match i {
+            0 => "player_animation".to_string(),
+            i => format!("player_appearances[{}]", i - 1),
+        };
+        match config.animations.get(appearance) {
+            Some(animation) => {
+                let index = config.player.attack_animation_index;
+                if index as usize >= animation.custom.len() {
+                    report(
+                        "player.attack_animation_index".to_string(),
+                        format!("custom animation {index} not found in animation {appearance}"),
+                    );
+                }
+            }
+            None => report(location, format!("animation {appearance} not found")),
+        }
+    }
+
+    if !(0.0..=1.0).contains(&config.player.critical_chance) {
+        report(
+            "player.critical_chance".to_string(),
+            "critical chance must be from 0.0 to 1.0".to_string(),
+        );
+    }
+
+    for (i, item_id) in config.player.carried_equipment.iter().enumerate() {
+        if !config.equipment.contains_key(item_id) {
+            report(
+                format!("player.carried_equipment[{i}]"),
+                format!("equipment {item_id} not found"),
+            );
+        }
+    }
+
+    let mut equipment: Vec<_> = config.equipment.iter().collect();
+    equipment.sort_by_key(|(name, _)| *name);
+    for (name, equipment) in equipment {
+        let Some(overlay_animation) = &equipment.overlay_animation else {
+            continue;
+        };
+        match config.animations.get(overlay_animation) {
+            Some(animation) => {
+                let index = config.player.attack_animation_index;
+                if index as usize >= animation.custom.len() {
+                    report(
+                        format!("equipment.{name}.overlay_animation"),
+                        format!(
+                            "custom animation {index} not found in animation {overlay_animation}"
+                        ),
+                    );
+                }
+            }
+            None => report(
+                format!("equipment.{name}.overlay_animation"),
+                format!("animation {overlay_animation} not found"),
+            ),
+        }
+    }
+
+    if config.world_clock.day_length <= 0.0 {
+        report(
+            "world_clock.day_length".to_string(),
+            "day length must be positive".to_string(),
+        );
+    }
+    if !(0.0..1.0).contains(&config.world_clock.start_time) {
+        report(
+            "world_clock.start_time".to_string(),
+            "start time must be from 0.0 to 1.0".to_string(),
+        );
+    }
+
+    let mut mob_templates: Vec<_> = config.mob_templates.iter().collect();
+    mob_templates.sort_by_key(|(name, _)| *name);
+    for (name, mob_template) in mob_templates {
+        if &mob_template.id != name {
+            report(
+                format!("mob_templates.{name}.id"),
+                format!("id {} does not match the template name", mob_template.id),
+            );
+        }
+        if mob_template.attacks.is_empty() {
+            report(
+                format!("mob_templates.{name}.attacks"),
+                "no attacks".to_string(),
+            );
+        }
+        for (i, attack) in mob_template.attacks.iter().enumerate() {
+            if !(0.0..=1.0).contains(&attack.critical_chance) {
+                report(
+                    format!("mob_templates.{name}.attacks[{i}].critical_chance"),
+                    "critical chance must be from 0.0 to 1.0".to_string(),
+                );
+            }
+        }
+        if let Some(boss) = &mob_template.boss {
+            check_boss(name, mob_template, boss, config, &mut report);
+        }
+        let Some(animation) = config.animations.get(&mob_template.animation_id) else {
+            report(
+                format!("mob_templates.{name}.animation_id"),
+                format!("animation {} not found", mob_template.animation_id),
+            );
+            continue;
+        };
+        for (i, attack) in mob_template.attacks.iter().enumerate() {
+            if attack.animation_index as usize >= animation.custom.len() {
+                report(
+                    format!("mob_templates.{name}.attacks[{i}].animation_index"),
+                    format!(
+                        "custom animation {} not found in animation {}",
+                        attack.animation_index, mob_template.animation_id
+                    ),
+                );
+            }
+        }
+    }
+
+    let mut tile_animations: Vec<_> = config.tile_animations.iter().collect();
+    tile_animations.sort_by_key(|(name, _)| *name);
+    for (name, tile_animation) in tile_animations {
+        if tile_animation.frames.is_empty() {
+            report(format!("tile_animations.{name}"), "no frames".to_string());
+        } else if tile_animation.frames.len() != tile_animation.start_times.len() {
+            report(
+                format!("tile_animations.{name}"),
+                format!(
+                    "{} frames but {} start times",
+                    tile_animation.frames.len(),
+                    tile_animation.start_times.len()
+                ),
+            );
+        }
+    }
+
+    let mut animations: Vec<_> = config.animations.iter().collect();
+    animations.sort_by_key(|(name, _)| *name);
+    for (name, animation) in animations {
+        for (key, directional_animation) in animation_parts(animation) {
+            for (direction, frames) in directional_frames(directional_animation) {
+                let location = format!("animations.{name}.{key}.{direction}");
+                if frames.len() != directional_animation.start_times.len() {
+                    report(
+                        location.clone(),
+                        format!(
+                            "{} frames but {} start times",
+                            frames.len(),
+                            directional_animation.start_times.len()
+                        ),
+                    );
+                }
+                let Some(charset_tiles) = charset_tiles else {
+                    continue;
+                };
+                for sprite_index in frames {
+                    if !sprite_in_bounds(*sprite_index, animation.sprite_size, charset_tiles) {
+                        report(
+                            location.clone(),
+                            format!(
+                                "sprite {} of size {}x{} is outside of the {}x{} charset",
+                                sprite_index.0,
+                                animation.sprite_size.x,
+                                animation.sprite_size.y,
+                                charset_tiles.x,
+                                charset_tiles.y
+                            ),
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn check_boss(
+    name: &str,
+    mob_template: &MobTemplate,
+    boss: &BossConfig,
+    config: &ServerConfig,
+    report: &mut impl FnMut(String, String),
+) {
+    let location = format!("mob_templates.{name}.boss");
+    if boss.phases.is_empty() {
+        report(format!("{location}.phases"), "no phases".to_string());
+    }
+    let attack_count = mob_template.attacks.len();
+    let mut previous_threshold = f32::INFINITY;
+    for (i, phase) in boss.phases.iter().enumerate() {
+        let location = format!("{location}.phases[{i}]");
+        if !(0.0..=1.0).contains(&phase.health_threshold) {
+            report(
+                format!("{location}.health_threshold"),
+                "health threshold must be from 0.0 to 1.0".to_string(),
+            );
+        } else if phase.health_threshold >= previous_threshold {
+            report(
+                format!("{location}.health_threshold"),
+                "health thresholds must be descending".to_string(),
+            );
+        }
+        previous_threshold = phase.health_threshold;
+
+        match &phase.rotation {
+            AttackRotation::Weighted { weights } => {
+                if weights.len() != attack_count {
+                    report(
+                        format!("{location}.rotation"),
+                        format!("{} weights but {attack_count} attacks", weights.len()),
+                    );
+                } else if weights.iter().all(|&weight| weight == 0) {
+                    report(
+                        format!("{location}.rotation"),
+                        "all weights are 0".to_string(),
+                    );
+                }
+            }
+            AttackRotation::Sequence { attacks } => {
+                if attacks.is_empty() {
+                    report(format!("{location}.rotation"), "no attacks".to_string());
+                }
+                for &attack_index in attacks {
+                    if attack_index as usize >= attack_count {
+                        report(
+                            format!("{location}.rotation"),
+                            format!("attack {attack_index} not found"),
+                        );
+                    }
+                }
+            }
+        }
+
+        for (j, summon) in phase.summons.iter().enumerate() {
+            if !config.mob_templates.contains_key(&summon.mob_template) {
+                report(
+                    format!("{location}.summons[{j}].mob_template"),
+                    format!("mob template {} not found", summon.mob_template),
+                );
+            }
+        }
+    }
+    if let Some(enrage) = &boss.enrage {
+        if enrage.damage_multiplier <= 0.0 {
+            report(
+                format!("{location}.enrage.damage_multiplier"),
+                "damage multiplier must be positive".to_string(),
+            );
+        }
+    }
+}
+
+fn check_world(world: &World, config: Option<&ServerConfig>, problems: &mut Vec<String>) {
+    if let Some(config) = config {
+        let mut tile_animations: Vec<_> = config.tile_animations.iter().collect();
+        tile_animations.sort_by_key(|(name, _)| *name);
+        for (name, tile_animation) in tile_animations {
+            if world.tileset_id(&tile_animation.tileset).is_none() {
+                problems.push(format!(
+                    "{CONFIG_PATH}: tile_animations.{name}.tileset: tileset {} not found in {MAP_PATH}",
+                    tile_animation.tileset
+                ));
+            }
+        }
+    }
+
+    let mut maps: Vec<_> = world.maps.iter().collect();
+    maps.sort_by_key(|(room_id, _)| room_id.0);
+
+    let spawn_points = world
+        .start_points
+        .iter()
+        .map(|start_point| ("player start", start_point))
+        .chain(maps.iter().flat_map(|(_, map)| {
+            map.checkpoints
+                .iter()
+                .map(|checkpoint| ("checkpoint", checkpoint))
+        }));
+    for (kind, spawn_point) in spawn_points {
+        let Some(map) = world.maps.get(&spawn_point.room_id) else {
+            problems.push(format!(
+                "{MAP_PATH}: {kind} {}: room not found",
+                spawn_point.name
+            ));
+            continue;
+        };
+        if room::collision_at(map.size, &map.collisions, spawn_point.position) {
+            problems.push(format_map_problem(
+                map,
+                spawn_point.position.map(|c| c as u32),
+                &format!("{kind} {} is blocked", spawn_point.name),
+            ));
+        }
+    }
+
+    // Otherwise instances spread over the shared world
+    for group in &world.instance_groups {
+        let mut start_rooms = world
+            .start_points
+            .iter()
+            .filter(|start_point| group.contains(&start_point.room_id))
+            .filter_map(|start_point| world.maps.get(&start_point.room_id))
+            .map(|map| map.name.as_str())
+            .collect::<Vec<_>>();
+        start_rooms.sort();
+        start_rooms.dedup();
+        for name in start_rooms {
+            problems.push(format!(
+                "{MAP_PATH}: {name}: start level is reachable from an instance entrance, mark the portals leaving the instance with instance_exit"
+            ));
+        }
+    }
+
+    for (_, map) in maps {
+        for mob_spawn in &map.mob_spawns {
+            if let Some(config) = config {
+                if !config.mob_templates.contains_key(&mob_spawn.mob_template) {
+                    problems.push(format_map_problem(
+                        map,
+                        mob_spawn.position,
+                        &format!("mob template {} not found", mob_spawn.mob_template),
+                    ));
+                }
+            }
+            let position = mob_spawn.position.cast().add_scalar(0.5);
+            if room::collision_at(map.size, &map.collisions, position) {
+                problems.push(format_map_problem(
+                    map,
+                    mob_spawn.position,
+                    "mob spawn is blocked",
+                ));
+            } else if room::terrain_at(map.size, &map.terrain, position).water {
+                problems.push(format_map_problem(
+                    map,
+                    mob_spawn.position,
+                    "mob spawn is in water",
+                ));
+            }
+        }
+        for area in &map.mob_spawn_areas {
+            if let Some(config) = config {
+                for (mob_template, _) in &area.mobs {
+                    if !config.mob_templates.contains_key(mob_template) {
+                        problems.push(format_map_problem(
+                            map,
+                            area.position,
+                            &format!("spawn area mob template {mob_template} not found"),
+                        ));
+                    }
+                }
+            }
+            if area.mobs.iter().all(|(_, weight)| *weight == 0) {
+                problems.push(format_map_problem(
+                    map,
+                    area.position,
+                    "spawn area has no mobs with a weight",
+                ));
+            }
+            let has_free_tile = (0..area.size.y)
+                .flat_map(|dy| (0..area.size.x).map(move |dx| Vector2::new(dx, dy)))
+                .map(|offset| (area.position + offset).cast().add_scalar(0.5))
+                .any(|position| {
+                    !room::collision_at(map.size, &map.collisions, position)
+                        && !room::terrain_at(map.size, &map.terrain, position).water
+                });
+            if !has_free_tile {
+                problems.push(format_map_problem(
+                    map,
+                    area.position,
+                    "spawn area has no free tiles",
+                ));
+            }
+        }
+        for portal in &map.portals {
+            let Some(target_map) = world.maps.get(&portal.target_room_id) else {
+                problems.push(format_map_problem(
+                    map,
+                    portal.position,
+                    "portal target room not found",
+                ));
+                continue;
+            };
+            let target_position = portal.target_position.add_scalar(0.5);
+            if room::collision_at(target_map.size, &target_map.collisions, target_position) {
+                problems.push(format_map_problem(
+                    map,
+                    portal.position,
+                    &format!(
+                        "portal target {} ({}, {}) is blocked",
+                        target_map.name, portal.target_position.x, portal.target_position.y
+                    ),
+                ));
+            }
+        }
+    }
+}
+
+fn format_map_problem(map: &RoomMap, position: Vector2<u32>, message: &str) -> String {
+    format!(
+        "{MAP_PATH}: {} ({}, {}): {message}",
+        map.name, position.x, position.y
+    )
+}
+
+fn animation_parts(animation: &AnimationSet) -> Vec<(String, &DirectionalAnimation)> {
+    let mut parts = vec![
+        ("idle".to_string(), &animation.idle),
+        ("walk".to_string(), &animation.walk),
+    ];
+    for (i, custom) in animation.custom.iter().enumerate() {
+        parts.push((format!("custom[{i}]"), custom));
+    }
+    parts
+}
+
+fn directional_frames(animation: &DirectionalAnimation) -> [(&str, &[SpriteIndex]); 4] {
+    [
+        ("right", &animation.right),
+        ("down", &animation.down),
+        ("left", &animation.left),
+        ("up", &animation.up),
+    ]
+}
+
+fn sprite_in_bounds(
+    sprite_index: SpriteIndex,
+    sprite_size: Vector2<u32>,
+    charset_tiles: Vector2<u32>,
+) -> bool {
+    if charset_tiles.x == 0 {
+        return false;
+    }
+    let index = sprite_index.0 as u32;
+    let column = index % charset_tiles.x;
+    let row = index / charset_tiles.x;
+    column + sprite_size.x <= charset_tiles.x && row + sprite_size.y <= charset_tiles.y
+}
+
+fn read_png_size(path: &str) -> Result<Vector2<u32>> {
+    let mut header = [0; 24];
+    std::fs::File::open(path)?.read_exact(&mut header)?;
+    if &header[..8] != b"\x89PNG\r\n\x1a\n" || &header[12..16] != b"IHDR" {
+        return Err(eyre!("Not a PNG file"));
+    }
+    let width = u32::from_be_bytes(header[16..20].try_into()?);
+    let height = u32::from_be_bytes(header[20..24].try_into()?);
+    Ok(Vector2::new(width, height))
+}
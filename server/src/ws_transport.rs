@@ -0,0 +1,66 @@
+use std::sync::Arc;
+
+use axum::extract::ws;
+use axum::extract::ws::WebSocket;
+use futures_util::stream::{SplitSink, SplitStream};
+use futures_util::{SinkExt, StreamExt};
+use mmo_common::player_event::{PlayerEvent, PlayerEventEnvelope};
+use tokio::sync::mpsc;
+
+use crate::client_connection::{self, CommandSource, EventSink};
+use crate::{metrics, object, server_actor};
+
+pub async fn handle(ws: WebSocket, server_actor_sender: mpsc::Sender<server_actor::Message>) {
+    let player_id = object::next_object_id();
+    let (ws_sink, ws_stream) = ws.split();
+    client_connection::handle(
+        WsCommandSource(ws_stream),
+        WsEventSink(ws_sink),
+        server_actor_sender,
+        player_id,
+    )
+    .await;
+}
+
+struct WsCommandSource(SplitStream<WebSocket>);
+
+impl CommandSource for WsCommandSource {
+    async fn recv(&mut self) -> Option<Vec<u8>> {
+        match self.0.next().await? {
+            Ok(ws::Message::Binary(bytes)) => Some(bytes),
+            Ok(ws::Message::Close(_)) => {
+                tracing::debug!("Received close message");
+                None
+            }
+            Ok(_) => {
+                tracing::warn!("Unexpected websocket message type");
+                None
+            }
+            Err(err) => {
+                tracing::debug!("Websocket error: {err}");
+                None
+            }
+        }
+    }
+}
+
+struct WsEventSink(SplitSink<WebSocket, ws::Message>);
+
+impl EventSink for WsEventSink {
+    async fn run(mut self, mut events: mpsc::Receiver<Vec<Arc<PlayerEvent>>>) {
+        while let Some(events) = events.recv().await {
+            let event_count = events.len();
+            let envelope = PlayerEventEnvelope { events };
+            let encoded = postcard::to_stdvec(&envelope).unwrap();
+            metrics::events_sent(event_count, encoded.len());
+            if let Err(err) = self.0.send(ws::Message::Binary(encoded)).await {
+                tracing::debug!("Error sending events: {err}");
+                return;
+            }
+        }
+        tracing::debug!("Closing sender");
+        if let Err(err) = self.0.close().await {
+            tracing::debug!("Error closing websocket: {err}");
+        }
+    }
+}
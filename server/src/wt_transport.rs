@@ -0,0 +1,282 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use eyre::{OptionExt, Result};
+use mmo_common::object::ObjectId;
+use mmo_common::player_event::{PlayerEvent, PlayerEventEnvelope};
+use mmo_common::transport::{self, WebTransportInfo, MAX_FRAME_LEN, WEBTRANSPORT_PATH};
+use tokio::sync::{mpsc, watch};
+use tokio::time::{Instant, MissedTickBehavior};
+use tracing::instrument;
+use wtransport::endpoint::IncomingSession;
+use wtransport::{Connection, Endpoint, Identity, RecvStream, SendStream, ServerConfig};
+
+use crate::client_connection::{self, CommandSource, EventSink};
+use crate::{metrics, object, server_actor};
+
+static STREAM_ACCEPT_TIMEOUT: Duration = Duration::from_secs(3);
+
+// Datagrams may get lost, so the last movement of each moving object is repeated
+static DATAGRAM_RESEND_INTERVAL: Duration = Duration::from_millis(500);
+
+pub struct WebTransportConfig {
+    pub port: u16,
+    identity: Identity,
+    self_signed: bool,
+}
+
+impl WebTransportConfig {
+    /// Enabled by `MMO_WT_PORT`. Uses `MMO_WT_CERT` and `MMO_WT_KEY` if given,
+    /// otherwise generates a self-signed certificate for local testing.
+    pub async fn from_env() -> Result<Option<Self>> {
+        let Ok(port) = std::env::var("MMO_WT_PORT") else {
+            return Ok(None);
+        };
+        let port = port.parse()?;
+
+        let cert_path = std::env::var("MMO_WT_CERT").ok();
+        let key_path = std::env::var("MMO_WT_KEY").ok();
+        let (identity, self_signed) = match (cert_path, key_path) {
+            (Some(cert_path), Some(key_path)) => {
+                (Identity::load_pemfiles(cert_path, key_path).await?, false)
+            }
+            (None, None) => (
+                Identity::self_signed(["localhost", "127.0.0.1", "::1"])?,
+                true,
+            ),
+            _ => eyre::bail!("MMO_WT_CERT and MMO_WT_KEY must be set together"),
+        };
+
+        Ok(Some(WebTransportConfig {
+            port,
+            identity,
+            self_signed,
+        }))
+    }
+
+    pub fn info(&self) -> Result<WebTransportInfo> {
+        let certificate_hash = if self.self_signed {
+            let certificate = self
+                .identity
+                .certificate_chain()
+                .as_slice()
+                .first()
+                .ok_or_eyre("Empty certificate chain")?;
+            Some(certificate.hash().as_ref().to_vec())
+        } else {
+            None
+        };
+        Ok(WebTransportInfo {
+            port: self.port,
+            certificate_hash,
+        })
+    }
+}
+
+#[instrument(skip_all)]
+pub async fn serve(
+    config: WebTransportConfig,
+    server_actor_sender: mpsc::Sender<server_actor::Message>,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let server_config = ServerConfig::builder()
+        .with_bind_default(config.port)
+        .with_identity(config.identity)
+        .keep_alive_interval(Some(Duration::from_secs(3)))
+        .build();
+    let endpoint = Endpoint::server(server_config)?;
+    tracing::info!("Listening for WebTransport on port {}", config.port);
+
+    loop {
+        tokio::select! {
+            incoming_session = endpoint.accept() => {
+                tokio::spawn(handle_session(
+                    incoming_session,
+                    server_actor_sender.clone(),
+                ));
+            }
+            _ = shutdown.wait_for(|shutdown| *shutdown) => break,
+        }
+    }
+    tracing::info!("Stopped accepting WebTransport sessions");
+    Ok(())
+}
+
+async fn handle_session(
+    incoming_session: IncomingSession,
+    server_actor_sender: mpsc::Sender<server_actor::Message>,
+) {
+    let session_request = match incoming_session.await {
+        Ok(session_request) => session_request,
+        Err(err) => {
+            tracing::debug!("WebTransport session failed: {err}");
+            metrics::handshake_failed();
+            return;
+        }
+    };
+    if session_request.path() != WEBTRANSPORT_PATH {
+        session_request.not_found().await;
+        return;
+    }
+    let connection = match session_request.accept().await {
+        Ok(connection) => connection,
+        Err(err) => {
+            tracing::debug!("WebTransport connection failed: {err}");
+            metrics::handshake_failed();
+            return;
+        }
+    };
+    let (send_stream, recv_stream) =
+        match tokio::time::timeout(STREAM_ACCEPT_TIMEOUT, connection.accept_bi()).await {
+            Ok(Ok(streams)) => streams,
+            Ok(Err(err)) => {
+                tracing::debug!("WebTransport stream failed: {err}");
+                metrics::handshake_failed();
+                return;
+            }
+            Err(_) => {
+                tracing::warn!("Timeout waiting for WebTransport stream");
+                metrics::handshake_failed();
+                return;
+            }
+        };
+
+    let player_id = object::next_object_id();
+    let event_sink = WtEventSink {
+        connection,
+        send_stream,
+        player_id,
+        moving_objects: HashMap::new(),
+    };
+    client_connection::handle(
+        WtCommandSource(recv_stream),
+        event_sink,
+        server_actor_sender,
+        player_id,
+    )
+    .await;
+}
+
+struct WtCommandSource(RecvStream);
+
+impl CommandSource for WtCommandSource {
+    async fn recv(&mut self) -> Option<Vec<u8>> {
+        let mut len_bytes = [0; 4];
+        if let Err(err) = self.0.read_exact(&mut len_bytes).await {
+            tracing::debug!("WebTransport stream closed: {err}");
+            return None;
+        }
+        let len = u32::from_le_bytes(len_bytes) as usize;
+        if len > MAX_FRAME_LEN {
+            tracing::warn!("Frame too large: {len}");
+            return None;
+        }
+        let mut bytes = vec![0; len];
+        if let Err(err) = self.0.read_exact(&mut bytes).await {
+            tracing::debug!("WebTransport stream closed: {err}");
+            return None;
+        }
+        Some(bytes)
+    }
+}
+
+struct WtEventSink {
+    connection: Connection,
+    send_stream: SendStream,
+    player_id: ObjectId,
+    moving_objects: HashMap<ObjectId, (Arc<PlayerEvent>, Instant)>,
+}
+
+impl EventSink for WtEventSink {
+    async fn run(mut self, mut events: mpsc::Receiver<Vec<Arc<PlayerEvent>>>) {
+        let mut resend_interval = tokio::time::interval(DATAGRAM_RESEND_INTERVAL);
+        resend_interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        loop {
+            tokio::select! {
+                events = events.recv() => {
+                    let Some(events) = events else {
+                        break;
+                    };
+                    if let Err(err) = self.send_events(events).await {
+                        tracing::debug!("Error sending events: {err}");
+                        break;
+                    }
+                }
+                _ = resend_interval.tick() => self.resend_movements(),
+            }
+        }
+        tracing::debug!("Closing sender");
+        let _ = self.send_stream.finish().await;
+    }
+}
+
+impl WtEventSink {
+    async fn send_events(&mut self, events: Vec<Arc<PlayerEvent>>) -> Result<()> {
+        let now = Instant::now();
+        let mut reliable_events = vec![];
+        for event in events {
+            match *event {
+                // Own movement stays reliable, it resets client-side prediction
+                PlayerEvent::ObjectMovementChanged {
+                    object_id,
+                    direction: Some(_),
+                    ..
+                } if object_id != self.player_id => {
+                    if send_datagram(&self.connection, &event) {
+                        self.moving_objects.insert(object_id, (event, now));
+                    } else {
+                        self.moving_objects.remove(&object_id);
+                        reliable_events.push(event);
+                    }
+                }
+                PlayerEvent::ObjectMovementChanged { object_id, .. }
+                | PlayerEvent::ObjectDisappeared { object_id } => {
+                    self.moving_objects.remove(&object_id);
+                    reliable_events.push(event);
+                }
+                PlayerEvent::RoomEntered { .. } => {
+                    self.moving_objects.clear();
+                    reliable_events.push(event);
+                }
+                _ => reliable_events.push(event),
+            }
+        }
+
+        if !reliable_events.is_empty() {
+            let event_count = reliable_events.len();
+            let envelope = PlayerEventEnvelope {
+                events: reliable_events,
+            };
+            let frame = transport::encode_frame(&postcard::to_stdvec(&envelope)?);
+            self.send_stream.write_all(&frame).await?;
+            metrics::events_sent(event_count, frame.len());
+        }
+        Ok(())
+    }
+
+    fn resend_movements(&mut self) {
+        let now = Instant::now();
+        for (event, sent_at) in self.moving_objects.values_mut() {
+            if now - *sent_at >= DATAGRAM_RESEND_INTERVAL {
+                *sent_at = now;
+                send_datagram(&self.connection, event);
+            }
+        }
+    }
+}
+
+fn send_datagram(connection: &Connection, event: &Arc<PlayerEvent>) -> bool {
+    let envelope = PlayerEventEnvelope {
+        events: vec![event.clone()],
+    };
+    let Ok(bytes) = postcard::to_stdvec(&envelope) else {
+        return false;
+    };
+    let len = bytes.len();
+    let sent = connection.send_datagram(bytes).is_ok();
+    if sent {
+        metrics::events_sent(1, len);
+    }
+    sent
+}